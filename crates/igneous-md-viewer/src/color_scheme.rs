@@ -0,0 +1,120 @@
+//! Following the color scheme of the desktop. See: [follow]
+//!
+//! The color scheme is read from the `org.freedesktop.appearance color-scheme` setting of the
+//! settings portal, falling back to `gtk-application-prefer-dark-theme` if the portal isn't
+//! available or has no preference.
+use gtk4::{
+    gio,
+    glib::{self, Variant},
+    prelude::*,
+};
+use std::{cell::Cell, rc::Rc};
+
+/// Namespace of the portal setting
+const APPEARANCE: &str = "org.freedesktop.appearance";
+/// Key of the portal setting
+const COLOR_SCHEME: &str = "color-scheme";
+
+/// Call `f` whenever the color scheme of the desktop changes, with whether it is dark
+///
+/// Returns whether the color scheme is dark right now.
+pub fn follow(f: impl Fn(bool) + 'static) -> bool {
+    let settings = gtk4::Settings::default();
+    let portal = portal();
+
+    // 0: no preference, 1: prefer dark, 2: prefer light
+    let preference = Rc::new(Cell::new(
+        portal.as_ref().and_then(read_preference).unwrap_or(0),
+    ));
+
+    let is_dark = {
+        let settings = settings.clone();
+        let preference = Rc::clone(&preference);
+
+        Rc::new(move || match preference.get() {
+            1 => true,
+            2 => false,
+            _ => settings
+                .as_ref()
+                .is_some_and(|s| s.is_gtk_application_prefer_dark_theme()),
+        })
+    };
+
+    let f = Rc::new(f);
+
+    if let Some(settings) = &settings {
+        let (f, is_dark) = (Rc::clone(&f), Rc::clone(&is_dark));
+
+        settings.connect_gtk_application_prefer_dark_theme_notify(move |_| f(is_dark()));
+    }
+
+    if let Some(portal) = &portal {
+        let (f, is_dark) = (Rc::clone(&f), Rc::clone(&is_dark));
+        // The proxy is kept alive by its own handler, so it keeps receiving signals
+        let proxy = portal.clone();
+
+        portal.connect_g_signal(
+            Some("SettingChanged"),
+            move |_, _, _, parameters: &Variant| {
+                let _ = &proxy;
+
+                if let Some((namespace, key, value)) = parameters.get::<(String, String, Variant)>()
+                    && namespace == APPEARANCE
+                    && key == COLOR_SCHEME
+                {
+                    preference.set(value.get::<u32>().unwrap_or(0));
+                    f(is_dark());
+                }
+            },
+        );
+    }
+
+    is_dark()
+}
+
+/// Connect to the settings portal
+fn portal() -> Option<gio::DBusProxy> {
+    gio::DBusProxy::for_bus_sync(
+        gio::BusType::Session,
+        gio::DBusProxyFlags::NONE,
+        None,
+        "org.freedesktop.portal.Desktop",
+        "/org/freedesktop/portal/desktop",
+        "org.freedesktop.portal.Settings",
+        gio::Cancellable::NONE,
+    )
+    .inspect_err(|e| eprintln!("Failed to connect to the settings portal: {e}"))
+    .ok()
+}
+
+/// Read the color scheme preference from the settings portal
+fn read_preference(portal: &gio::DBusProxy) -> Option<u32> {
+    let result = portal
+        .call_sync(
+            "ReadOne",
+            Some(&(APPEARANCE, COLOR_SCHEME).to_variant()),
+            gio::DBusCallFlags::NONE,
+            1000,
+            gio::Cancellable::NONE,
+        )
+        .or_else(|_| {
+            // Portals before version 2 only have `Read`, which wraps the value in another variant
+            portal.call_sync(
+                "Read",
+                Some(&(APPEARANCE, COLOR_SCHEME).to_variant()),
+                gio::DBusCallFlags::NONE,
+                1000,
+                gio::Cancellable::NONE,
+            )
+        })
+        .inspect_err(|e: &glib::Error| eprintln!("Failed to read the color scheme: {e}"))
+        .ok()?;
+
+    let mut value = result.child_value(0);
+
+    while let Some(inner) = value.as_variant() {
+        value = inner;
+    }
+
+    value.get::<u32>()
+}
@@ -1,9 +1,17 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::Path};
 
+#[cfg(feature = "gui")]
+mod color_scheme;
+pub mod runtime;
+#[cfg(feature = "gui")]
+mod socket;
+
+#[cfg(feature = "gui")]
 use gtk4::{
-    Application, ApplicationWindow, PAPER_NAME_A4, PageSetup, PaperSize, PrintSettings, gio, glib,
-    prelude::*,
+    Application, ApplicationWindow, EventSequenceState, GestureClick, PAPER_NAME_A4, PageSetup,
+    PaperSize, PrintSettings, PropagationPhase, gio, glib, prelude::*,
 };
+#[cfg(feature = "gui")]
 use webkit6::{
     CacheModel, PrintOperation, Settings, URISchemeRequest, UserContentManager, WebContext,
     WebView, prelude::*,
@@ -13,6 +21,7 @@ use webkit6::{
 pub static HTML: &str = include_str!(concat!(env!("OUT_DIR"), "/index.html"));
 
 /// Struct representing the igneous-md markdown viewer.
+#[cfg(feature = "gui")]
 #[derive(Debug)]
 pub struct Viewer<'a> {
     /// Address the webview will have set as `window.location`
@@ -21,8 +30,10 @@ pub struct Viewer<'a> {
     headless: bool,
 }
 
+#[cfg(feature = "gui")]
 const APP_ID: &str = "dod.igneous-md.viewer";
 
+#[cfg(feature = "gui")]
 impl<'a> Viewer<'a> {
     /// Create a new [Viewer]
     pub fn new(addr: Address<'a>, headless: bool) -> Self {
@@ -34,16 +45,23 @@ impl<'a> Viewer<'a> {
         let app = Application::builder().application_id(APP_ID).build();
 
         let addr = self.addr.to_string();
+        let socket = match self.addr.server {
+            Server::Unix(path) => Some(path.to_path_buf()),
+            Server::Tcp(..) => None,
+        };
         let headless = self.headless;
         app.connect_activate(move |app| {
-            Self::build_ui(&addr, headless, app);
+            Self::build_ui(&addr, socket.as_deref(), headless, app);
         });
 
         app.run_with_args::<&str>(&[]);
     }
 
     /// Build the actual GTK UI
-    fn build_ui(addr: &str, headless: bool, app: &Application) {
+    ///
+    /// If the server listens on a unix `socket`, the viewer connects to it for main.js, see
+    /// [socket::bridge()].
+    fn build_ui(addr: &str, socket: Option<&Path>, headless: bool, app: &Application) {
         let window = ApplicationWindow::builder()
             .application(app)
             .title("igneous-md viewer")
@@ -52,11 +70,12 @@ impl<'a> Viewer<'a> {
 
         let context = WebContext::default().unwrap();
         context.set_cache_model(CacheModel::DocumentBrowser);
-        // TODO: Not sure this will behave properly with relative paths in all cases. Needs further
-        // testing.
+        // The server resolves local assets to absolute, percent-encoded paths
         context.register_uri_scheme("asset", |req: &URISchemeRequest| {
             let uri = req.uri().unwrap();
-            let path = uri.strip_prefix("asset://").unwrap();
+            let path = glib::Uri::unescape_string(uri.strip_prefix("asset://").unwrap(), None)
+                .unwrap_or_default();
+            let path = path.as_str();
 
             match std::fs::read(path) {
                 Ok(bytes) => {
@@ -92,9 +111,50 @@ impl<'a> Viewer<'a> {
         view.show();
 
         let web_view_clone = view.clone();
-        content.connect_script_message_received(None, move |_manager, value| {
-            Self::to_pdf_handler(&web_view_clone, &value.to_string());
+        // The headless viewer is only used to export, so it closes once that is done
+        let window_clone = headless.then(|| window.clone());
+        content.connect_script_message_received(Some("exportPDF"), move |_manager, value| {
+            Self::to_pdf_handler(&web_view_clone, &value.to_string(), window_clone.clone());
+        });
+
+        if let Some(socket) = socket {
+            socket::bridge(&view, &content, socket.to_path_buf());
+        }
+
+        // Use the title of the document (see main.js) as the window title
+        let window_clone = window.clone();
+        view.connect_title_notify(move |view| {
+            window_clone.set_title(view.title().map(|t| t.to_string()).as_deref());
+        });
+
+        // Webkit uses the back and forward buttons of the mouse for its own history, so they
+        // have to be caught before they reach it (see main.js)
+        let history_buttons = GestureClick::builder()
+            .button(0)
+            .propagation_phase(PropagationPhase::Capture)
+            .build();
+        let web_view_clone = view.clone();
+        history_buttons.connect_pressed(move |gesture, _, _, _| {
+            let script = match gesture.current_button() {
+                8 => r#"navigate_history("Back")"#,
+                9 => r#"navigate_history("Forward")"#,
+                _ => return,
+            };
+
+            gesture.set_state(EventSequenceState::Claimed);
+            web_view_clone.evaluate_javascript(
+                script,
+                None,
+                None,
+                gio::Cancellable::NONE,
+                |result| {
+                    if let Err(e) = result {
+                        eprintln!("Failed to navigate history: {e}");
+                    }
+                },
+            );
         });
+        view.add_controller(history_buttons);
 
         window.set_child(Some(&view));
 
@@ -104,11 +164,42 @@ impl<'a> Viewer<'a> {
             window.present();
         }
 
-        view.load_html(HTML, Some(addr));
+        // The stylesheets follow the color scheme of the desktop (see main.js), except when
+        // exporting, so exports look the same no matter the desktop
+        let color_scheme = if headless {
+            "none"
+        } else {
+            let web_view_clone = view.clone();
+            let dark = color_scheme::follow(move |dark| {
+                let script = if dark {
+                    r#"set_color_scheme("dark")"#
+                } else {
+                    r#"set_color_scheme("light")"#
+                };
+
+                web_view_clone.evaluate_javascript(
+                    script,
+                    None,
+                    None,
+                    gio::Cancellable::NONE,
+                    |result| {
+                        if let Err(e) = result {
+                            eprintln!("Failed to set color scheme: {e}");
+                        }
+                    },
+                );
+            });
+
+            if dark { "dark" } else { "light" }
+        };
+
+        view.load_html(HTML, Some(&format!("{addr}&color_scheme={color_scheme}")));
     }
 
     /// Export current webview content (the markdown) to pdf
-    fn to_pdf_handler(web_view: &WebView, path: &str) {
+    ///
+    /// If a `window` is given, it is closed once the export is done.
+    fn to_pdf_handler(web_view: &WebView, path: &str, window: Option<ApplicationWindow>) {
         let print_settings = PrintSettings::new();
 
         print_settings.set_printer("Print to File");
@@ -130,8 +221,13 @@ impl<'a> Viewer<'a> {
             .build();
 
         let path_clone = path.to_owned();
+        // Also emitted after failing
         print_op.connect_finished(move |_| {
             println!("PDF exported successfully to {}!", path_clone);
+
+            if let Some(window) = &window {
+                window.close();
+            }
         });
 
         print_op.connect_failed(|_, error| {
@@ -142,10 +238,20 @@ impl<'a> Viewer<'a> {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+/// Where the server the viewer connects to is listening
+#[derive(Debug, Clone, Copy)]
+pub enum Server<'a> {
+    /// The host and port of the server
+    Tcp(&'a str, u16),
+    /// Path to the unix domain socket of the server
+    Unix(&'a Path),
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Address<'a> {
-    host: &'a str,
-    port: u16,
+    server: Server<'a>,
+    /// Token the server requires clients to present
+    token: Option<&'a str>,
     update_rate: u64,
     css: Option<&'a str>,
     path: &'a str,
@@ -153,15 +259,15 @@ pub struct Address<'a> {
 
 impl<'a> Address<'a> {
     pub fn new(
-        host: &'a str,
-        port: u16,
+        server: Server<'a>,
+        token: Option<&'a str>,
         update_rate: u64,
         css: Option<&'a str>,
         path: &'a str,
     ) -> Self {
         Self {
-            host,
-            port,
+            server,
+            token,
             update_rate,
             css,
             path,
@@ -171,14 +277,30 @@ impl<'a> Address<'a> {
 
 impl Display for Address<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "http://{}:{}/?update_rate={}&path={}{}",
-            self.host,
-            self.port,
-            self.update_rate,
-            self.path,
-            self.css.map(|s| format!("&css={}", s)).unwrap_or_default(),
-        )
+        let mut query = form_urlencoded::Serializer::new(String::new());
+
+        // main.js can't connect to sockets, the viewer does it instead (see socket.rs)
+        match self.server {
+            Server::Tcp(host, port) => write!(f, "http://{host}:{port}/?")?,
+            Server::Unix(_) => {
+                write!(f, "http://localhost/?")?;
+                query.append_pair("socket", "true");
+            }
+        }
+
+        query.append_pair("update_rate", &self.update_rate.to_string());
+
+        if let Some(token) = self.token {
+            query.append_pair("token", token);
+        }
+
+        // Paths may contain `&`, `#` and other characters with a meaning in urls
+        query.append_pair("path", self.path);
+
+        if let Some(css) = self.css {
+            query.append_pair("css", css);
+        }
+
+        f.write_str(&query.finish())
     }
 }
@@ -65,6 +65,22 @@ document.addEventListener("keydown", (event) => {
             window.scrollTo(0, 0);
             break;
 
+        case "H":
+            navigate_history("Back");
+            break;
+
+        case "L":
+            navigate_history("Forward");
+            break;
+
+        case "ArrowLeft":
+            if (event.altKey) navigate_history("Back");
+            break;
+
+        case "ArrowRight":
+            if (event.altKey) navigate_history("Forward");
+            break;
+
         case "j":
             window.scrollBy({ top: 150, behavior: "smooth" });
             break;
@@ -102,12 +118,69 @@ document.addEventListener("keydown", (event) => {
     lastKey = event.key;
 });
 
+// Mouse back and forward buttons
+document.addEventListener("mouseup", (event) => {
+    switch (event.button) {
+        case 3:
+            event.preventDefault();
+            navigate_history("Back");
+            break;
+        case 4:
+            event.preventDefault();
+            navigate_history("Forward");
+            break;
+    }
+});
+
+// Go "Back" or "Forward" in the history of viewed files
+//
+// The server restores the position in the file using a ScrollToLine message. Also called by the
+// GTK viewer, since webkit handles the mouse buttons itself.
+function navigate_history(direction) {
+    ws.send(JSON.stringify({ t: direction }));
+}
+
+// The banner is a notice shown while the document is gone, or for a while after it moved
+let bannerTimeout = null;
+let documentGone = false;
+
+// Show the `text` in the banner, hiding it after `duration` ms if given
+function show_banner(text, duration) {
+    const banner = document.getElementById("banner");
+
+    clearTimeout(bannerTimeout);
+
+    banner.textContent = text;
+    banner.hidden = false;
+
+    if (duration) {
+        bannerTimeout = setTimeout(() => {
+            banner.hidden = true;
+        }, duration);
+    }
+}
+
+// Hide the banner once the document is back
+function hide_gone_banner() {
+    if (!documentGone) return;
+
+    documentGone = false;
+    document.getElementById("banner").hidden = true;
+}
+
+// Heading to scroll to once the document we are redirecting to has loaded
+let pendingAnchor = null;
+
 function handle_redirect(href) {
+    const [path, anchor] = href.split("#", 2);
+
+    pendingAnchor = anchor ?? null;
+
     ws.send(
         JSON.stringify({
             t: "Redirect",
             c: {
-                path: href,
+                path: path,
             },
         }),
     );
@@ -117,13 +190,242 @@ function handle_redirect(href) {
     return false;
 }
 
+// Set while scrolling to a line sent by the server, so it isn't reported back
+let syncingScroll = false;
+let scrollTimeout = null;
+
+function scroll_to_line(line) {
+    let target = null;
+
+    for (const el of document.querySelectorAll("[data-source-line]")) {
+        if (Number(el.dataset.sourceLine) > line) break;
+
+        target = el;
+    }
+
+    const before = window.scrollY;
+
+    // Lines before the first block, e.g. of the front matter, are at the top
+    if (target) {
+        target.scrollIntoView({ block: "start" });
+    } else {
+        window.scrollTo(0, 0);
+    }
+
+    // Only a scroll that actually happened fires an event
+    syncingScroll = window.scrollY !== before;
+}
+
+function top_visible_line() {
+    for (const el of document.querySelectorAll("[data-source-line]")) {
+        if (el.getBoundingClientRect().bottom > 0) {
+            return Number(el.dataset.sourceLine);
+        }
+    }
+
+    return null;
+}
+
+window.addEventListener("scroll", () => {
+    clearTimeout(scrollTimeout);
+
+    scrollTimeout = setTimeout(() => {
+        if (syncingScroll) {
+            syncingScroll = false;
+            return;
+        }
+
+        const line = top_visible_line();
+
+        if (line !== null && ws.readyState === WebSocket.OPEN) {
+            ws.send(JSON.stringify({ t: "SourceLine", c: { line: line } }));
+        }
+    }, 100);
+});
+
 const url = new URL(window.location.href);
 
 const params = new URLSearchParams(url.search);
 
-const ws = new WebSocket(
-    `ws://${window.location.host}/ws/?md_path=${params.get("path")}&update_rate=${params.get("update_rate")}`,
-);
+// The token is kept out of the address bar and the history of browsers. Reloads take it from the
+// session instead
+const token = params.get("token") ?? sessionStorage.getItem("token");
+
+if (params.has("token")) {
+    sessionStorage.setItem("token", token);
+    params.delete("token");
+    url.search = params.toString();
+    history.replaceState(null, "", url);
+}
+
+// Stylesheet to start with, set by `--css`
+const css = params.get("css");
+
+// The GTK viewer follows the color scheme of the desktop and sets it itself, browsers follow
+// prefers-color-scheme. A color scheme of "none" keeps the stylesheets as they are
+const darkQuery = window.matchMedia("(prefers-color-scheme: dark)");
+let colorScheme =
+    params.get("color_scheme") ?? (darkQuery.matches ? "dark" : "light");
+
+// Only the GTK viewer can export to pdf (see `Export` below)
+const canExport = window.webkit?.messageHandlers?.exportPDF !== undefined;
+
+// Passes the messages of a server listening on a unix socket through the GTK viewer, since
+// websockets can only connect over TCP. Implements the parts of WebSocket used here
+class SocketBridge {
+    constructor(url) {
+        this.readyState = WebSocket.CONNECTING;
+        this.onopen = null;
+        this.onmessage = null;
+
+        // Called by the GTK viewer
+        window.socketBridge = this;
+        window.webkit.messageHandlers.socket.postMessage(`open ${url}`);
+    }
+
+    send(data) {
+        window.webkit.messageHandlers.socket.postMessage(`send ${data}`);
+    }
+
+    opened() {
+        this.readyState = WebSocket.OPEN;
+        this.onopen?.();
+    }
+
+    received(data) {
+        this.onmessage?.({ data: data });
+    }
+
+    closed() {
+        this.readyState = WebSocket.CLOSED;
+        console.warn("Connection to the server closed");
+    }
+}
+
+// The parameters are decoded by `URLSearchParams`, so they have to be encoded again
+const wsUrl =
+    `/ws/?md_path=${encodeURIComponent(params.get("path"))}&update_rate=${encodeURIComponent(params.get("update_rate"))}&patch=true&export=${canExport}&color_scheme=${colorScheme}` +
+    (css ? `&css=${encodeURIComponent(css)}` : "") +
+    (token !== null ? `&token=${encodeURIComponent(token)}` : "");
+
+const ws =
+    params.get("socket") === "true"
+        ? new SocketBridge(wsUrl)
+        : new WebSocket(`ws://${window.location.host}${wsUrl}`);
+
+// Tell the server to serve stylesheets of the "light" or "dark" color scheme
+//
+// Also called by the GTK viewer when the color scheme of the desktop changes.
+function set_color_scheme(scheme) {
+    if (scheme === colorScheme) return;
+
+    colorScheme = scheme;
+
+    ws.send(JSON.stringify({ t: "SetColorScheme", c: { scheme: scheme } }));
+}
+
+darkQuery.addEventListener("change", (event) => {
+    if (params.has("color_scheme")) return;
+
+    set_color_scheme(event.matches ? "dark" : "light");
+});
+
+// Highlight code and typeset math inside the `roots`, skipping what was already rendered
+function render_extras(roots) {
+    for (const root of roots) {
+        root.querySelectorAll(
+            // Skip code which the server already highlighted
+            'code[class*="language-"]:not([data-highlighted]):not(.language-math)',
+        ).forEach((el) => hljs.highlightElement(el));
+
+        root.querySelectorAll("code.language-math").forEach((el) => {
+            const isDisplay = el.classList.contains("math-display");
+            const wrapper = document.createElement("span");
+            wrapper.textContent = isDisplay
+                ? "$$" + el.textContent + "$$"
+                : "$" + el.textContent + "$";
+            el.replaceWith(wrapper);
+        });
+    }
+
+    MathJax.typeset(roots);
+}
+
+// Parse the html of a single block
+function parse_block(html) {
+    const template = document.createElement("template");
+    template.innerHTML = html;
+
+    resolve_assets(template.content);
+
+    return template.content.firstElementChild;
+}
+
+// Only the GTK viewer can load the `asset://` uris of local images and other media, browsers load
+// them from the server instead
+function resolve_assets(root) {
+    if (canExport) {
+        return;
+    }
+
+    const query = token !== null ? `?token=${encodeURIComponent(token)}` : "";
+
+    for (const element of root.querySelectorAll('[src^="asset://"]')) {
+        element.setAttribute(
+            "src",
+            `/asset${element.getAttribute("src").slice("asset://".length)}${query}`,
+        );
+    }
+}
+
+// Apply the patches of a `HtmlPatch` to the blocks inside of `<main>`
+function apply_patches(patches) {
+    const main = document.querySelector("main");
+    const changed = [];
+
+    for (const patch of patches) {
+        const block = main.children[patch.c.index];
+
+        switch (patch.t) {
+            case "Insert": {
+                const el = parse_block(patch.c.html);
+
+                if (block) block.before(el);
+                else main.append(el);
+
+                changed.push(el);
+                break;
+            }
+            case "Replace": {
+                const el = parse_block(patch.c.html);
+
+                block.replaceWith(el);
+
+                changed.push(el);
+                break;
+            }
+            case "Remove":
+                block.remove();
+                break;
+            case "SetLines": {
+                const elements = [block, ...block.querySelectorAll("*")].filter(
+                    (el) => el.hasAttribute("data-source-line"),
+                );
+
+                patch.c.lines.forEach((line, i) => {
+                    if (elements[i]) elements[i].dataset.sourceLine = line;
+                });
+                break;
+            }
+            default:
+                console.warn("Unknown patch type:", patch.t);
+                break;
+        }
+    }
+
+    // Blocks which were inserted and then replaced are no longer in the document
+    render_extras(changed.filter((el) => el.isConnected));
+}
 
 function safeParse(jsonString) {
     try {
@@ -148,34 +450,56 @@ ws.onmessage = (event) => {
         case "CssUpdate":
             styleSheet.textContent = content.css;
             break;
+        case "CssEntries":
+            // Not used by this viewer, but available to show a picker of stylesheets
+            break;
         case "HtmlUpdate":
             {
-                const main = document.body;
+                // Only <main> is replaced, so the banner stays
+                document.querySelector("main").replaceWith(parse_block(content.html));
 
-                main.innerHTML = content.html;
+                const main = document.querySelector("main");
 
                 console.log("Markdown updated");
-                hljs.configure({
-                    cssSelector: 'code[class*="language-"]', // Highlight.js configuration
-                });
-                hljs.highlightAll();
-
-                document
-                    .querySelectorAll("code.language-math")
-                    .forEach((el) => {
-                        {
-                            const isDisplay =
-                                el.classList.contains("math-display");
-                            const wrapper = document.createElement("span");
-                            wrapper.textContent = isDisplay
-                                ? "$$" + el.textContent + "$$"
-                                : "$" + el.textContent + "$";
-                            el.replaceWith(wrapper);
-                        }
-                    });
-                MathJax.typeset();
+                render_extras([main]);
+                hide_gone_banner();
+
+                if (pendingAnchor) {
+                    document.getElementById(pendingAnchor)?.scrollIntoView();
+                    pendingAnchor = null;
+                }
+            }
+            break;
+        case "HtmlPatch":
+            apply_patches(content.patches);
+
+            console.log(`Markdown patched (${content.patches.length} blocks)`);
+            hide_gone_banner();
+
+            if (pendingAnchor) {
+                document.getElementById(pendingAnchor)?.scrollIntoView();
+                pendingAnchor = null;
             }
             break;
+        case "FrontMatter":
+            document.title = content.metadata.title ?? "Igneous-md";
+            break;
+        case "DocumentGone":
+            documentGone = true;
+            show_banner(
+                `${content.path} was deleted or moved. It is shown again once it reappears.`,
+            );
+            break;
+        case "DocumentMoved":
+            documentGone = false;
+            show_banner(`Moved to ${content.to}`, 5000);
+            break;
+        case "ScrollToLine":
+            scroll_to_line(content.line);
+            break;
+        case "Outline":
+            // Not used by this viewer, but available to build a table of contents
+            break;
         case "Export":
             window.webkit.messageHandlers.exportPDF.postMessage(content.path);
             break;
@@ -4,10 +4,10 @@
 //!
 //! It's useful for when you accidentally closed the viewer, but don't want to restart the whole
 //! server.
-use std::{error::Error, fs};
+use std::{fs, path::PathBuf};
 
 use clap::{CommandFactory, Parser};
-use igneous_md_viewer::{Address, Viewer};
+use igneous_md_viewer::{Address, Server, Viewer, runtime::InstanceFiles};
 
 fn main() {
     let cli = Cli::parse();
@@ -21,18 +21,65 @@ fn main() {
         );
     }
 
+    let files = InstanceFiles::new(&cli.instance);
+
+    // The token of the instance is only sent to the server of the instance, not to whatever
+    // listens on a port given explicitly
+    let mut token = cli.token_file.as_ref().map(|path| {
+        fs::read_to_string(path)
+            .expect("Failed to read the token file.")
+            .trim()
+            .to_string()
+    });
+
+    let socket;
+    let server = if let Some(port) = cli.port {
+        Server::Tcp("localhost", port)
+    } else if let Some(path) = &cli.socket {
+        Server::Unix(path)
+    } else {
+        let endpoint = files
+            .read_endpoint()
+            .unwrap_or_else(|| panic!("No server of instance `{}` is running.", cli.instance));
+
+        // Servers listening on a socket don't need a token
+        token = files.read_token();
+
+        match endpoint.strip_prefix("unix:") {
+            Some(path) => {
+                socket = PathBuf::from(path);
+
+                Server::Unix(&socket)
+            }
+            None => Server::Tcp(
+                "localhost",
+                endpoint.parse().expect("Invalid port file of the server."),
+            ),
+        }
+    };
+
     let addr = Address::new(
-        "localhost",
-        cli.port
-            .map_or_else(read_port, Ok)
-            .expect("Failed to get port of server."),
+        server,
+        token.as_deref(),
         cli.update_rate,
         cli.css.as_deref(),
-        cli.path.as_str(),
+        // The server may have been started in a different directory
+        &fs::canonicalize(&cli.path).map_or(cli.path.clone(), |p| p.to_string_lossy().to_string()),
     );
 
     if cli.browser {
-        if open::that_detached(addr.to_string()).is_err() {
+        if matches!(server, Server::Unix(_)) {
+            println!("WARN: Browsers can't connect to servers listening on a socket");
+
+            return;
+        }
+
+        // The url contains the token, which is kept off the command line of the browser
+        if files
+            .write_redirect(&addr.to_string())
+            .and_then(open::that_detached)
+            .is_err()
+        {
             println!("WARN: Failed to open browser");
         }
 
@@ -44,12 +91,6 @@ fn main() {
     viewer.start();
 }
 
-fn read_port() -> Result<u16, Box<dyn Error>> {
-    Ok(fs::read_to_string("/tmp/igneous-md")?
-        .parse()
-        .expect("Invalid port file in tmp."))
-}
-
 #[derive(Debug, Parser)]
 /// Igneous-md viewer
 ///
@@ -59,10 +100,21 @@ pub struct Cli {
     pub path: String,
     /// Port of the server
     ///
-    /// If none is supplied the viewer will attempt to read `/tmp/igneous-md` where the server
-    /// writes it's port to on start.
+    /// If none is supplied the viewer will attempt to read the port file of the `--instance`,
+    /// where the server writes it's port to on start.
     #[arg(short, long)]
     pub port: Option<u16>,
+    /// File containing the token the server on `--port` requires
+    ///
+    /// The token of the server of the `--instance` is read automatically.
+    #[arg(long, value_name = "PATH", requires = "port")]
+    pub token_file: Option<PathBuf>,
+    /// Path to the unix domain socket of the server, instead of the port
+    #[arg(short, long, value_name = "PATH", conflicts_with = "port")]
+    pub socket: Option<PathBuf>,
+    /// Name of the server instance to connect to
+    #[arg(short, long, value_name = "NAME", default_value = "default")]
+    pub instance: String,
     /// Path to the initial css to use
     #[arg(short, long)]
     pub css: Option<String>,
@@ -0,0 +1,207 @@
+//! Files servers share with their viewers in the runtime dir. See: [InstanceFiles]
+//!
+//! A server claims its instance by locking the lock file and writes the endpoint it is listening
+//! on and its token next to it, where viewers and later invocations look for them. The lock is
+//! released by the OS, even if the server crashes, so port files whose lock isn't held anymore
+//! are stale and removed.
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Directory running servers keep their files in
+///
+/// This is `$XDG_RUNTIME_DIR/igneous-md/`, which is only accessible by the user. Use
+/// [create_private_dir()] before writing to it.
+pub static RUNTIME_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("igneous-md")
+});
+
+/// Create the `dir` if it doesn't exist, making sure only the user can access it
+///
+/// Fails if the directory belongs to another user, e.g. because it was created by someone else
+/// in a shared temporary directory.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+        builder.mode(0o700).create(dir)?;
+
+        // Only the owner can change the permissions
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+    }
+
+    #[cfg(not(unix))]
+    builder.create(dir)
+}
+
+/// The files of the server instance with the given name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceFiles {
+    /// Directory the files are in, usually [RUNTIME_DIR]
+    dir: PathBuf,
+    /// Name of the instance, which mustn't contain path separators
+    name: String,
+}
+
+impl InstanceFiles {
+    /// Get the files of the instance `name` in [RUNTIME_DIR]
+    pub fn new(name: &str) -> Self {
+        Self::in_dir(RUNTIME_DIR.clone(), name)
+    }
+
+    /// Get the files of the instance `name` in `dir`, instead of [RUNTIME_DIR]
+    pub fn in_dir(dir: PathBuf, name: &str) -> Self {
+        Self {
+            dir,
+            name: name.to_string(),
+        }
+    }
+
+    /// Get the directory the files are in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the name of the instance
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the file the server writes the endpoint it is listening on to
+    ///
+    /// This is the port, or `unix:` followed by the path of the socket.
+    pub fn port_file(&self) -> PathBuf {
+        self.dir.join(format!("{}.port", self.name))
+    }
+
+    /// Get the file the server writes the token clients have to present to
+    pub fn token_file(&self) -> PathBuf {
+        self.dir.join(format!("{}.token", self.name))
+    }
+
+    /// Get the file locked by the server while it is running
+    pub fn lock_file(&self) -> PathBuf {
+        self.dir.join(format!("{}.lock", self.name))
+    }
+
+    /// Get the page browsers are opened with, see [Self::write_redirect()]
+    pub fn redirect_file(&self) -> PathBuf {
+        self.dir.join(format!("{}.html", self.name))
+    }
+
+    /// Write a page redirecting to the `url` of the viewer, which only the user can read
+    ///
+    /// Browsers are opened with the page instead of the url, so the token in it isn't exposed to
+    /// other users in the arguments of the browser. Returns the path of the page.
+    pub fn write_redirect(&self, url: &str) -> io::Result<PathBuf> {
+        create_private_dir(&self.dir)?;
+
+        let url = url
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+
+        let path = self.redirect_file();
+
+        let mut options = OpenOptions::new();
+        options.create(true).truncate(true).write(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options.open(&path)?.write_all(
+            format!(
+                "<!DOCTYPE html>\n\
+                 <meta http-equiv=\"refresh\" content=\"0; url={url}\">\n\
+                 <a href=\"{url}\">Open the viewer</a>\n"
+            )
+            .as_bytes(),
+        )?;
+
+        Ok(path)
+    }
+
+    /// Read the port file, see [Self::port_file()]
+    ///
+    /// Returns [None] if no server is running, removing the port file if it was left behind.
+    pub fn read_endpoint(&self) -> Option<String> {
+        let port_file = self.port_file();
+
+        let content = match fs::read_to_string(&port_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Could not read {}: {e}", port_file.display());
+
+                return None;
+            }
+        };
+
+        if !self.is_running() {
+            log::debug!("{} is stale. Attempting to delete.", port_file.display());
+
+            self.remove_port_file();
+
+            return None;
+        }
+
+        Some(content.trim().to_string())
+    }
+
+    /// Remove the port file, e.g. if it is invalid
+    pub fn remove_port_file(&self) {
+        if let Err(e) = fs::remove_file(self.port_file())
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            log::warn!("Failed to remove port file: {e}");
+        }
+    }
+
+    /// Read the token file, see [Self::token_file()]
+    ///
+    /// Returns [None] if the server doesn't need a token. Read it after [Self::read_endpoint()],
+    /// which removes the files of servers which are gone.
+    pub fn read_token(&self) -> Option<String> {
+        match fs::read_to_string(self.token_file()) {
+            Ok(token) => Some(token.trim().to_string()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                log::warn!("Could not read {}: {e}", self.token_file().display());
+
+                None
+            }
+        }
+    }
+
+    /// Check if a server holds the lock of this instance
+    ///
+    /// Takes the lock for a moment if it is free, so servers claiming the instance meanwhile have
+    /// to try again.
+    pub fn is_running(&self) -> bool {
+        let Ok(file) = File::open(self.lock_file()) else {
+            return false;
+        };
+
+        // Only fails if the lock is held, the lock taken here is released once the file is closed
+        match file.try_lock() {
+            Ok(()) => false,
+            Err(TryLockError::WouldBlock) => true,
+            Err(TryLockError::Error(e)) => {
+                log::warn!("Failed to check the lock of instance `{}`: {e}", self.name);
+
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Connection to servers listening on a unix domain socket. See: [bridge]
+//!
+//! The websockets of main.js can only connect over TCP, so the viewer connects to the socket
+//! instead and passes the messages between main.js (see `SocketBridge` there) and the server.
+use futures_channel::mpsc;
+use futures_util::StreamExt;
+use gtk4::{gio, glib};
+use std::{
+    cell::RefCell,
+    error::Error,
+    io,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::mpsc as std_mpsc,
+    thread,
+    time::Duration,
+};
+use tungstenite::Message;
+use webkit6::{UserContentManager, WebView, prelude::*};
+
+/// Name of the script message handler main.js posts to
+const HANDLER: &str = "socket";
+
+/// How often the connection checks for messages from main.js while waiting for the server
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Events of the connection, passed on to main.js
+enum Event {
+    /// The connection was opened
+    Opened,
+    /// A message was received from the server
+    Received(String),
+    /// The connection was closed
+    Closed,
+}
+
+/// Pass the messages of main.js in the `view` to the server listening on `socket`
+///
+/// main.js opens the connection by posting `open <path and query of the websocket>` to the
+/// `socket` message handler of the `content` manager, and sends messages by posting
+/// `send <message>`.
+pub fn bridge(view: &WebView, content: &UserContentManager, socket: PathBuf) {
+    content.register_script_message_handler(HANDLER, None);
+
+    let (event_tx, mut event_rx) = mpsc::unbounded();
+    // Messages to the server, only available once main.js opened the connection
+    let outgoing = RefCell::new(None::<std_mpsc::Sender<String>>);
+
+    content.connect_script_message_received(Some(HANDLER), move |_manager, value| {
+        let message = value.to_string();
+
+        if let Some(url) = message.strip_prefix("open ") {
+            let (tx, rx) = std_mpsc::channel();
+            outgoing.replace(Some(tx));
+
+            let (socket, url, events) = (socket.clone(), url.to_string(), event_tx.clone());
+
+            thread::spawn(move || {
+                if let Err(e) = connect(&socket, &url, &rx, &events) {
+                    eprintln!("Connection to the server failed: {e}");
+                }
+
+                let _ = events.unbounded_send(Event::Closed);
+            });
+        } else if let Some(data) = message.strip_prefix("send ")
+            && let Some(tx) = &*outgoing.borrow()
+        {
+            // Fails if the connection is closed, which main.js is told about
+            let _ = tx.send(data.to_string());
+        }
+    });
+
+    let view = view.clone();
+    glib::MainContext::default().spawn_local(async move {
+        while let Some(event) = event_rx.next().await {
+            let script = match event {
+                Event::Opened => "socketBridge.opened()".to_string(),
+                Event::Received(data) => format!("socketBridge.received({})", js_string(&data)),
+                Event::Closed => "socketBridge.closed()".to_string(),
+            };
+
+            view.evaluate_javascript(&script, None, None, gio::Cancellable::NONE, |result| {
+                if let Err(e) = result {
+                    eprintln!("Failed to pass message of the server: {e}");
+                }
+            });
+        }
+    });
+}
+
+/// Connect to the server on `socket` and pass messages until the connection is closed
+///
+/// `url` is the path and query of the websocket.
+fn connect(
+    socket: &Path,
+    url: &str,
+    outgoing: &std_mpsc::Receiver<String>,
+    events: &mpsc::UnboundedSender<Event>,
+) -> Result<(), Box<dyn Error>> {
+    let stream = UnixStream::connect(socket)?;
+    let (mut ws, _) =
+        tungstenite::client(format!("ws://localhost{url}"), stream).map_err(|e| e.to_string())?;
+
+    // Reading only blocks this long, so messages from main.js are sent in time
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let _ = events.unbounded_send(Event::Opened);
+
+    loop {
+        loop {
+            match outgoing.try_recv() {
+                Ok(msg) => ws.send(Message::text(msg))?,
+                Err(std_mpsc::TryRecvError::Empty) => break,
+                // The viewer was closed
+                Err(std_mpsc::TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        match ws.read() {
+            Ok(Message::Text(text)) => {
+                if events
+                    .unbounded_send(Event::Received(text.to_string()))
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Quote `s` as a JavaScript string literal
+fn js_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            // Including line terminators, which aren't allowed in string literals
+            c if c.is_control() || matches!(c, '\u{2028}' | '\u{2029}') => {
+                quoted.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
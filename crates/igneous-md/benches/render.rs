@@ -0,0 +1,84 @@
+//! Benchmarks of converting real-world READMEs to html
+//!
+//! Run using `cargo bench -p igneous-md`. See `fixtures/README.md` for where the documents are
+//! from.
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use igneous_md::convert::{ConvertOptions, blocks, md_to_main};
+use std::{hint::black_box, path::Path};
+
+const FIXTURES: [(&str, &str); 3] = [
+    ("igneous-md", include_str!("../../../README.md")),
+    ("serde_json", include_str!("fixtures/serde_json.md")),
+    ("syntect", include_str!("fixtures/syntect.md")),
+];
+
+/// How often all fixtures are repeated for the large document
+const LARGE_REPEAT: usize = 20;
+
+/// The fixtures and a large document made up of all of them
+fn documents() -> Vec<(String, String)> {
+    let mut documents: Vec<_> = FIXTURES
+        .iter()
+        .map(|(name, md)| (name.to_string(), md.to_string()))
+        .collect();
+
+    let all = FIXTURES.map(|(_, md)| md).join("\n\n");
+    documents.push((format!("all-x{LARGE_REPEAT}"), all.repeat(LARGE_REPEAT)));
+
+    documents
+}
+
+fn render(c: &mut Criterion) {
+    let md_path = Path::new("/bench/README.md");
+
+    let options = [
+        ("default", ConvertOptions::default()),
+        (
+            "server-side",
+            ConvertOptions {
+                highlight: true,
+                math: true,
+                ..ConvertOptions::default()
+            },
+        ),
+    ];
+
+    for (options_name, options) in &options {
+        let mut group = c.benchmark_group(format!("render/{options_name}"));
+
+        for (name, md) in documents() {
+            group.throughput(Throughput::Bytes(md.len() as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(&name), &md, |b, md| {
+                b.iter(|| md_to_main(black_box(md), md_path, options))
+            });
+        }
+
+        group.finish();
+    }
+}
+
+fn diff(c: &mut Criterion) {
+    let md_path = Path::new("/bench/README.md");
+    let mut group = c.benchmark_group("diff");
+
+    for (name, md) in documents() {
+        // An edit at the start of the document, which moves all other blocks
+        let edited = format!("Edited\n\n{md}");
+
+        let old = blocks::blocks(&md_to_main(&md, md_path, &ConvertOptions::default()))
+            .expect("Markdown only creates elements.");
+        let new = blocks::blocks(&md_to_main(&edited, md_path, &ConvertOptions::default()))
+            .expect("Markdown only creates elements.");
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(&name),
+            &(old, new),
+            |b, (old, new)| b.iter(|| blocks::diff(black_box(old), black_box(new))),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, render, diff);
+criterion_main!(benches);
@@ -0,0 +1,269 @@
+//! Unsaved markdown pushed by editors. See: [Buffers]
+//!
+//! Editors send the content of their buffers using [crate::ws::msg::ClientMsg::PushMarkdown] and
+//! [crate::ws::msg::ClientMsg::PatchMarkdown]. As long as an editor is connected, all clients
+//! viewing the same file render its buffer instead of the file on disk.
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// Errors that can occur while patching a buffer
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PatchError {
+    /// No content was pushed for the file, which could be patched
+    #[error("No markdown was pushed for {0}")]
+    NoBuffer(PathBuf),
+    /// The lines replaced by the edit don't exist
+    #[error("Lines {first}..{last} are out of range, the buffer has {len} lines")]
+    OutOfRange {
+        /// See [LineEdit::first]
+        first: usize,
+        /// See [LineEdit::last]
+        last: usize,
+        /// Number of lines in the buffer
+        len: usize,
+    },
+}
+
+/// A change to the lines of a buffer
+///
+/// Replaces the lines `first..last` with [Self::lines]. Lines are counted from `0`. This is the
+/// same format Neovim uses for `nvim_buf_attach()` callbacks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LineEdit {
+    /// First line that is replaced
+    pub first: usize,
+    /// Line after the last line that is replaced
+    pub last: usize,
+    /// The new lines, without line endings
+    pub lines: Vec<String>,
+}
+
+/// The content of a buffer
+#[derive(Debug)]
+struct Buffer {
+    /// The markdown
+    content: String,
+    /// Id of the connection which pushed the markdown
+    connection: usize,
+    /// Incremented on every change to any buffer, so clients can tell if they are up to date
+    version: u64,
+}
+
+/// The buffers of all connected editors, shared by all connections of a server
+#[derive(Debug)]
+pub struct Buffers {
+    /// The buffers by the canonical path of their files
+    buffers: RwLock<HashMap<PathBuf, Buffer>>,
+    /// The version of the last change. See: [Buffer::version]
+    version: AtomicU64,
+    /// Sends the canonical path of every buffer which changed or was removed
+    update_sender: broadcast::Sender<PathBuf>,
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            buffers: RwLock::default(),
+            version: AtomicU64::default(),
+            update_sender: broadcast::channel(16).0,
+        }
+    }
+}
+
+impl Buffers {
+    /// Set the content of the buffer for the file at `path`
+    pub fn push(&self, connection: usize, path: &Path, content: String) {
+        let path = canonical(path);
+
+        self.buffers.write().unwrap().insert(
+            path.clone(),
+            Buffer {
+                content,
+                connection,
+                version: self.next_version(),
+            },
+        );
+
+        // Fails if there are no receivers, which is fine
+        let _ = self.update_sender.send(path);
+    }
+
+    /// Apply the `edits` to the buffer for the file at `path`, in order
+    ///
+    /// The buffer has to be created using [Self::push()] first. If any edit fails, the buffer is
+    /// left unchanged.
+    pub fn patch(
+        &self,
+        connection: usize,
+        path: &Path,
+        edits: &[LineEdit],
+    ) -> Result<(), PatchError> {
+        let path = canonical(path);
+
+        {
+            let mut buffers = self.buffers.write().unwrap();
+
+            let buffer = buffers
+                .get_mut(&path)
+                .ok_or_else(|| PatchError::NoBuffer(path.clone()))?;
+
+            let mut lines: Vec<&str> = buffer.content.split('\n').collect();
+
+            for edit in edits {
+                if edit.first > edit.last || edit.last > lines.len() {
+                    return Err(PatchError::OutOfRange {
+                        first: edit.first,
+                        last: edit.last,
+                        len: lines.len(),
+                    });
+                }
+
+                lines.splice(edit.first..edit.last, edit.lines.iter().map(String::as_str));
+            }
+
+            *buffer = Buffer {
+                content: lines.join("\n"),
+                connection,
+                version: self.next_version(),
+            };
+        }
+
+        let _ = self.update_sender.send(path);
+
+        Ok(())
+    }
+
+    /// Get the content and version of the buffer for the file at `path`, if there is one
+    pub fn get(&self, path: &Path) -> Option<(String, u64)> {
+        self.buffers
+            .read()
+            .unwrap()
+            .get(&canonical(path))
+            .map(|b| (b.content.clone(), b.version))
+    }
+
+    /// Whether there is a buffer for the file at `path`
+    pub fn contains(&self, path: &Path) -> bool {
+        self.buffers.read().unwrap().contains_key(&canonical(path))
+    }
+
+    /// Remove all buffers pushed by the `connection`
+    ///
+    /// Called when an editor disconnects, so its files are shown as they are on disk again.
+    pub fn release(&self, connection: usize) {
+        let mut released = Vec::new();
+
+        self.buffers.write().unwrap().retain(|path, buffer| {
+            if buffer.connection == connection {
+                released.push(path.clone());
+                return false;
+            }
+
+            true
+        });
+
+        for path in released {
+            let _ = self.update_sender.send(path);
+        }
+    }
+
+    /// Get a receiver for the paths of buffers which changed or were removed
+    pub fn subscribe(&self) -> broadcast::Receiver<PathBuf> {
+        self.update_sender.subscribe()
+    }
+
+    /// Increment and return [Self::version]
+    fn next_version(&self) -> u64 {
+        self.version.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// Canonicalize the `path`, so different paths to the same file share a buffer
+///
+/// Files that don't exist yet keep their path as it is.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Buffers, LineEdit, PatchError};
+    use std::path::Path;
+
+    #[test]
+    fn push_and_release() {
+        let buffers = Buffers::default();
+        let mut updates = buffers.subscribe();
+        let path = Path::new("/does/not/exist.md");
+
+        buffers.push(1, path, "# Old".to_string());
+        buffers.push(1, path, "# New".to_string());
+
+        let (content, version) = buffers.get(path).unwrap();
+        assert_eq!(content, "# New");
+        assert_eq!(version, 2);
+
+        // only the buffers of the connection are released
+        buffers.release(2);
+        assert!(buffers.get(path).is_some());
+        buffers.release(1);
+        assert!(buffers.get(path).is_none());
+
+        for _ in 0..3 {
+            assert_eq!(updates.try_recv().unwrap(), path);
+        }
+        assert!(updates.try_recv().is_err());
+    }
+
+    #[test]
+    fn patch() {
+        let buffers = Buffers::default();
+        let path = Path::new("/does/not/exist.md");
+
+        let edit = |first, last, lines: &[&str]| LineEdit {
+            first,
+            last,
+            lines: lines.iter().map(|l| l.to_string()).collect(),
+        };
+
+        assert_eq!(
+            buffers.patch(1, path, &[edit(0, 0, &["a"])]),
+            Err(PatchError::NoBuffer(path.to_path_buf()))
+        );
+
+        buffers.push(1, path, "a\nb\nc".to_string());
+
+        buffers
+            .patch(
+                1,
+                path,
+                &[
+                    edit(1, 2, &["B", "B2"]),
+                    edit(0, 1, &[]),
+                    edit(3, 3, &["d"]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(buffers.get(path).unwrap().0, "B\nB2\nc\nd");
+
+        // failing edits leave the buffer unchanged
+        assert_eq!(
+            buffers.patch(1, path, &[edit(0, 1, &[]), edit(4, 5, &[])]),
+            Err(PatchError::OutOfRange {
+                first: 4,
+                last: 5,
+                len: 3
+            })
+        );
+        assert_eq!(buffers.get(path).unwrap().0, "B\nB2\nc\nd");
+    }
+}
@@ -1,9 +1,13 @@
 //! Module containing all CLI related functionality
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use git_version::git_version;
 use std::path::PathBuf;
 
-use crate::paths::DEFAULT_CONFIG_DIR;
+use igneous_md::{
+    convert::{ConvertOptions, filter::ExternalFilter},
+    instance::{DEFAULT_INSTANCE, Instance},
+    paths::DEFAULT_CONFIG_DIR,
+};
 
 const VERSION: &str = git_version!(
     args = ["--always", "--dirty=-dirty"],
@@ -42,11 +46,16 @@ pub enum Action {
     /// View a markdown file
     #[command(visible_alias = "v")]
     View {
-        /// Path to markdown file
+        /// Path to markdown file, or `-` to read it from stdin
+        ///
+        /// Markdown from stdin is updated live. NUL or form-feed characters separate documents,
+        /// each replacing the previous one once it is complete.
         #[arg(value_name = "PATH", required = true)]
         path: PathBuf,
-        /// Path to stylesheet within css dir
-        #[arg(short, long, value_name = "PATH")]
+        /// Stylesheet to start with, by file name or stem within the css dir
+        ///
+        /// Defaults to the stylesheet selected last.
+        #[arg(short, long, value_name = "NAME")]
         css: Option<PathBuf>,
         /// Start server without viewer
         #[arg(long, default_value = "false")]
@@ -55,13 +64,38 @@ pub enum Action {
         /// Port to run the server on
         #[arg(short, long, default_value = "0")]
         port: u16,
-        // TODO: Add this option back in here once viewer is updated to not need an http server
-        // /// Open browser tab
-        // #[arg(short, long, visible_aliases = ["web"], default_value = "false")]
-        // browser: bool,
-        /// How often to check for updates (in ms)
+        /// Unix socket to run the server on, instead of a port
+        ///
+        /// Unlike ports, only you can connect to the socket.
+        #[arg(short, long, value_name = "PATH", conflicts_with = "port")]
+        socket: Option<PathBuf>,
+        /// Name of the server instance to connect to or launch
+        ///
+        /// Servers of different instances run side by side, e.g. one per set of documents.
+        /// Viewers connect to the server of an instance by its name.
+        #[arg(short, long, value_name = "NAME", default_value = DEFAULT_INSTANCE)]
+        instance: Instance,
+        /// Use the server instance of the project the file belongs to
+        ///
+        /// The project is the closest directory containing a `.git`, or the directory of the
+        /// file.
+        #[arg(long, conflicts_with = "instance")]
+        project: bool,
+        /// Open browser tab instead of the viewer
+        #[arg(short, long, visible_aliases = ["web"], default_value = "false")]
+        #[cfg(feature = "viewer")]
+        browser: bool,
+        /// How often to poll for updates (in ms), if files can't be watched
         #[arg(short, long, default_value = "1000")]
         update_rate: u64,
+        /// Poll files for changes instead of watching them
+        ///
+        /// Use this for file systems which don't support watching, such as some network file
+        /// systems.
+        #[arg(long)]
+        poll: bool,
+        #[command(flatten)]
+        convert_args: ConvertArgs,
     },
     /// Convert a md file to html and save it to disk
     ///
@@ -69,16 +103,23 @@ pub enum Action {
     #[command(visible_alias = "export")]
     #[cfg(feature = "viewer")]
     Convert {
-        /// The file to convert
+        /// The file to convert, or `-` to read it from stdin
+        ///
+        /// If stdin contains several documents separated by NUL or form-feed characters, the last
+        /// one is converted.
         path: PathBuf,
-        /// Path to set the css stylesheet to
-        #[arg(short, long, value_name = "PATH")]
+        /// Stylesheet to use, by file name or stem within the css dir
+        ///
+        /// Defaults to the stylesheet selected last.
+        #[arg(short, long, value_name = "NAME")]
         css: Option<PathBuf>,
         /// Path to save the file to
         ///
         /// Defaults to saving it into the config dir.
         #[arg(short, long, value_name = "FILE")]
         export_path: Option<PathBuf>,
+        #[command(flatten)]
+        convert_args: ConvertArgs,
     },
     /// Generate shell completions
     Completions {
@@ -92,3 +133,43 @@ pub enum Action {
         overwrite: bool,
     },
 }
+
+/// Arguments adjusting how markdown is converted to html
+///
+/// These only take effect when a new server is launched, since the options are shared by all
+/// clients of a server.
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// Highlight code blocks on the server instead of in the viewer
+    #[arg(long)]
+    pub server_highlight: bool,
+    /// Render math to MathML on the server instead of in the viewer
+    #[arg(long)]
+    pub server_math: bool,
+    /// Remove scripts and other unsafe html from the markdown
+    ///
+    /// Use this when viewing untrusted files. Always on if `safe = true` is set in the
+    /// `config.toml` of the config dir.
+    #[arg(long)]
+    pub safe: bool,
+    /// Pass the html through a command, which writes the modified html to stdout
+    ///
+    /// The command gets the html on stdin, or a JSON description of the document if it is
+    /// prefixed with `json:`. Can be given multiple times, filters are run in order.
+    ///
+    /// The html is left unchanged if the command fails, runs longer than 10 seconds or writes
+    /// html without a `<main>` element.
+    #[arg(long, value_name = "COMMAND")]
+    pub filter: Vec<ExternalFilter>,
+}
+
+impl From<ConvertArgs> for ConvertOptions {
+    fn from(args: ConvertArgs) -> Self {
+        Self {
+            highlight: args.server_highlight,
+            math: args.server_math,
+            safe: args.safe,
+            filters: args.filter,
+        }
+    }
+}
@@ -1,16 +1,32 @@
 //! Module containing the [Client] struct.
 //!
 //! For more information see [Client]
-use kuchikiki::traits::*;
 use std::{
-    io,
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
     time::SystemTime,
 };
 use tokio::sync::broadcast;
 
-use crate::{config::Config, convert::md_to_html};
+use crate::{
+    buffers::Buffers,
+    config::{ColorScheme, Config},
+    convert::{
+        self, ConvertOptions, Heading,
+        blocks::{self, BlockPatch},
+        front_matter::FrontMatter,
+        include, md_to_main,
+    },
+    documents::{Documents, Rendered, Renderer, Subscription},
+    history::History,
+    paths,
+    server::Shared,
+    watcher::{FileWatcher, WatchGuard},
+};
 
 /// Struct representing a client connection to the server
 ///
@@ -32,25 +48,64 @@ pub struct Client {
     ///
     /// Needed to allow for [`crate::ws::msg::ClientMsg::RedirectDefault`]
     initial_md_path: PathBuf,
+    /// The files viewed before and after [Self::md_path]
+    history: History,
+    /// Line to scroll to once the file the client navigated to is rendered
+    ///
+    /// See: [Self::back()], [Self::forward()]
+    restored_line: Option<usize>,
     /// Last time the file was modified
     last_modified: SystemTime,
-    /// The markdown from the file
-    md: String,
-    /// The html `<main>` element of the file
-    html: String,
+    /// Files included by [Self::md_path] and the last time they were modified
+    ///
+    /// See [include::expand]
+    included: Vec<(PathBuf, Option<SystemTime>)>,
+    /// The html `<main>` element of the file and its outline
+    rendered: Arc<Rendered>,
+    /// The front matter of the markdown of the file
+    front_matter: FrontMatter,
+    /// The stylesheet last requested by [Self::front_matter]
+    ///
+    /// Used to only apply the stylesheet when it changes, so the user can still change the css
+    /// manually.
+    front_matter_css: Option<String>,
     /// [Config] shared between all clients
     pub config: Arc<RwLock<Config>>,
+    /// Markdown pushed by editors, shared between all clients
+    pub buffers: Arc<Buffers>,
+    /// Version of the buffer the markdown was last taken from, if it was taken from one
+    ///
+    /// See: [Buffers::get()]
+    buffer_version: Option<u64>,
+    /// Watcher shared between all clients
+    file_watcher: Arc<FileWatcher>,
+    /// Keep [Self::md_path] and the files it includes watched
+    watches: Vec<WatchGuard>,
+    /// Whether any of the files couldn't be watched and have to be polled instead
+    polling: bool,
+    /// Renders shared between all clients
+    documents: Arc<Documents>,
+    /// Keeps the render of [Self::md_path] in [Self::documents]
+    document: Subscription,
+    /// The render last used by [Self::html_patch()], which the viewer is showing
+    shown: Option<Arc<Rendered>>,
+    /// Whether the viewer supports [crate::ws::msg::ServerMsg::HtmlPatch]
+    patch_support: bool,
+    /// Whether [Self::md_path] was found missing by the last render, see [Self::set_gone()]
+    gone: bool,
     /// Receiver of [notify::Event]s
     pub config_update_receiver: broadcast::Receiver<notify::Event>,
     /// The current position in [Config::css_entries]
     ///
     /// If this is [None] then there are no css entries available.
     current_css_index: Option<u16>,
+    /// The color scheme preferred by the viewer, see [Self::set_color_scheme()]
+    color_scheme: Option<ColorScheme>,
 }
 
 // NOTE: We could implement conversions to booleans here
 
-/// Enum returned by [Client::changed] to indicate if a `.md` file has changed.
+/// Enum returned by [RenderJob::changed] to indicate if a `.md` file has changed.
 #[derive(Debug, Clone)]
 pub enum MdChanged {
     /// The file has changed, contains the time of the latest change
@@ -60,72 +115,339 @@ pub enum MdChanged {
 }
 
 impl Client {
-    /// Attempt to create a new [Client]
-    pub fn new(md_path: PathBuf, config: Arc<RwLock<Config>>) -> Self {
+    /// Attempt to create a new [Client], using the state `shared` between all clients
+    pub fn new(md_path: PathBuf, shared: &Shared) -> Self {
         let (config_update_receiver, current_css_index);
         {
-            let config = config.read().unwrap();
+            let config = shared.config.read().unwrap();
 
             config_update_receiver = config.update_sender.subscribe();
+            // Start with the stylesheet the user selected last
             current_css_index = if config.css_entries_len() > 0 {
-                Some(0)
+                let last = config.last_css().and_then(|n| config.css_index_by_name(n));
+
+                Some(last.unwrap_or(0) as u16)
             } else {
                 None
             }
         }
 
-        Self {
+        let mut client = Self {
             initial_md_path: md_path.clone(),
+            history: History::new(md_path.clone()),
+            restored_line: None,
+            document: shared.documents.subscribe(&md_path),
             md_path,
-            md: String::new(),
             last_modified: SystemTime::UNIX_EPOCH,
-            html: String::new(),
-            config,
+            included: Vec::new(),
+            rendered: Arc::default(),
+            front_matter: FrontMatter::default(),
+            front_matter_css: None,
+            config: Arc::clone(&shared.config),
+            buffers: Arc::clone(&shared.buffers),
+            buffer_version: None,
+            file_watcher: Arc::clone(&shared.file_watcher),
+            watches: Vec::new(),
+            polling: false,
+            documents: Arc::clone(&shared.documents),
+            shown: None,
+            patch_support: false,
+            gone: false,
             config_update_receiver,
             current_css_index,
+            color_scheme: None,
+        };
+
+        client.update_watches();
+
+        client
+    }
+
+    // NOTE: Being able to change this path without actually updating all the values derived from
+    // it creates a strange state, where all of the data is false given the new path, but the user
+    // must actually call a function to get data to update the data. This should probably be
+    // addressed in the future.
+
+    /// Get [Self::md_path]
+    pub fn md_path(&self) -> &Path {
+        &self.md_path
+    }
+
+    /// Get the canonical path of [Self::md_path], see [Subscription::path()]
+    pub fn canonical_md_path(&self) -> &Path {
+        self.document.path()
+    }
+
+    /// Whether the file at the canonical `path` is the one currently viewed
+    ///
+    /// [Self::md_path] is only canonicalized when it changes, so this is cheap enough to be
+    /// called for every [crate::ws::SourceLine].
+    pub fn is_viewing(&self, path: &Path) -> bool {
+        self.canonical_md_path() == path
+    }
+
+    /// Set [Self::md_path] to the target of a link found in the current file
+    ///
+    /// Returns `false` if the target doesn't exist, neither on disk nor as a buffer pushed by an
+    /// editor. The current file is kept then, so the history only contains files which existed.
+    ///
+    /// See: [paths::resolve_md_link()]
+    pub fn follow_link(&mut self, href: &str) -> bool {
+        let target = paths::resolve_md_link(&self.md_path, href);
+
+        if !target.exists() && !self.buffers.contains(&target) {
+            return false;
         }
+
+        self.navigate(target);
+
+        true
+    }
+
+    /// Set [Self::md_path] back to [Self::initial_md_path]
+    pub fn reset_md_path_to_initial(&mut self) {
+        self.navigate(self.initial_md_path.clone());
     }
 
-    /// Read [Self::md_path] to a string and set [Self::md] to it
-    fn update_md(&mut self) -> io::Result<()> {
-        self.md = std::fs::read_to_string(&self.md_path)?;
+    /// Set [Self::md_path] to a new entry of [Self::history]
+    fn navigate(&mut self, md_path: PathBuf) {
+        self.history.push(md_path.clone());
+        self.restored_line = None;
+        self.set_md_path(md_path);
+    }
+
+    /// Set [Self::md_path] to the previous file in [Self::history]
+    ///
+    /// Returns `false` if there is no previous file.
+    pub fn back(&mut self) -> bool {
+        let Some(entry) = self.history.back().cloned() else {
+            return false;
+        };
+
+        self.restored_line = Some(entry.line);
+        self.set_md_path(entry.path);
 
-        Ok(())
+        true
     }
 
-    /// Check if [Self::md_path] has changed
+    /// Set [Self::md_path] to the next file in [Self::history]
     ///
-    /// Checking is done via the files metadata.
-    pub fn changed(&self) -> io::Result<MdChanged> {
-        let last_modified = std::fs::metadata(&self.md_path)?.modified()?;
+    /// Returns `false` if there is no next file.
+    pub fn forward(&mut self) -> bool {
+        let Some(entry) = self.history.forward().cloned() else {
+            return false;
+        };
 
-        if last_modified != self.last_modified {
-            Ok(MdChanged::Changed(last_modified))
-        } else {
-            Ok(MdChanged::NotChanged)
+        self.restored_line = Some(entry.line);
+        self.set_md_path(entry.path);
+
+        true
+    }
+
+    /// Remember that the client is at `line` of [Self::md_path], see [History::set_line()]
+    pub fn set_line(&mut self, line: usize) {
+        self.history.set_line(line);
+    }
+
+    /// Take the line the client was at, when it last viewed the file it went back or forward to
+    ///
+    /// Returns [None] if the client didn't go back or forward since this was last called.
+    pub fn take_restored_line(&mut self) -> Option<usize> {
+        self.restored_line.take()
+    }
+
+    /// Follow [Self::md_path] to `to`, if it is the file at the canonical path `from`
+    ///
+    /// The current entry of [Self::history] is moved as well. Returns `false` if `from` isn't
+    /// the viewed file.
+    pub fn follow_move(&mut self, from: &Path, to: &Path) -> bool {
+        // The file doesn't exist anymore, so only its directory can be canonicalized
+        let md_path =
+            paths::document_dir(&self.md_path).join(self.md_path.file_name().unwrap_or_default());
+
+        if md_path != from {
+            return false;
+        }
+
+        if self.initial_md_path == self.md_path {
+            self.initial_md_path = to.to_path_buf();
         }
+
+        self.history.replace(to.to_path_buf());
+        self.set_md_path(to.to_path_buf());
+
+        true
     }
 
-    // NOTE: Being able to change this path without actually updating all the values derived from
-    // it creates a strange state, where all of the data is false given the new path, but the user
-    // must actually call a function to get data to update the data. This should probably be
-    // addressed in the future.
+    /// Mark [Self::md_path] as missing, since rendering it failed
+    ///
+    /// Until the file is rendered again, all renders are forced, so it is shown once it
+    /// reappears. Returns `false` if the file was already marked as missing.
+    pub fn set_gone(&mut self) -> bool {
+        !std::mem::replace(&mut self.gone, true)
+    }
 
-    /// Set [Self::md_path]
-    pub fn set_md_path(&mut self, md_path: PathBuf) {
+    /// Set [Self::md_path], moving the watches and the [Self::document] subscription to the new file
+    fn set_md_path(&mut self, md_path: PathBuf) {
+        self.document = self.documents.subscribe(&md_path);
         self.md_path = md_path;
+        self.included.clear();
+        self.gone = false;
+        self.update_watches();
     }
 
-    /// Set [Self::md_path] back to [Self::initial_md_path]
-    pub fn reset_md_path_to_initial(&mut self) {
-        self.md_path = self.initial_md_path.clone();
+    /// Watch [Self::md_path] and the files it includes, replacing the previous [Self::watches]
+    fn update_watches(&mut self) {
+        let mut polling = false;
+
+        let watches = std::iter::once(&self.md_path)
+            .chain(self.included.iter().map(|(path, _)| path))
+            .filter_map(|path| {
+                let watch = self.file_watcher.watch(path);
+                polling |= watch.is_none();
+                watch
+            })
+            .collect();
+
+        // The new watches are created before the old ones are dropped, so files which are still
+        // needed stay watched
+        self.watches = watches;
+        self.polling = polling;
+    }
+
+    /// Whether the file at the canonical `path` is [Self::md_path] or one of the files it includes
+    pub fn is_watching(&self, path: &Path) -> bool {
+        self.watches.iter().any(|w| w.path() == path)
+    }
+
+    /// Whether the files have to be polled for changes, since they can't be watched
+    pub fn polling(&self) -> bool {
+        self.polling
+    }
+
+    /// Set [Self::patch_support]
+    pub fn set_patch_support(&mut self, patch_support: bool) {
+        self.patch_support = patch_support;
+    }
+
+    /// Get the patches turning the html the viewer is showing into the current html
+    ///
+    /// The current html is assumed to be shown afterwards. Returns [None] if the viewer doesn't
+    /// support patches or the html can't be split into blocks, in which case the full html has to
+    /// be sent.
+    pub fn html_patch(&mut self) -> Option<Vec<BlockPatch>> {
+        let shown = self.shown.replace(Arc::clone(&self.rendered));
+
+        if !self.patch_support {
+            return None;
+        }
+
+        Some(blocks::diff(
+            shown?.blocks.as_deref()?,
+            self.rendered.blocks.as_deref()?,
+        ))
+    }
+
+    /// Get the outline of the current html
+    pub fn outline(&self) -> Vec<Heading> {
+        self.rendered.outline.clone()
+    }
+
+    /// Get the front matter of the current markdown
+    pub fn front_matter(&self) -> &FrontMatter {
+        &self.front_matter
+    }
+
+    /// Switch to the stylesheet requested by [Self::front_matter]
+    ///
+    /// This only happens if the requested stylesheet changed since this was last called, for
+    /// example because a different file is now being viewed.
+    ///
+    /// Returns `true` if the current css was changed.
+    pub fn apply_front_matter_css(&mut self) -> bool {
+        let css = self.front_matter.css().map(str::to_string);
+
+        if css == self.front_matter_css {
+            return false;
+        }
+
+        self.front_matter_css = css;
+
+        let Some(name) = &self.front_matter_css else {
+            return false;
+        };
+
+        let index = self.config.read().unwrap().css_index_by_name(name);
+
+        match index {
+            Some(i) => {
+                self.current_css_index = Some(i as u16);
+                true
+            }
+            None => {
+                log::warn!("Stylesheet `{name}` requested by front matter was not found.");
+                false
+            }
+        }
+    }
+
+    /// Switch to the stylesheet with the given file name or stem, see [Config::css_index_by_name()]
+    ///
+    /// Returns `false` if there is no such stylesheet.
+    pub fn set_css_by_name(&mut self, name: &str) -> bool {
+        let Some(index) = self.config.read().unwrap().css_index_by_name(name) else {
+            return false;
+        };
+
+        self.current_css_index = Some(index as u16);
+
+        true
+    }
+
+    /// Set [Self::color_scheme], switching to a stylesheet of that color scheme
+    ///
+    /// See: [Config::css_index_for_color_scheme()]. Returns `true` if the current css was
+    /// changed.
+    pub fn set_color_scheme(&mut self, color_scheme: ColorScheme) -> bool {
+        self.color_scheme = Some(color_scheme);
+
+        let Some(i) = self.current_css_index else {
+            return false;
+        };
+
+        let index = self
+            .config
+            .read()
+            .unwrap()
+            .css_index_for_color_scheme(i as usize, color_scheme) as u16;
+
+        self.current_css_index = Some(index);
+
+        index != i
+    }
+
+    /// Store the current stylesheet as the one the user selected last, see [Config::set_last_css()]
+    pub fn remember_css(&self) {
+        if let Some(name) = self.current_css_name() {
+            self.config.write().unwrap().set_last_css(name);
+        }
+    }
+
+    /// Get the file name of the current stylesheet, see [crate::config::CssEntry::name()]
+    pub fn current_css_name(&self) -> Option<String> {
+        self.current_css_index.and_then(|i| {
+            self.config
+                .read()
+                .unwrap()
+                .get_css_entries_clone()
+                .get(i as usize)
+                .map(|entry| entry.name())
+        })
     }
 
-    /// [Self::get_latest_html_if_changed], but will always return html.
-    pub fn get_latest_html(&mut self) -> io::Result<String> {
-        Ok(self
-            .get_latest_html_if_changed()?
-            .unwrap_or(self.html.clone()))
+    /// Get [Self::current_css_index]
+    pub fn current_css_index(&self) -> Option<u16> {
+        self.current_css_index
     }
 
     /// Get the current css content from [Self::config.css_entries] without changing the index
@@ -140,32 +462,43 @@ impl Client {
         })
     }
 
-    /// Checks if the`.md` file has changed, if so returning the new html else returning [None]
-    pub fn get_latest_html_if_changed(&mut self) -> io::Result<Option<String>> {
-        if let MdChanged::Changed(time) = self.changed()? {
-            self.last_modified = time;
-        } else {
-            return Ok(None);
+    /// Create a job checking if the `.md` file has changed and rendering it if so
+    ///
+    /// The job has to be run using [RenderJob::run()] and its result passed to
+    /// [Self::finish_render()]. If `force` is set, the file is rendered even if it is unchanged,
+    /// e.g. because a different file is viewed now.
+    pub fn render_job(&self, force: bool) -> RenderJob {
+        RenderJob {
+            md_path: self.md_path.clone(),
+            force: force || self.gone,
+            last_modified: self.last_modified,
+            included: self.included.clone(),
+            buffer_version: self.buffer_version,
+            buffers: Arc::clone(&self.buffers),
+            options: self.config.read().unwrap().convert_options(),
+            renderer: self.document.renderer(),
+            cancelled: Arc::default(),
         }
+    }
 
-        self.update_md()?;
-
-        let html = md_to_html(&self.md);
-
-        let document = kuchikiki::parse_html().one(html);
+    /// Update the client to the result of a [RenderJob], returning the new html
+    ///
+    /// Returns [None] if the job was for a different file than the one viewed now.
+    pub fn finish_render(&mut self, render: Render) -> Option<String> {
+        if render.md_path != self.md_path {
+            return None;
+        }
 
-        let mut body = Vec::new();
-        document
-            .select_first("main")
-            .expect("Html must have a main")
-            .as_node()
-            .serialize(&mut body)
-            .expect("Serialization should never fail, if it does there is a bug.");
+        self.last_modified = render.last_modified;
+        self.buffer_version = render.buffer_version;
+        self.front_matter = render.front_matter;
+        self.included = render.included;
+        self.rendered = render.rendered;
+        self.gone = false;
 
-        self.html =
-            String::from_utf8(body).expect("Converting main element to string should never fail.");
+        self.update_watches();
 
-        Ok(Some(self.html.clone()))
+        Some(self.rendered.html.clone())
     }
 
     /// Change the current css
@@ -204,11 +537,183 @@ impl Client {
     }
 }
 
+/// Loading and rendering of the markdown viewed by a [Client], see [Client::render_job()]
+///
+/// Reading and converting large files takes a while, so this is meant to be run on a blocking
+/// thread, e.g. using [tokio::task::spawn_blocking()].
+#[derive(Debug)]
+pub struct RenderJob {
+    /// See [Client::md_path]
+    md_path: PathBuf,
+    /// Render even if the markdown didn't change
+    force: bool,
+    /// See [Client::last_modified]
+    last_modified: SystemTime,
+    /// See [Client::included]
+    included: Vec<(PathBuf, Option<SystemTime>)>,
+    /// See [Client::buffer_version]
+    buffer_version: Option<u64>,
+    /// See [Client::buffers]
+    buffers: Arc<Buffers>,
+    /// How to convert the markdown
+    options: ConvertOptions,
+    /// Renders [Self::md_path], sharing the render with other clients
+    renderer: Renderer,
+    /// Set by [Self::canceller()]
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The result of a [RenderJob], see [Client::finish_render()]
+#[derive(Debug)]
+pub struct Render {
+    /// The file which was rendered
+    md_path: PathBuf,
+    /// See [Client::last_modified]
+    last_modified: SystemTime,
+    /// See [Client::buffer_version]
+    buffer_version: Option<u64>,
+    /// See [Client::front_matter]
+    front_matter: FrontMatter,
+    /// See [Client::included]
+    included: Vec<(PathBuf, Option<SystemTime>)>,
+    /// See [Client::rendered]
+    rendered: Arc<Rendered>,
+}
+
+impl RenderJob {
+    /// Get a flag, which cancels the job when set
+    ///
+    /// A cancelled job stops before doing any further work and returns [None]. This is used to
+    /// drop renders of outdated markdown, once a newer change arrives.
+    pub fn canceller(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+
+    /// Load and render the markdown
+    ///
+    /// If an editor pushed the markdown of the file to [Buffers], it is used instead of the file
+    /// on disk. If another client already rendered the same markdown, its render is reused, see
+    /// [Documents].
+    ///
+    /// Returns [None] if the markdown didn't change or the job was cancelled.
+    pub fn run(self) -> io::Result<Option<Render>> {
+        let (md, last_modified, buffer_version) =
+            if let Some((content, version)) = self.buffers.get(&self.md_path) {
+                if !self.force && self.buffer_version == Some(version) {
+                    return Ok(None);
+                }
+
+                (content, self.last_modified, Some(version))
+            } else {
+                // If the editor disconnected, the file has to be read again, even if it is
+                // unchanged
+                let released = self.buffer_version.is_some();
+
+                let last_modified = match self.changed()? {
+                    MdChanged::Changed(time) => time,
+                    MdChanged::NotChanged if self.force || released => self.last_modified,
+                    MdChanged::NotChanged => return Ok(None),
+                };
+
+                if self.is_cancelled() {
+                    return Ok(None);
+                }
+
+                (fs::read_to_string(&self.md_path)?, last_modified, None)
+            };
+
+        let expansion = include::expand(&md, &self.md_path, &self.options);
+
+        if self.is_cancelled() {
+            return Ok(None);
+        }
+
+        // The source lines depend on the markdown before the includes are expanded as well
+        let rendered = self
+            .renderer
+            .render((&md, &expansion.md, &self.options), || {
+                render(&expansion, &self.md_path, &self.options)
+            });
+
+        Ok(Some(Render {
+            front_matter: FrontMatter::parse(&md).unwrap_or_default(),
+            last_modified,
+            buffer_version,
+            included: expansion.files,
+            rendered,
+            md_path: self.md_path,
+        }))
+    }
+
+    /// Check if [Self::md_path] or any of the files it includes have changed
+    ///
+    /// Checking is done via the files metadata.
+    fn changed(&self) -> io::Result<MdChanged> {
+        let last_modified = fs::metadata(&self.md_path)?.modified()?;
+
+        let included_changed = self.included.iter().any(|(path, modified)| {
+            fs::metadata(path).and_then(|m| m.modified()).ok() != *modified
+        });
+
+        if last_modified != self.last_modified || included_changed {
+            Ok(MdChanged::Changed(last_modified))
+        } else {
+            Ok(MdChanged::NotChanged)
+        }
+    }
+
+    /// Whether [Self::canceller()] was set
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Convert the `expansion` of the file at `md_path` to its `<main>` element
+fn render(expansion: &include::Expansion, md_path: &Path, options: &ConvertOptions) -> Rendered {
+    let main = md_to_main(&expansion.md, md_path, options);
+
+    let outline = convert::outline(&main);
+
+    // Lines of included files are shown as the line of their include directive
+    for element in main
+        .select("[data-source-line]")
+        .expect("Selector is hard-coded.")
+    {
+        let mut attributes = element.attributes.borrow_mut();
+
+        if let Some(line) = attributes.get_mut("data-source-line")
+            && let Ok(n) = line.parse()
+        {
+            *line = expansion.source_line(n).to_string();
+        }
+    }
+
+    let blocks = blocks::blocks(&main);
+
+    let mut body = Vec::new();
+    main.serialize(&mut body)
+        .expect("Serialization should never fail, if it does there is a bug.");
+
+    Rendered {
+        html: String::from_utf8(body)
+            .expect("Converting main element to string should never fail."),
+        outline,
+        blocks,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     impl Client {
+        /// Run a [RenderJob] and finish it
+        fn latest_html(&mut self, force: bool) -> Option<String> {
+            let render = self.render_job(force).run().unwrap()?;
+
+            self.finish_render(render)
+        }
+
         pub fn new_testing(config_stylesheets: usize) -> Self {
             let config = Config::new_testing(config_stylesheets);
 
@@ -222,15 +727,32 @@ mod test {
                 }
             }
 
+            let documents: Arc<Documents> = Arc::default();
+
             Self {
                 initial_md_path: PathBuf::new(),
+                history: History::new(PathBuf::new()),
+                restored_line: None,
                 md_path: PathBuf::new(),
-                md: String::new(),
                 last_modified: SystemTime::UNIX_EPOCH,
-                html: String::new(),
+                included: Vec::new(),
+                rendered: Arc::default(),
+                front_matter: FrontMatter::default(),
+                front_matter_css: None,
                 config_update_receiver,
                 config: Arc::new(RwLock::new(config)),
+                buffers: Arc::default(),
+                buffer_version: None,
+                file_watcher: Arc::new(FileWatcher::polling()),
+                watches: Vec::new(),
+                polling: true,
+                document: documents.subscribe(Path::new("")),
+                documents,
+                shown: None,
+                patch_support: false,
+                gone: false,
                 current_css_index,
+                color_scheme: None,
             }
         }
     }
@@ -290,6 +812,21 @@ mod test {
         assert_eq!(client.current_css(), Some("/* style2.css */".to_string()));
     }
 
+    #[test]
+    fn css_by_name() {
+        let mut client = Client::new_testing(3);
+
+        assert!(client.set_css_by_name("style2"));
+        assert_eq!(client.current_css(), Some("/* style2.css */".to_string()));
+        assert!(client.set_css_by_name("css/style3.css"));
+        assert_eq!(client.current_css(), Some("/* style3.css */".to_string()));
+        assert!(!client.set_css_by_name("missing"));
+        assert_eq!(client.current_css_index(), Some(2));
+
+        client.remember_css();
+        assert_eq!(client.config.read().unwrap().last_css(), Some("style3.css"));
+    }
+
     #[test]
     fn next_previous_on_single() {
         let mut client = Client::new_testing(1);
@@ -323,4 +860,145 @@ mod test {
         client.change_current_css_index(-1, false);
         assert_eq!(client.current_css(), None);
     }
+
+    #[test]
+    fn front_matter_css() {
+        let mut client = Client::new_testing(3);
+
+        client.front_matter =
+            FrontMatter::parse("---\ncss: style3\n---\n").expect("Front matter is valid.");
+        assert!(client.apply_front_matter_css());
+        assert_eq!(client.current_css(), Some("/* style3.css */".to_string()));
+
+        // the user changing the css manually isn't overridden by a re-render
+        client.change_current_css_index(1, true);
+        assert!(!client.apply_front_matter_css());
+        assert_eq!(client.current_css(), Some("/* style1.css */".to_string()));
+
+        client.front_matter =
+            FrontMatter::parse("---\ntheme: style2.css\n---\n").expect("Front matter is valid.");
+        assert!(client.apply_front_matter_css());
+        assert_eq!(client.current_css(), Some("/* style2.css */".to_string()));
+
+        client.front_matter =
+            FrontMatter::parse("---\ncss: missing\n---\n").expect("Front matter is valid.");
+        assert!(!client.apply_front_matter_css());
+        assert_eq!(client.current_css(), Some("/* style2.css */".to_string()));
+    }
+
+    #[test]
+    fn pushed_markdown() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("pushed.md");
+        std::fs::write(&path, "# Disk").unwrap();
+
+        let mut client = Client::new_testing(1);
+        client.set_md_path(path.clone());
+
+        let latest_contains = |client: &mut Client, text: &str| {
+            client
+                .latest_html(false)
+                .is_some_and(|html| html.contains(text))
+        };
+
+        assert!(latest_contains(&mut client, "Disk"));
+
+        client.buffers.push(1, &path, "# Buffer".to_string());
+        assert!(latest_contains(&mut client, "Buffer"));
+        assert!(client.latest_html(false).is_none());
+
+        // the file on disk is shown again, once the editor disconnects
+        client.buffers.release(1);
+        assert!(latest_contains(&mut client, "Disk"));
+    }
+
+    #[test]
+    fn render_jobs() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("jobs.md");
+        std::fs::write(&path, "# Title").unwrap();
+
+        let mut client = Client::new_testing(1);
+        client.set_md_path(path.clone());
+
+        // cancelled jobs don't render anything
+        let job = client.render_job(false);
+        job.canceller().store(true, Ordering::Relaxed);
+        assert!(job.run().unwrap().is_none());
+
+        assert!(client.latest_html(false).is_some());
+        assert!(client.latest_html(false).is_none());
+        // forced jobs render unchanged files as well
+        assert!(client.latest_html(true).is_some());
+
+        // results for a file that is no longer viewed are dropped
+        let render = client.render_job(true).run().unwrap().unwrap();
+        client.set_md_path(path.with_extension("other.md"));
+        assert!(client.finish_render(render).is_none());
+    }
+
+    #[test]
+    fn gone_and_moved() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let path = dir.join("file.md");
+        std::fs::write(&path, "# Title").unwrap();
+
+        let mut client = Client::new_testing(1);
+        client.navigate(path.clone());
+        assert!(client.latest_html(false).is_some());
+        assert!(client.is_viewing(&path.canonicalize().unwrap()));
+        assert!(!client.is_viewing(&dir.join("other.md")));
+
+        std::fs::remove_file(&path).unwrap();
+        let error = client.render_job(false).run().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(client.set_gone());
+        assert!(!client.set_gone());
+
+        // the file is shown once it reappears, even if it is unchanged
+        std::fs::write(&path, "# Title").unwrap();
+        assert!(client.latest_html(false).is_some());
+        assert!(client.set_gone());
+
+        let moved = dir.join("moved.md");
+        std::fs::rename(&path, &moved).unwrap();
+        assert!(!client.follow_move(&dir.join("other.md"), &moved));
+        assert!(client.follow_move(&dir.canonicalize().unwrap().join("file.md"), &moved));
+        assert_eq!(client.md_path(), moved);
+        assert!(client.is_viewing(&moved.canonicalize().unwrap()));
+        assert!(client.latest_html(true).is_some());
+    }
+
+    #[test]
+    fn back_and_forward() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("README.md"), "# Readme").unwrap();
+        std::fs::write(dir.join("guide.md"), "# Guide").unwrap();
+
+        let mut client = Client::new_testing(1);
+        client.navigate(dir.join("README.md"));
+        client.set_line(12);
+
+        // files which don't exist aren't navigated to
+        assert!(!client.follow_link("missing.md"));
+        assert_eq!(client.md_path(), dir.join("README.md"));
+
+        assert!(client.follow_link("guide.md"));
+        assert_eq!(client.md_path(), dir.join("guide.md"));
+        assert_eq!(client.take_restored_line(), None);
+
+        // the line is restored once
+        assert!(client.back());
+        assert_eq!(client.md_path(), dir.join("README.md"));
+        assert_eq!(client.take_restored_line(), Some(12));
+        assert_eq!(client.take_restored_line(), None);
+
+        assert!(client.forward());
+        assert_eq!(client.md_path(), dir.join("guide.md"));
+        assert_eq!(client.take_restored_line(), Some(1));
+        assert!(!client.forward());
+    }
 }
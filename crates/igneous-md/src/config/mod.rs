@@ -8,15 +8,64 @@
 //! The main item of this config is the [Config] struct, but it also contains [generate] to
 //! generate the default config on disk.
 use notify::Watcher;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
-    io,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
 };
+use strum::EnumString;
 use tokio::sync::broadcast;
 
+use crate::convert::ConvertOptions;
+
 pub mod generate;
 
+static COLOR_SCHEME_COMMENT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"/\*\s*igneous-md\s+color-scheme:\s*(light|dark)\s*\*/")
+        .expect("Regex is hard-coded.")
+});
+
+/// Whether a stylesheet is light or dark
+///
+/// Clients tell the server which one the desktop or browser prefers, and are served stylesheets
+/// of that color scheme. See: [Config::css_index_for_color_scheme()]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ColorScheme {
+    /// Dark text on a light background
+    Light,
+    /// Light text on a dark background
+    Dark,
+}
+
+impl ColorScheme {
+    /// Get the color scheme of the stylesheet at `path` with the given `css`
+    ///
+    /// The color scheme is set by a `/* igneous-md color-scheme: dark */` comment. Without one,
+    /// `light` or `dark` in the file name, e.g. `github-markdown-dark.css`, is used.
+    pub fn of_stylesheet(path: &Path, css: &str) -> Option<Self> {
+        if let Some(captures) = COLOR_SCHEME_COMMENT_REGEX.captures(css) {
+            return captures[1].parse().ok();
+        }
+
+        let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+
+        stem.split(|c: char| !c.is_alphanumeric())
+            .find_map(|word| word.parse().ok())
+    }
+
+    /// Get the name of the color scheme, e.g. `dark`
+    fn as_str(self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        }
+    }
+}
+
 /// A CSS entry with its path and content
 #[derive(Debug, Clone)]
 pub struct CssEntry {
@@ -24,6 +73,61 @@ pub struct CssEntry {
     pub path: PathBuf,
     /// CSS content read from the file
     pub content: String,
+    /// Whether the stylesheet is light or dark, if known
+    ///
+    /// See: [ColorScheme::of_stylesheet()]
+    pub color_scheme: Option<ColorScheme>,
+}
+
+impl CssEntry {
+    /// Name of the entry, which is the file name of [Self::path]
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Settings of the `config.toml` in the config dir
+///
+/// ```toml
+/// # Always sanitize the html, as if `--safe` was given
+/// safe = true
+/// ```
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Sanitize the html of all documents, see [ConvertOptions::safe]
+    pub safe: bool,
+}
+
+impl Settings {
+    /// Read the settings from the `file`, using the defaults if it doesn't exist
+    pub fn read(file: &Path) -> io::Result<Self> {
+        match fs::read_to_string(file) {
+            Ok(content) => toml::from_str(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid {}: {e}", file.display()),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The options a server was launched with, which apply to all of its clients
+///
+/// Sent in answer to [crate::ws::msg::ClientMsg::GetServerOptions], so files aren't shown by a
+/// running server which was launched with different options.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServerOptions {
+    /// See [Config::convert_options()]
+    pub convert: ConvertOptions,
+    /// See [Config::poll()]
+    pub poll: bool,
 }
 
 /// Struct containing all information relating to the config, including the css files.
@@ -39,13 +143,33 @@ pub struct Config {
     pub update_sender: tokio::sync::broadcast::Sender<notify::Event>,
     /// The watcher, if it is running
     watcher: Option<notify::RecommendedWatcher>,
+    /// The settings of the `config.toml`
+    settings: Settings,
+    /// How markdown is converted to html for all clients
+    convert_options: ConvertOptions,
+    /// Whether markdown files are polled for changes instead of being watched
+    poll: bool,
+    /// File the last stylesheet selected by the user is stored in, see [Self::set_last_css()]
+    last_css_file: Option<PathBuf>,
+    /// File name of the last stylesheet selected by the user
+    last_css: Option<String>,
 }
 
 impl Config {
     /// Attempt to create a new [Config]
     ///
-    /// This may fail, since to set [Config::css_entries] we need to read from the Filesystem.
+    /// This may fail, since to set [Config::css_entries] and [Config::settings] we need to read
+    /// from the Filesystem.
     pub fn new(config_dir: PathBuf) -> io::Result<Self> {
+        let settings = Settings::read(&config_dir.join("config.toml"))?;
+
+        let last_css_file = crate::paths::LAST_CSS_FILE.clone();
+        let last_css = last_css_file
+            .as_ref()
+            .and_then(|f| fs::read_to_string(f).ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+
         Ok(Self {
             css_entries: Arc::new(Mutex::new(crate::paths::read_css_dir(
                 &config_dir.join("css/"),
@@ -53,6 +177,14 @@ impl Config {
             config_dir,
             update_sender: broadcast::channel(1).0,
             watcher: None,
+            convert_options: ConvertOptions {
+                safe: settings.safe,
+                ..Default::default()
+            },
+            settings,
+            poll: false,
+            last_css_file,
+            last_css,
         })
     }
 
@@ -66,6 +198,94 @@ impl Config {
         self.css_entries.lock().unwrap().len()
     }
 
+    /// Find the index of the css entry with the given file name or file stem
+    ///
+    /// If `name` is a path, such as `css/github-markdown-dark.css`, only its file name is used.
+    pub fn css_index_by_name(&self, name: &str) -> Option<usize> {
+        let name = Path::new(name).file_name()?;
+
+        self.css_entries.lock().unwrap().iter().position(|entry| {
+            entry.path.file_name().is_some_and(|n| n == name)
+                || entry.path.file_stem().is_some_and(|n| n == name)
+        })
+    }
+
+    /// Find the css entry of the given `color_scheme` to use instead of the one at `index`
+    ///
+    /// Entries without a color scheme, or of the requested one, are kept. Otherwise the entry
+    /// with the other color scheme in its name is used, e.g. `github-markdown-light.css` for
+    /// `github-markdown-dark.css`, falling back to the first entry of the color scheme.
+    pub fn css_index_for_color_scheme(&self, index: usize, color_scheme: ColorScheme) -> usize {
+        let entries = self.css_entries.lock().unwrap();
+
+        let Some(current) = entries.get(index) else {
+            return index;
+        };
+
+        let Some(current_scheme) = current.color_scheme else {
+            return index;
+        };
+
+        if current_scheme == color_scheme {
+            return index;
+        }
+
+        let counterpart = current
+            .name()
+            .to_lowercase()
+            .replace(current_scheme.as_str(), color_scheme.as_str());
+
+        let of_scheme = || {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.color_scheme == Some(color_scheme))
+        };
+
+        of_scheme()
+            .find(|(_, entry)| entry.name().to_lowercase() == counterpart)
+            .or_else(|| of_scheme().next())
+            .map_or(index, |(i, _)| i)
+    }
+
+    /// The file names of all css entries, in the order of [Self::css_entries]
+    pub fn css_names(&self) -> Vec<String> {
+        self.css_entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(CssEntry::name)
+            .collect()
+    }
+
+    /// Get [Self::last_css]
+    pub fn last_css(&self) -> Option<&str> {
+        self.last_css.as_deref()
+    }
+
+    /// Set [Self::last_css] and store it, so the next launch starts with the same stylesheet
+    ///
+    /// Failing to store it is only logged, since the stylesheet is still applied.
+    pub fn set_last_css(&mut self, name: String) {
+        if self.last_css.as_ref() == Some(&name) {
+            return;
+        }
+
+        if let Some(file) = &self.last_css_file
+            && let Err(e) = file
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::write(file, &name))
+        {
+            log::warn!(
+                "Failed to store the last stylesheet in {}: {e}",
+                file.display()
+            );
+        }
+
+        self.last_css = Some(name);
+    }
+
     /// Directory where the css files are located
     pub fn css_dir(&self) -> PathBuf {
         self.config_dir.join("css")
@@ -76,6 +296,40 @@ impl Config {
         self.config_dir.join("css/hljs")
     }
 
+    /// Get [Self::convert_options]
+    pub fn convert_options(&self) -> ConvertOptions {
+        self.convert_options.clone()
+    }
+
+    /// Set [Self::convert_options]
+    ///
+    /// [ConvertOptions::safe] stays set if [Settings::safe] is, so the `config.toml` can't be
+    /// overridden.
+    pub fn set_convert_options(&mut self, convert_options: ConvertOptions) {
+        self.convert_options = ConvertOptions {
+            safe: convert_options.safe || self.settings.safe,
+            ..convert_options
+        };
+    }
+
+    /// Get [Self::poll]
+    pub fn poll(&self) -> bool {
+        self.poll
+    }
+
+    /// Set [Self::poll]
+    pub fn set_poll(&mut self, poll: bool) {
+        self.poll = poll;
+    }
+
+    /// Get the [ServerOptions] of a server using this config
+    pub fn server_options(&self) -> ServerOptions {
+        ServerOptions {
+            convert: self.convert_options(),
+            poll: self.poll,
+        }
+    }
+
     /// Start watching the [Self::config_dir]
     ///
     /// After this [Self::update_sender] will start sending events.
@@ -109,11 +363,23 @@ impl Config {
     }
 
     /// Get the path to export a file
-    pub fn export_path(&self) -> PathBuf {
-        self.config_dir.join(format!(
-            "export-{}.pdf",
-            chrono::Local::now().format("%y-%m-%d-%H-%M-%S"),
-        ))
+    ///
+    /// If a `title` is given, it is used as the file name. Otherwise the file is named after the
+    /// current time.
+    pub fn export_path(&self, title: Option<&str>) -> PathBuf {
+        let title = title
+            .map(|t| t.trim().replace(['/', '\\', '\0'], "-"))
+            .filter(|t| !t.is_empty() && t != "." && t != "..");
+
+        let file_name = match title {
+            Some(title) => format!("{title}.pdf"),
+            None => format!(
+                "export-{}.pdf",
+                chrono::Local::now().format("%y-%m-%d-%H-%M-%S"),
+            ),
+        };
+
+        self.config_dir.join(file_name)
     }
 }
 
@@ -131,6 +397,7 @@ impl Config {
             css_entries.push(CssEntry {
                 path: PathBuf::from(format!("/css/style{n}.css")),
                 content: format!("/* style{n}.css */"),
+                color_scheme: None,
             });
         }
 
@@ -139,6 +406,77 @@ impl Config {
             css_entries: Arc::new(Mutex::new(css_entries)),
             update_sender: broadcast::channel(1).0,
             watcher: None,
+            settings: Settings::default(),
+            convert_options: ConvertOptions::default(),
+            poll: false,
+            last_css_file: None,
+            last_css: None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ColorScheme, Config, CssEntry, Settings};
+    use crate::convert::ConvertOptions;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn color_scheme_of_stylesheet() {
+        let of = |name: &str, css: &str| ColorScheme::of_stylesheet(Path::new(name), css);
+
+        assert_eq!(of("github-markdown-dark.css", ""), Some(ColorScheme::Dark));
+        assert_eq!(of("01_Light.css", ""), Some(ColorScheme::Light));
+        assert_eq!(of("darkest.css", ""), None);
+        // the comment takes precedence over the name
+        assert_eq!(
+            of("dark.css", "/* igneous-md color-scheme: light */ body {}"),
+            Some(ColorScheme::Light)
+        );
+    }
+
+    #[test]
+    fn css_for_color_scheme() {
+        let config = Config::new_testing(0);
+
+        *config.css_entries.lock().unwrap() = [
+            ("a-dark.css", Some(ColorScheme::Dark)),
+            ("b-dark.css", Some(ColorScheme::Dark)),
+            ("b-light.css", Some(ColorScheme::Light)),
+            ("plain.css", None),
+        ]
+        .into_iter()
+        .map(|(name, color_scheme)| CssEntry {
+            path: PathBuf::from("/css").join(name),
+            content: String::new(),
+            color_scheme,
+        })
+        .collect();
+
+        // the counterpart is preferred
+        assert_eq!(config.css_index_for_color_scheme(1, ColorScheme::Light), 2);
+        assert_eq!(config.css_index_for_color_scheme(2, ColorScheme::Dark), 1);
+        // otherwise the first entry of the color scheme is used
+        assert_eq!(config.css_index_for_color_scheme(0, ColorScheme::Light), 2);
+        // entries which already match or have no color scheme are kept
+        assert_eq!(config.css_index_for_color_scheme(0, ColorScheme::Dark), 0);
+        assert_eq!(config.css_index_for_color_scheme(3, ColorScheme::Dark), 3);
+    }
+
+    #[test]
+    fn settings() {
+        assert_eq!(
+            toml::from_str::<Settings>("safe = true").unwrap(),
+            Settings { safe: true }
+        );
+        assert_eq!(toml::from_str::<Settings>("").unwrap(), Settings::default());
+        assert!(toml::from_str::<Settings>("sfae = true").is_err());
+
+        let mut config = Config::new_testing(0);
+        config.settings.safe = true;
+
+        // the safe mode of the settings can't be turned off
+        config.set_convert_options(ConvertOptions::default());
+        assert!(config.convert_options().safe);
+    }
+}
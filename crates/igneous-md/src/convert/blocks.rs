@@ -0,0 +1,320 @@
+//! Splitting of documents into blocks, which can be updated individually. See: [blocks] and [diff]
+//!
+//! The blocks of a document are the elements directly inside its `<main>`. Instead of sending the
+//! whole html on every change, only the blocks which changed are sent as [BlockPatch]es.
+use kuchikiki::NodeRef;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, DiffOp, capture_diff_slices};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::LazyLock,
+};
+
+static SOURCE_LINE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#" data-source-line="[^"]*""#).expect("Regex is hard-coded."));
+
+/// A block of a document, see [blocks]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// Id of the block, which is also set as its `data-block` attribute
+    ///
+    /// The id is a hash of the html of the block without its `data-source-line`s, so it stays the
+    /// same as long as the content of the block doesn't change, even if the block moves. Blocks
+    /// with the same content get a `-1`, `-2`, ... suffix, so ids are unique within a document.
+    pub id: String,
+    /// The html of the block
+    pub html: String,
+    /// The `data-source-line`s of the block and its descendants, in document order
+    pub lines: Vec<String>,
+}
+
+/// A change to the blocks of a document, see [diff]
+///
+/// Indices count the element children of `<main>`, starting at `0`, at the time the patch is
+/// applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "t", content = "c")]
+pub enum BlockPatch {
+    /// Insert a block before the block at `index`, or at the end if there is no such block
+    Insert {
+        /// Where to insert the block
+        index: usize,
+        /// Html of the block
+        html: String,
+    },
+    /// Replace the block at `index`
+    Replace {
+        /// Which block to replace
+        index: usize,
+        /// Html of the new block
+        html: String,
+    },
+    /// Remove the block at `index`
+    Remove {
+        /// Which block to remove
+        index: usize,
+    },
+    /// Only the lines of the block at `index` changed, e.g. because lines were inserted above it
+    ///
+    /// The `data-source-line`s of the block and its descendants are set to `lines`, in document
+    /// order.
+    SetLines {
+        /// Which block changed
+        index: usize,
+        /// See [Block::lines]
+        lines: Vec<String>,
+    },
+}
+
+/// Split the children of `main` into [Block]s, setting the `data-block` attribute of each
+///
+/// Returns [None] if `main` contains text outside of any element, since such a document can't be
+/// patched block by block.
+pub fn blocks(main: &NodeRef) -> Option<Vec<Block>> {
+    let mut blocks = Vec::new();
+    let mut occurrences: HashMap<u64, usize> = HashMap::new();
+
+    for child in main.children() {
+        let Some(element) = child.as_element() else {
+            if child
+                .as_text()
+                .is_some_and(|text| !text.borrow().trim().is_empty())
+            {
+                return None;
+            }
+
+            // Whitespace and comments aren't shown
+            continue;
+        };
+
+        let content = SOURCE_LINE_REGEX
+            .replace_all(&serialize(&child), "")
+            .into_owned();
+
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let occurrence = occurrences.entry(hash).or_default();
+        let id = match *occurrence {
+            0 => format!("{hash:016x}"),
+            n => format!("{hash:016x}-{n}"),
+        };
+        *occurrence += 1;
+
+        element
+            .attributes
+            .borrow_mut()
+            .insert("data-block", id.clone());
+
+        let lines = child
+            .inclusive_descendants()
+            .filter_map(|node| {
+                node.as_element()?
+                    .attributes
+                    .borrow()
+                    .get("data-source-line")
+                    .map(str::to_string)
+            })
+            .collect();
+
+        blocks.push(Block {
+            id,
+            html: serialize(&child),
+            lines,
+        });
+    }
+
+    Some(blocks)
+}
+
+/// Get the patches, which turn the `old` blocks into the `new` ones when applied in order
+///
+/// Patches are ordered from the end of the document to the start, so the index of each patch is
+/// the index of the block in `old`.
+pub fn diff(old: &[Block], new: &[Block]) -> Vec<BlockPatch> {
+    let old_ids: Vec<&str> = old.iter().map(|b| b.id.as_str()).collect();
+    let new_ids: Vec<&str> = new.iter().map(|b| b.id.as_str()).collect();
+
+    let mut patches = Vec::new();
+
+    for op in capture_diff_slices(Algorithm::Myers, &old_ids, &new_ids)
+        .into_iter()
+        .rev()
+    {
+        match op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => {
+                for i in (0..len).rev() {
+                    let lines = &new[new_index + i].lines;
+
+                    if old[old_index + i].lines != *lines {
+                        patches.push(BlockPatch::SetLines {
+                            index: old_index + i,
+                            lines: lines.clone(),
+                        });
+                    }
+                }
+            }
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => remove(&mut patches, old_index..old_index + old_len),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => insert(
+                &mut patches,
+                old_index,
+                &new[new_index..new_index + new_len],
+            ),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                let common = old_len.min(new_len);
+
+                remove(&mut patches, old_index + common..old_index + old_len);
+                insert(
+                    &mut patches,
+                    old_index + common,
+                    &new[new_index + common..new_index + new_len],
+                );
+
+                for i in (0..common).rev() {
+                    patches.push(BlockPatch::Replace {
+                        index: old_index + i,
+                        html: new[new_index + i].html.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    patches
+}
+
+/// Add patches removing the blocks at `indices`
+fn remove(patches: &mut Vec<BlockPatch>, indices: std::ops::Range<usize>) {
+    patches.extend(indices.rev().map(|index| BlockPatch::Remove { index }));
+}
+
+/// Add patches inserting the `blocks` in order before the block at `index`
+fn insert(patches: &mut Vec<BlockPatch>, index: usize, blocks: &[Block]) {
+    // Inserting in reverse at the same index results in the original order
+    patches.extend(blocks.iter().rev().map(|block| BlockPatch::Insert {
+        index,
+        html: block.html.clone(),
+    }));
+}
+
+/// Serialize the `node` to a string
+fn serialize(node: &NodeRef) -> String {
+    let mut html = Vec::new();
+    node.serialize(&mut html)
+        .expect("Serialization should never fail, if it does there is a bug.");
+
+    String::from_utf8(html).expect("Serializing a node should never fail.")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Block, BlockPatch, blocks, diff};
+    use crate::convert::{ConvertOptions, md_to_main};
+    use std::path::Path;
+
+    fn blocks_of(md: &str) -> Vec<Block> {
+        let main = md_to_main(md, Path::new("/test.md"), &ConvertOptions::default());
+
+        blocks(&main).expect("Markdown only creates elements.")
+    }
+
+    /// Apply the `patches` to `old`, taking the blocks to insert from `new`
+    ///
+    /// Returns the ids and lines of the resulting blocks.
+    fn apply(
+        mut old: Vec<Block>,
+        patches: &[BlockPatch],
+        new: &[Block],
+    ) -> Vec<(String, Vec<String>)> {
+        let block = |html: &str| new.iter().find(|b| b.html == html).unwrap().clone();
+
+        for patch in patches {
+            match patch {
+                BlockPatch::Insert { index, html } => old.insert(*index, block(html)),
+                BlockPatch::Replace { index, html } => old[*index] = block(html),
+                BlockPatch::Remove { index } => {
+                    old.remove(*index);
+                }
+                BlockPatch::SetLines { index, lines } => old[*index].lines = lines.clone(),
+            }
+        }
+
+        ids_and_lines(&old)
+    }
+
+    fn ids_and_lines(blocks: &[Block]) -> Vec<(String, Vec<String>)> {
+        blocks
+            .iter()
+            .map(|b| (b.id.clone(), b.lines.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn ids() {
+        let blocks = blocks_of("# Title\n\n***\n\nText\n\n***");
+
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[0].html.starts_with(&format!(
+            r#"<h1 data-source-line="1" id="title" data-block="{}">"#,
+            blocks[0].id
+        )));
+        // equal blocks get different ids
+        assert_eq!(blocks[3].id, format!("{}-1", blocks[1].id));
+
+        // moved blocks keep their id
+        let moved = blocks_of("\n\n# Title");
+        assert_eq!(moved[0].id, blocks[0].id);
+        assert_eq!(moved[0].lines, ["3"]);
+    }
+
+    #[test]
+    fn patches() {
+        let old = blocks_of("# Title\n\nA\n\nB\n\n- C\n- D\n\nE");
+        let new = blocks_of("# Title\n\nNew\n\nA\n\nB changed\n\n- C\n- D\n\nF\n\nG");
+
+        let patches = diff(&old, &new);
+
+        assert_eq!(apply(old.clone(), &patches, &new), ids_and_lines(&new));
+        // blocks which only moved aren't sent again
+        assert_eq!(
+            patches
+                .iter()
+                .filter(|p| matches!(p, BlockPatch::SetLines { .. }))
+                .count(),
+            2
+        );
+        assert!(!patches.iter().any(|p| match p {
+            BlockPatch::Insert { html, .. } | BlockPatch::Replace { html, .. } =>
+                html.contains(">A<") || html.contains(">C<"),
+            _ => false,
+        }));
+
+        assert_eq!(
+            apply(new.clone(), &diff(&new, &old), &old),
+            ids_and_lines(&old)
+        );
+        assert_eq!(diff(&old, &old), []);
+        assert_eq!(
+            apply(Vec::new(), &diff(&[], &new), &new),
+            ids_and_lines(&new)
+        );
+    }
+}
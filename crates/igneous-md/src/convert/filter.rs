@@ -0,0 +1,320 @@
+//! External filters, which transform the html using a local command. See: [ExternalFilter]
+//!
+//! Similar to pandoc filters, a filter gets the document on stdin and writes the modified html to
+//! stdout. The document is passed either as html or as a JSON object of the form:
+//!
+//! ```json
+//! {
+//!     "path": "/path/to/file.md",
+//!     "markdown": "# Title\n...",
+//!     "outline": [{ "level": 1, "text": "Title", "slug": "title", "children": [] }],
+//!     "html": "<html><head></head><body><main>...</main></body></html>"
+//! }
+//! ```
+use kuchikiki::{NodeRef, traits::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    io::{self, Read, Write},
+    process::{Command, ExitStatus, Stdio},
+    str::FromStr,
+    string::FromUtf8Error,
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+use super::{
+    outline,
+    transform::{Context, Transform},
+};
+
+/// Errors that can occur while running an [ExternalFilter]
+#[derive(Debug, Error)]
+pub enum FilterError {
+    /// The command couldn't be started or communicated with
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The command exited unsuccessfully
+    #[error("The command failed ({status}): {stderr}")]
+    Failed {
+        /// Exit status of the command
+        status: ExitStatus,
+        /// What the command wrote to stderr
+        stderr: String,
+    },
+    /// The command didn't write valid UTF-8 to stdout
+    #[error("The output is not valid UTF-8: {0}")]
+    InvalidOutput(#[from] FromUtf8Error),
+    /// The command didn't exit in time and was killed
+    #[error("The command didn't finish within {0:?}")]
+    TimedOut(Duration),
+    /// The html written by the command has no `<main>` element
+    #[error("The output has no <main> element")]
+    MissingMain,
+}
+
+/// How long an [ExternalFilter] may run by default, before it is killed
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What an [ExternalFilter] gets on stdin
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterInput {
+    /// The html of the document
+    Html,
+    /// A JSON description of the document, see [self]
+    Json,
+}
+
+/// A user-configured command, which is applied to the html of a document as a [Transform]
+///
+/// The command is run using `sh -c`, so it may contain arguments. If it fails, takes longer than
+/// its `timeout` or writes html without a `<main>` element, the html is left unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExternalFilter {
+    /// The command to run
+    pub command: String,
+    /// What the command gets on stdin
+    pub input: FilterInput,
+    /// How long the command may run, before it is killed
+    pub timeout: Duration,
+}
+
+impl FromStr for ExternalFilter {
+    type Err = Infallible;
+
+    /// Parse a filter, commands prefixed with `json:` get [FilterInput::Json]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix("json:") {
+            Some(command) => Self {
+                command: command.to_string(),
+                input: FilterInput::Json,
+                timeout: DEFAULT_TIMEOUT,
+            },
+            None => Self {
+                command: s.to_string(),
+                input: FilterInput::Html,
+                timeout: DEFAULT_TIMEOUT,
+            },
+        })
+    }
+}
+
+impl ExternalFilter {
+    /// Run the command, writing `input` to its stdin and returning its stdout
+    ///
+    /// The command is killed if it doesn't exit within the `timeout`.
+    pub fn run(&self, input: Vec<u8>) -> Result<String, FilterError> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("Stdin is piped.");
+
+        // Write from another thread, so a command writing a lot before reading all of its input
+        // cannot deadlock
+        let writer = thread::spawn(move || stdin.write_all(&input));
+
+        // Outputs are read from other threads as well, so the command can't block on a full pipe
+        // while it is waited for
+        let stdout = read_all(child.stdout.take().expect("Stdout is piped."));
+        let stderr = read_all(child.stderr.take().expect("Stderr is piped."));
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                // The command may have exited in the meantime, which is fine
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(FilterError::TimedOut(self.timeout));
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        // A command may not read its input at all, which is fine
+        if let Ok(Err(e)) = writer.join()
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            return Err(e.into());
+        }
+
+        let stdout = stdout.join().expect("Reading doesn't panic.")?;
+        let stderr = stderr.join().expect("Reading doesn't panic.")?;
+
+        if !status.success() {
+            return Err(FilterError::Failed {
+                status,
+                stderr: String::from_utf8_lossy(&stderr).trim().to_string(),
+            });
+        }
+
+        Ok(String::from_utf8(stdout)?)
+    }
+
+    /// Run the command on the `document`, returning the document it wrote
+    fn filter(&self, document: &NodeRef, context: &Context) -> Result<NodeRef, FilterError> {
+        let html = self.run(self.input(document, context))?;
+        let new_document = kuchikiki::parse_html().one(html);
+
+        // Everything after the filters expects the `<main>` created by the pipeline
+        if new_document.select_first("main").is_err() {
+            return Err(FilterError::MissingMain);
+        }
+
+        Ok(new_document)
+    }
+
+    /// Create the input for the command from the `document`
+    fn input(&self, document: &NodeRef, context: &Context) -> Vec<u8> {
+        let mut html = Vec::new();
+        document
+            .serialize(&mut html)
+            .expect("Serialization should never fail, if it does there is a bug.");
+
+        match self.input {
+            FilterInput::Html => html,
+            FilterInput::Json => serde_json::json!({
+                "path": context.md_path,
+                "markdown": context.md,
+                "outline": outline(document),
+                "html": String::from_utf8_lossy(&html),
+            })
+            .to_string()
+            .into_bytes(),
+        }
+    }
+}
+
+impl Transform for ExternalFilter {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn apply(&self, document: &NodeRef, context: &Context) {
+        let new_document = match self.filter(document, context) {
+            Ok(new_document) => new_document,
+            Err(e) => {
+                log::warn!("External filter `{}` failed: {e}", self.command);
+                return;
+            }
+        };
+
+        document.children().for_each(|c| c.detach());
+
+        for child in new_document.children() {
+            document.append(child);
+        }
+    }
+}
+
+/// Read all of `reader` on another thread
+fn read_all(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DEFAULT_TIMEOUT, ExternalFilter, FilterError, FilterInput};
+    use crate::convert::transform::{Context, MainWrapper, Pipeline};
+    use std::{
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    fn run(filters: &[&str]) -> String {
+        let mut pipeline = Pipeline::default();
+        pipeline.push(MainWrapper);
+
+        for filter in filters {
+            pipeline.push(filter.parse::<ExternalFilter>().unwrap());
+        }
+
+        pipeline.run(
+            "<h1>Title</h1>".to_string(),
+            &Context {
+                md_path: Path::new("/docs/test.md"),
+                md: "# Title",
+            },
+        )
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "json:./filter.py --flag".parse::<ExternalFilter>().unwrap(),
+            ExternalFilter {
+                command: "./filter.py --flag".to_string(),
+                input: FilterInput::Json,
+                timeout: DEFAULT_TIMEOUT,
+            }
+        );
+        assert_eq!(
+            "sed s/a/b/".parse::<ExternalFilter>().unwrap().input,
+            FilterInput::Html
+        );
+    }
+
+    #[test]
+    fn html() {
+        assert!(run(&["sed s/Title/Changed/"]).contains("<main><h1>Changed</h1></main>"));
+    }
+
+    #[test]
+    fn json() {
+        let html = run(&[
+            r#"json:grep -o '"path":"[^"]*"' | sed 's|"path":\(.*\)|<main><p>\1</p></main>|'"#,
+        ]);
+
+        assert!(html.contains(r#"<p>"/docs/test.md"</p>"#));
+    }
+
+    #[test]
+    fn failing() {
+        // failing filters leave the html unchanged
+        assert!(run(&["exit 1", "sed s/Title/Changed/"]).contains("<h1>Changed</h1>"));
+
+        let filter: ExternalFilter = "echo error >&2; exit 3".parse().unwrap();
+        let Err(FilterError::Failed { stderr, .. }) = filter.run(Vec::new()) else {
+            panic!("The command should fail.");
+        };
+        assert_eq!(stderr, "error");
+    }
+
+    #[test]
+    fn missing_main() {
+        // output without a `<main>` leaves the html unchanged
+        for filter in ["true", "echo '<p>Fragment</p>'", "sed s/main/div/g"] {
+            assert!(run(&[filter]).contains("<main><h1>Title</h1></main>"));
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let filter = ExternalFilter {
+            timeout: Duration::from_millis(100),
+            .."sleep 10".parse::<ExternalFilter>().unwrap()
+        };
+
+        let start = Instant::now();
+        assert!(matches!(
+            filter.run(Vec::new()),
+            Err(FilterError::TimedOut(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,140 @@
+//! Parsing of YAML and TOML front matter. See: [FrontMatter]
+//!
+//! The front matter itself is removed from the html by [super::md_to_html], this module only
+//! extracts the metadata it contains.
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Metadata from the front matter at the start of a document
+///
+/// YAML front matter is fenced by `---` and TOML front matter by `+++`:
+///
+/// ```md
+/// ---
+/// title: My document
+/// css: github-markdown-light
+/// ---
+///
+/// # Content
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct FrontMatter(Map<String, Value>);
+
+impl FrontMatter {
+    /// Parse the front matter of the given markdown
+    ///
+    /// Returns [None] if there is no front matter or it is invalid.
+    pub fn parse(md: &str) -> Option<Self> {
+        let (fence, content) = extract(md)?;
+
+        let result = match fence {
+            "---" => serde_yaml::from_str::<Option<Map<String, Value>>>(content)
+                .map(Option::unwrap_or_default)
+                .map_err(|e| e.to_string()),
+            _ => toml::from_str::<Map<String, Value>>(content).map_err(|e| e.to_string()),
+        };
+
+        match result {
+            Ok(map) => Some(Self(map)),
+            Err(e) => {
+                log::warn!("Invalid front matter: {e}");
+                None
+            }
+        }
+    }
+
+    /// The `title` of the document
+    pub fn title(&self) -> Option<&str> {
+        self.0.get("title").and_then(Value::as_str)
+    }
+
+    /// The stylesheet the document should initially be shown with
+    ///
+    /// Set via either the `css` or `theme` key.
+    pub fn css(&self) -> Option<&str> {
+        self.0
+            .get("css")
+            .or_else(|| self.0.get("theme"))
+            .and_then(Value::as_str)
+    }
+}
+
+/// Whether the `md` starts with front matter, including its closing fence
+pub fn exists(md: &str) -> bool {
+    extract(md).is_some()
+}
+
+/// Find the front matter at the start of the `md`
+///
+/// Returns the fence used and the content between the fences. Like markdown-rs, only spaces and
+/// tabs may follow the fences.
+fn extract(md: &str) -> Option<(&'static str, &str)> {
+    let fence = ["---", "+++"]
+        .into_iter()
+        .find(|fence| md.lines().next().is_some_and(|l| trim_fence(l) == *fence))?;
+
+    let start = md.find('\n')? + 1;
+    let mut end = start;
+
+    for line in md[start..].split_inclusive('\n') {
+        if trim_fence(line) == fence {
+            return Some((fence, &md[start..end]));
+        }
+
+        end += line.len();
+    }
+
+    None
+}
+
+/// Remove the whitespace allowed after a fence from the `line`
+fn trim_fence(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+        .trim_end_matches([' ', '\t'])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FrontMatter, exists};
+
+    #[test]
+    fn yaml() {
+        let front_matter = FrontMatter::parse(
+            "---\ntitle: My document\ncss: github-markdown-light\ntags: [a, b]\n---\n# Content",
+        )
+        .expect("Front matter is valid.");
+
+        assert_eq!(front_matter.title(), Some("My document"));
+        assert_eq!(front_matter.css(), Some("github-markdown-light"));
+        assert_eq!(front_matter.0["tags"], serde_json::json!(["a", "b"]));
+    }
+
+    #[test]
+    fn toml() {
+        let front_matter = FrontMatter::parse("+++\ntitle = \"Toml\"\ntheme = \"dark\"\n+++\n")
+            .expect("Front matter is valid.");
+
+        assert_eq!(front_matter.title(), Some("Toml"));
+        assert_eq!(front_matter.css(), Some("dark"));
+    }
+
+    #[test]
+    fn empty_and_missing() {
+        assert_eq!(
+            FrontMatter::parse("---\n---\n"),
+            Some(FrontMatter::default())
+        );
+        assert_eq!(FrontMatter::parse("# No front matter\n---\n"), None);
+        assert_eq!(FrontMatter::parse("---\ntitle: unclosed\n"), None);
+        assert_eq!(
+            FrontMatter::parse("\n---\ntitle: not at start\n---\n"),
+            None
+        );
+        assert_eq!(FrontMatter::parse("---\n[invalid: yaml\n---\n"), None);
+
+        // only spaces and tabs may follow the fences
+        assert!(exists("---  \ntitle: spaces\n---\t\r\n"));
+        assert!(!exists("---\ntitle: unclosed\n---\u{a0}\n"));
+    }
+}
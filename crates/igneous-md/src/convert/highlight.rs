@@ -0,0 +1,182 @@
+//! Server-side syntax highlighting of code blocks. See: [highlight]
+//!
+//! The output uses the same classes as [highlight.js](https://highlightjs.org/), so that the
+//! stylesheets in the `css/hljs/` config dir work for code highlighted here as well as for code
+//! highlighted by the viewer.
+use std::sync::LazyLock;
+use syntect::{
+    parsing::{ParseState, Scope, ScopeStack, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+/// Mapping of TextMate scopes to highlight.js classes
+///
+/// The first scope, which is a prefix of the scope of a token, determines the class. Therefore,
+/// more specific scopes have to come before less specific ones.
+static HLJS_CLASSES: LazyLock<Vec<(Scope, &'static str)>> = LazyLock::new(|| {
+    [
+        ("comment", "hljs-comment"),
+        ("string.regexp", "hljs-regexp"),
+        ("string", "hljs-string"),
+        ("constant.numeric", "hljs-number"),
+        ("constant.character.escape", "hljs-char escape_"),
+        ("constant.language", "hljs-literal"),
+        ("constant.other.symbol", "hljs-symbol"),
+        ("keyword.operator", "hljs-operator"),
+        ("keyword", "hljs-keyword"),
+        ("storage.type", "hljs-keyword"),
+        ("storage.modifier", "hljs-keyword"),
+        ("support.function", "hljs-built_in"),
+        ("support.type", "hljs-type"),
+        ("support.class", "hljs-title class_"),
+        ("entity.name.function", "hljs-title function_"),
+        ("entity.name.section", "hljs-section"),
+        ("entity.name.tag", "hljs-name"),
+        ("entity.name", "hljs-title class_"),
+        ("entity.other.attribute-name", "hljs-attr"),
+        (
+            "entity.other.inherited-class",
+            "hljs-title class_ inherited__",
+        ),
+        ("variable.parameter", "hljs-params"),
+        ("variable.language", "hljs-variable language_"),
+        ("meta.preprocessor", "hljs-meta"),
+        ("meta.annotation", "hljs-meta"),
+        ("markup.heading", "hljs-section"),
+        ("markup.bold", "hljs-strong"),
+        ("markup.italic", "hljs-emphasis"),
+        ("markup.inserted", "hljs-addition"),
+        ("markup.deleted", "hljs-deletion"),
+        ("markup.quote", "hljs-quote"),
+        ("markup.list", "hljs-bullet"),
+        ("markup.raw", "hljs-code"),
+        ("markup.underline.link", "hljs-link"),
+    ]
+    .into_iter()
+    .map(|(scope, class)| (Scope::new(scope).expect("Scopes are hard-coded."), class))
+    .collect()
+});
+
+/// A piece of highlighted code
+///
+/// All of [Self::text] should be styled using [Self::class]. If there is no class, the text is
+/// plain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// The highlight.js class(es) of the text
+    pub class: Option<&'static str>,
+    /// The code itself
+    pub text: String,
+}
+
+/// Highlight the given `code`, written in the language `lang`
+///
+/// `lang` is matched against the names and file extensions of the known languages, the same way
+/// the `language-*` class of a code block would be.
+///
+/// Returns [None] if the language is unknown or the code cannot be parsed.
+pub fn highlight(code: &str, lang: &str) -> Option<Vec<Token>> {
+    let syntax = SYNTAX_SET.find_syntax_by_token(lang)?;
+
+    let mut parse_state = ParseState::new(syntax);
+    let mut scope_stack = ScopeStack::new();
+    let mut tokens: Vec<Token> = Vec::new();
+
+    for line in LinesWithEndings::from(code) {
+        let ops = match parse_state.parse_line(line, &SYNTAX_SET) {
+            Ok(ops) => ops,
+            Err(e) => {
+                log::warn!("Failed to highlight code block ({lang}): {e}");
+                return None;
+            }
+        };
+
+        let mut last_index = 0;
+
+        for (index, op) in ops {
+            push_token(
+                &mut tokens,
+                hljs_class(&scope_stack),
+                &line[last_index..index],
+            );
+            last_index = index;
+
+            scope_stack
+                .apply(&op)
+                .inspect_err(|e| log::warn!("Failed to highlight code block ({lang}): {e}"))
+                .ok()?;
+        }
+
+        push_token(&mut tokens, hljs_class(&scope_stack), &line[last_index..]);
+    }
+
+    Some(tokens)
+}
+
+/// Add the text to `tokens`, merging it into the last token if they share the same class
+fn push_token(tokens: &mut Vec<Token>, class: Option<&'static str>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    match tokens.last_mut() {
+        Some(last) if last.class == class => last.text.push_str(text),
+        _ => tokens.push(Token {
+            class,
+            text: text.to_string(),
+        }),
+    }
+}
+
+/// Get the highlight.js class of the innermost scope in `stack`, which has one
+fn hljs_class(stack: &ScopeStack) -> Option<&'static str> {
+    stack.as_slice().iter().rev().find_map(|scope| {
+        HLJS_CLASSES
+            .iter()
+            .find(|(prefix, _)| prefix.is_prefix_of(*scope))
+            .map(|(_, class)| *class)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Token, highlight};
+
+    #[test]
+    fn unknown_language() {
+        assert!(highlight("some code", "not-a-language").is_none());
+    }
+
+    #[test]
+    fn rust() {
+        let code = "// comment\nfn main() {\n    let x = \"text\";\n}\n";
+        let tokens = highlight(code, "rust").expect("Rust is a known language.");
+
+        // highlighting must never change the code itself
+        assert_eq!(
+            tokens.iter().map(|t| t.text.as_str()).collect::<String>(),
+            code
+        );
+
+        let class_of = |text: &str| {
+            tokens
+                .iter()
+                .find(|t| t.text.contains(text))
+                .and_then(|t| t.class)
+        };
+
+        assert_eq!(class_of("// comment"), Some("hljs-comment"));
+        assert_eq!(class_of("fn"), Some("hljs-keyword"));
+        assert_eq!(class_of("main"), Some("hljs-title function_"));
+        assert_eq!(class_of("\"text\""), Some("hljs-string"));
+
+        // adjacent tokens with the same class are merged
+        assert!(
+            tokens
+                .windows(2)
+                .all(|w| !matches!(w, [Token { class: a, .. }, Token { class: b, .. }] if a == b))
+        );
+    }
+}
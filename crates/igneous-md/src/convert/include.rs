@@ -0,0 +1,407 @@
+//! Transclusion of other markdown files. See: [expand]
+//!
+//! Files are included using either of the following directives, each on its own line:
+//!
+//! ```md
+//! <!-- include: ../shared/footer.md -->
+//!
+//! {{#include ../shared/footer.md}}
+//! ```
+//!
+//! Directives within code blocks and code spans are left as they are. In safe mode (see
+//! [ConvertOptions::safe]) only files within the directory of the document can be included.
+use markdown::mdast::Node;
+use regex::{Captures, Regex};
+use std::{
+    collections::HashSet,
+    fs, io, iter,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::SystemTime,
+};
+use thiserror::Error;
+
+use super::{ConvertOptions, markdown_options};
+use crate::paths;
+
+/// How deeply includes may be nested
+pub const MAX_DEPTH: usize = 8;
+
+static INCLUDE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?m)^[ \t]*(?:<!--[ \t]*include:[ \t]*(.+?)[ \t]*-->|\{\{#include[ \t]+(.+?)[ \t]*\}\})[ \t]*$",
+    )
+    .expect("Regex is hard-coded.")
+});
+
+/// Errors that can occur while including a file
+#[derive(Debug, Error)]
+pub enum IncludeError {
+    /// The file includes itself, either directly or via other files
+    #[error("The file is already being included")]
+    Cycle,
+    /// Includes are nested more deeply than [MAX_DEPTH]
+    #[error("Includes are nested more than {MAX_DEPTH} levels deep")]
+    TooDeep,
+    /// The file is outside the directory of the document, which isn't allowed in safe mode
+    #[error("Only files within the directory of the document can be included in safe mode")]
+    OutsideDirectory,
+    /// The file couldn't be read
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// The result of [expand]
+#[derive(Debug, Default)]
+pub struct Expansion {
+    /// The markdown with all includes replaced by the content of the included files
+    pub md: String,
+    /// All files which were included, with the time they were last modified
+    ///
+    /// This also contains files which couldn't be read, so that the including file can be
+    /// re-rendered once they can be.
+    pub files: Vec<(PathBuf, Option<SystemTime>)>,
+    /// For each line of [Self::md], the line of the original markdown it comes from
+    ///
+    /// Lines of included files belong to the line of their include directive.
+    lines: Vec<usize>,
+}
+
+impl Expansion {
+    /// Map a line of [Self::md] to the line of the original markdown it comes from
+    ///
+    /// Lines start at `1`.
+    pub fn source_line(&self, line: usize) -> usize {
+        line.checked_sub(1)
+            .and_then(|i| self.lines.get(i))
+            .copied()
+            .unwrap_or(line)
+    }
+}
+
+/// Replace all include directives in the `md` with the content of the included files
+///
+/// `path` is the location of the `md`, which the paths of the included files are relative to.
+/// Included files may include further files, relative to their own location. If
+/// [ConvertOptions::safe] is set, they have to be within the directory of `path`.
+///
+/// Files that cannot be included are replaced by an alert describing the error.
+pub fn expand(md: &str, path: &Path, options: &ConvertOptions) -> Expansion {
+    let mut includer = Includer {
+        root: options.safe.then(|| paths::document_dir(path)),
+        stack: vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())],
+        files: Vec::new(),
+    };
+
+    let mut expanded = String::with_capacity(md.len());
+    let mut lines = Vec::new();
+
+    // Expand line by line, to keep track of where the lines of the result come from
+    for (i, line) in includer.expand_lines(md, path).into_iter().enumerate() {
+        lines.extend(iter::repeat_n(i + 1, line.split_inclusive('\n').count()));
+        expanded.push_str(&line);
+    }
+
+    Expansion {
+        md: expanded,
+        files: includer.files,
+        lines,
+    }
+}
+
+/// State of an [expand]
+struct Includer {
+    /// The directory files have to be within, if any
+    root: Option<PathBuf>,
+    /// The canonical paths of the files currently being included
+    stack: Vec<PathBuf>,
+    /// See [Expansion::files]
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Includer {
+    /// Expand the includes of every line of the `md` of the file at `path`, returning the lines
+    fn expand_lines(&mut self, md: &str, path: &Path) -> Vec<String> {
+        let code = code_lines(md);
+
+        md.split_inclusive('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                if code.contains(&(i + 1)) {
+                    line.to_string()
+                } else {
+                    self.expand_line(line, path)
+                }
+            })
+            .collect()
+    }
+
+    /// Replace the include directive on the `line` of the file at `path`, if there is one
+    fn expand_line(&mut self, line: &str, path: &Path) -> String {
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        INCLUDE_REGEX
+            .replace_all(line, |captures: &Captures| {
+                let target = captures
+                    .get(1)
+                    .or_else(|| captures.get(2))
+                    .expect("One of the groups always matches.")
+                    .as_str();
+
+                match self.include_file(&dir.join(target)) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        log::warn!("Failed to include `{target}` in {}: {e}", path.display());
+
+                        format!("> [!CAUTION]\n> Failed to include `{target}`: {e}")
+                    }
+                }
+            })
+            .into_owned()
+    }
+
+    /// Read the file at `path` and expand its includes
+    fn include_file(&mut self, path: &Path) -> Result<String, IncludeError> {
+        if self.stack.len() > MAX_DEPTH {
+            return Err(IncludeError::TooDeep);
+        }
+
+        // Checked before accessing the file, so it isn't even revealed whether it exists
+        if let Some(root) = &self.root
+            && !paths::normalize(path).starts_with(root)
+        {
+            return Err(IncludeError::OutsideDirectory);
+        }
+
+        // Get the time before reading, so changes made while reading aren't missed
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        self.files.push((path.to_path_buf(), modified));
+
+        let canonical = path.canonicalize()?;
+
+        // Symlinks may still point outside the directory
+        if let Some(root) = &self.root
+            && !canonical.starts_with(root)
+        {
+            return Err(IncludeError::OutsideDirectory);
+        }
+
+        if self.stack.contains(&canonical) {
+            return Err(IncludeError::Cycle);
+        }
+
+        let content = fs::read_to_string(path)?;
+
+        self.stack.push(canonical);
+        let expanded = self
+            .expand_lines(content.trim_end_matches('\n'), path)
+            .concat();
+        self.stack.pop();
+
+        Ok(expanded)
+    }
+}
+
+/// Get the lines of the `md` within code blocks, code spans or math, starting at `1`
+fn code_lines(md: &str) -> HashSet<usize> {
+    let mut lines = HashSet::new();
+
+    // Only MDX syntax can fail to parse, which isn't enabled
+    let Ok(root) = markdown::to_mdast(md, &markdown_options(md).parse) else {
+        return lines;
+    };
+
+    let mut nodes = vec![&root];
+
+    while let Some(node) = nodes.pop() {
+        match node {
+            Node::Code(_) | Node::InlineCode(_) | Node::Math(_) | Node::InlineMath(_) => {
+                if let Some(position) = node.position() {
+                    lines.extend(position.start.line..=position.end.line);
+                }
+            }
+            _ => nodes.extend(node.children().into_iter().flatten()),
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MAX_DEPTH, expand};
+    use crate::convert::ConvertOptions;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Create an empty directory for a test, which is removed once it is dropped
+    fn test_dir() -> TempDir {
+        let dir = tempfile::tempdir().expect("Failed to create test dir.");
+        fs::create_dir(dir.path().join("shared")).expect("Failed to create test dir.");
+
+        dir
+    }
+
+    #[test]
+    fn nested() {
+        let temp = test_dir();
+        let dir = temp.path();
+
+        fs::write(
+            dir.join("shared/footer.md"),
+            "Footer\n{{#include ./license.md}}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("shared/license.md"), "License\n").unwrap();
+
+        let expansion = expand(
+            "# Title\n<!-- include: shared/footer.md -->\nEnd",
+            &dir.join("index.md"),
+            &ConvertOptions::default(),
+        );
+
+        assert_eq!(expansion.md, "# Title\nFooter\nLicense\nEnd");
+        assert_eq!(
+            (1..=5)
+                .map(|l| expansion.source_line(l))
+                .collect::<Vec<_>>(),
+            [1, 2, 2, 3, 5]
+        );
+        assert_eq!(
+            expansion
+                .files
+                .iter()
+                .map(|(p, m)| (p.clone(), m.is_some()))
+                .collect::<Vec<_>>(),
+            [
+                (dir.join("shared/footer.md"), true),
+                (dir.join("shared/./license.md"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let temp = test_dir();
+        let dir = temp.path();
+
+        fs::write(dir.join("a.md"), "A\n<!-- include: b.md -->\n").unwrap();
+        fs::write(dir.join("b.md"), "B\n<!-- include: a.md -->\n").unwrap();
+        fs::write(dir.join("self.md"), "<!-- include: self.md -->").unwrap();
+
+        let cycle = expand(
+            "<!-- include: a.md -->",
+            &dir.join("index.md"),
+            &ConvertOptions::default(),
+        );
+        assert_eq!(
+            cycle.md,
+            "A\nB\n> [!CAUTION]\n> Failed to include `a.md`: The file is already being included"
+        );
+
+        let missing = expand(
+            "{{#include missing.md}}",
+            &dir.join("index.md"),
+            &ConvertOptions::default(),
+        );
+        assert!(
+            missing
+                .md
+                .starts_with("> [!CAUTION]\n> Failed to include `missing.md`: ")
+        );
+        // missing files are still tracked, so they can be included once they exist
+        assert_eq!(missing.files, [(dir.join("missing.md"), None)]);
+
+        // a file including itself is a cycle as well, even via a different path
+        let own = expand(
+            "<!-- include: shared/../self.md -->",
+            &dir.join("self.md"),
+            &ConvertOptions::default(),
+        );
+        assert!(own.md.contains("The file is already being included"));
+
+        // files which don't form a cycle are stopped by the depth limit
+        for n in 0..=MAX_DEPTH {
+            fs::write(
+                dir.join(format!("{n}.md")),
+                format!("<!-- include: {}.md -->", n + 1),
+            )
+            .unwrap();
+        }
+
+        let too_deep = expand(
+            "<!-- include: 0.md -->",
+            &dir.join("index.md"),
+            &ConvertOptions::default(),
+        );
+        assert!(too_deep.md.contains(&format!(
+            "Includes are nested more than {MAX_DEPTH} levels deep"
+        )));
+    }
+
+    #[test]
+    fn code() {
+        let temp = test_dir();
+        let dir = temp.path();
+
+        fs::write(dir.join("a.md"), "A").unwrap();
+
+        let md = concat!(
+            "```md\n<!-- include: a.md -->\n```\n\n",
+            "    {{#include a.md}}\n\n",
+            "`x\n{{#include a.md}}\n`\n\n",
+            "{{#include a.md}}",
+        );
+        let expansion = expand(md, &dir.join("index.md"), &ConvertOptions::default());
+
+        // only the directive outside of code is expanded
+        assert_eq!(expansion.md, md.replace("\n\n{{#include a.md}}", "\n\nA"));
+        assert_eq!(expansion.files.len(), 1);
+    }
+
+    #[test]
+    fn safe() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let safe = ConvertOptions {
+            safe: true,
+            ..Default::default()
+        };
+
+        fs::create_dir_all(dir.join("docs/sub")).unwrap();
+        fs::write(dir.join("docs/sub/a.md"), "A\n{{#include ../b.md}}").unwrap();
+        fs::write(dir.join("docs/b.md"), "B").unwrap();
+        fs::write(dir.join("secret.md"), "Secret").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.md"), dir.join("docs/link.md")).unwrap();
+
+        let path = dir.join("docs/index.md");
+
+        // files within the directory of the document can be included
+        assert_eq!(expand("{{#include sub/a.md}}", &path, &safe).md, "A\nB");
+
+        for target in [
+            "../secret.md".to_string(),
+            dir.join("secret.md").display().to_string(),
+            "sub/../../secret.md".to_string(),
+            "link.md".to_string(),
+        ] {
+            let expansion = expand(&format!("{{{{#include {target}}}}}"), &path, &safe);
+
+            assert!(
+                expansion.md.contains("can be included in safe mode"),
+                "{target} was included"
+            );
+        }
+
+        // without safe mode, they are included
+        assert_eq!(
+            expand(
+                "{{#include ../secret.md}}",
+                &path,
+                &ConvertOptions::default()
+            )
+            .md,
+            "Secret"
+        );
+    }
+}
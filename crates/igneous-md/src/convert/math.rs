@@ -0,0 +1,912 @@
+//! Conversion of TeX math to [MathML](https://developer.mozilla.org/en-US/docs/Web/MathML). See:
+//! [tex_to_mathml]
+//!
+//! Only the commonly used subset of TeX found in markdown documents is supported. Anything else
+//! results in a [MathError], which [super::transform::Math] turns into an inline error element.
+use std::iter::Peekable;
+use thiserror::Error;
+
+/// Errors that can occur while converting TeX to MathML
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MathError {
+    /// A command which isn't supported
+    #[error("Unknown command `\\{0}`")]
+    UnknownCommand(String),
+    /// An environment which isn't supported
+    #[error("Unknown environment `{0}`")]
+    UnknownEnvironment(String),
+    /// A `{` without a matching `}`
+    #[error("Missing closing `}}`")]
+    UnclosedGroup,
+    /// A token which isn't allowed in the given position
+    #[error("Unexpected `{0}`")]
+    Unexpected(String),
+    /// A command is missing one of its arguments
+    #[error("Missing argument for `\\{0}`")]
+    MissingArgument(String),
+    /// `\left` or `\begin` without a matching `\right` or `\end`
+    #[error("Missing `\\{0}`")]
+    Unclosed(&'static str),
+    /// `\end` doesn't match the `\begin`
+    #[error("`\\begin{{{0}}}` ended by `\\end{{{1}}}`")]
+    MismatchedEnvironment(String, String),
+    /// An atom has two superscripts or two subscripts
+    #[error("Double {0}script")]
+    DoubleScript(&'static str),
+    /// Groups, arguments or environments are nested deeper than [MAX_DEPTH]
+    #[error("Nested too deeply")]
+    TooDeep,
+}
+
+/// Maximum nesting depth of atoms, so deeply nested input can't overflow the stack
+pub const MAX_DEPTH: usize = 100;
+
+/// Convert the given `tex` to a MathML `<math>` element
+///
+/// If `display` is `true` the math is rendered as a block, otherwise it is rendered inline.
+///
+/// The original `tex` is kept as an annotation, so it can be copied from the rendered math.
+pub fn tex_to_mathml(tex: &str, display: bool) -> Result<String, MathError> {
+    let mut parser = Parser {
+        tokens: tokenize(tex).into_iter().peekable(),
+        display,
+        variant: None,
+        depth: 0,
+    };
+
+    let content = parser.parse_expression(false)?;
+
+    if let Some(token) = parser.tokens.next() {
+        return Err(MathError::Unexpected(token.to_string()));
+    }
+
+    Ok(format!(
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="{}"><semantics><mrow>{content}</mrow><annotation encoding="application/x-tex">{}</annotation></semantics></math>"#,
+        if display { "block" } else { "inline" },
+        escape(tex.trim()),
+    ))
+}
+
+/// The tokens TeX math consists of
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A command such as `\frac`, without the leading `\`
+    Command(String),
+    /// Any character without special meaning
+    Char(char),
+    /// Whitespace, which is only relevant in text
+    Space,
+    /// `{`
+    Open,
+    /// `}`
+    Close,
+    /// `^`
+    Sup,
+    /// `_`
+    Sub,
+    /// `'`
+    Prime,
+    /// `&`, separating the columns of a table
+    Align,
+    /// `\\`, separating the rows of a table
+    NewRow,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Command(name) => write!(f, "\\{name}"),
+            Token::Char(c) => write!(f, "{c}"),
+            Token::Space => write!(f, " "),
+            Token::Open => write!(f, "{{"),
+            Token::Close => write!(f, "}}"),
+            Token::Sup => write!(f, "^"),
+            Token::Sub => write!(f, "_"),
+            Token::Prime => write!(f, "'"),
+            Token::Align => write!(f, "&"),
+            Token::NewRow => write!(f, "\\\\"),
+        }
+    }
+}
+
+/// Split the `tex` into [Token]s
+fn tokenize(tex: &str) -> Vec<Token> {
+    let mut chars = tex.chars().peekable();
+    let mut tokens = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => match chars.next() {
+                Some('\\') => Token::NewRow,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut name = c.to_string();
+
+                    while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                        name.push(c);
+                    }
+
+                    Token::Command(name)
+                }
+                Some(c) => Token::Command(c.to_string()),
+                None => Token::Char('\\'),
+            },
+            c if c.is_whitespace() => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+                Token::Space
+            }
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '\'' => Token::Prime,
+            '&' => Token::Align,
+            '%' => {
+                // Comments last until the end of the line
+                while chars.next_if(|c| *c != '\n').is_some() {}
+
+                continue;
+            }
+            c => Token::Char(c),
+        };
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// A parsed atom, ready to have sub- and superscripts attached
+struct Atom {
+    /// The MathML of the atom
+    mathml: String,
+    /// If scripts should be placed above and below the atom in display mode, like for `\sum`
+    limits: bool,
+}
+
+impl From<String> for Atom {
+    fn from(mathml: String) -> Self {
+        Self {
+            mathml,
+            limits: false,
+        }
+    }
+}
+
+/// Recursive descent parser turning [Token]s into MathML
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    /// If the math is rendered as a block
+    display: bool,
+    /// The `mathvariant` set by commands like `\mathbf`
+    variant: Option<&'static str>,
+    /// Number of atoms currently being parsed, see [MAX_DEPTH]
+    depth: usize,
+}
+
+impl Parser {
+    /// Skip over any whitespace
+    fn skip_spaces(&mut self) {
+        while self.tokens.next_if_eq(&Token::Space).is_some() {}
+    }
+
+    /// Parse atoms and their scripts until the end of the current group
+    ///
+    /// The group ends at the end of the input or at any of `}`, `&`, `\\`, `\right` and `\end`,
+    /// which are left for the caller to handle. If `optional` is `true`, the group also ends at `]`,
+    /// as it is the optional argument of a command.
+    fn parse_expression(&mut self, optional: bool) -> Result<String, MathError> {
+        let mut mathml = String::new();
+
+        loop {
+            self.skip_spaces();
+
+            match self.tokens.peek() {
+                None | Some(Token::Close | Token::Align | Token::NewRow) => break,
+                Some(Token::Char(']')) if optional => break,
+                Some(Token::Command(name)) if name == "right" || name == "end" => break,
+                _ => {}
+            }
+
+            let atom = match self.tokens.peek() {
+                Some(Token::Sup | Token::Sub | Token::Prime) => Atom::from(String::new()),
+                _ => self.parse_atom()?,
+            };
+
+            mathml.push_str(&self.parse_scripts(atom)?);
+        }
+
+        Ok(mathml)
+    }
+
+    /// Parse any sub- and superscripts following the `base`
+    fn parse_scripts(&mut self, base: Atom) -> Result<String, MathError> {
+        let (mut sub, mut sup) = (None, None);
+        let mut primes = String::new();
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Prime) => {
+                    self.tokens.next();
+                    primes.push('′');
+                }
+                Some(Token::Sub) => {
+                    self.tokens.next();
+
+                    if sub.is_some() {
+                        return Err(MathError::DoubleScript("sub"));
+                    }
+                    sub = Some(self.parse_argument("_")?);
+                }
+                Some(Token::Sup) => {
+                    self.tokens.next();
+
+                    if sup.is_some() {
+                        return Err(MathError::DoubleScript("super"));
+                    }
+                    sup = Some(self.parse_argument("^")?);
+                }
+                _ => break,
+            }
+        }
+
+        if !primes.is_empty() {
+            sup = Some(format!(
+                "<mrow><mo>{primes}</mo>{}</mrow>",
+                sup.unwrap_or_default()
+            ));
+        }
+
+        let limits = self.display && base.limits;
+        let base = if base.mathml.is_empty() {
+            "<mrow></mrow>".to_string()
+        } else {
+            base.mathml
+        };
+        let (under, over) = if limits {
+            ("munder", "mover")
+        } else {
+            ("msub", "msup")
+        };
+
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) if limits => {
+                format!("<munderover>{base}{sub}{sup}</munderover>")
+            }
+            (Some(sub), Some(sup)) => format!("<msubsup>{base}{sub}{sup}</msubsup>"),
+        })
+    }
+
+    /// Parse a single argument of a command, such as the `{a}` in `\frac{a}{b}`
+    ///
+    /// Arguments are either a group or a single token.
+    fn parse_argument(&mut self, command: &str) -> Result<String, MathError> {
+        self.skip_spaces();
+
+        match self.tokens.peek() {
+            None | Some(Token::Close | Token::Align | Token::NewRow) => {
+                Err(MathError::MissingArgument(command.to_string()))
+            }
+            _ => Ok(self.parse_atom()?.mathml),
+        }
+    }
+
+    /// Parse an argument as plain text, such as the `{text}` in `\text{text}`
+    fn parse_text_argument(&mut self, command: &str) -> Result<String, MathError> {
+        self.skip_spaces();
+
+        match self.tokens.next() {
+            Some(Token::Open) => {}
+            Some(Token::Char(c)) => return Ok(c.to_string()),
+            _ => return Err(MathError::MissingArgument(command.to_string())),
+        }
+
+        let mut text = String::new();
+        let mut depth = 0;
+
+        loop {
+            match self.tokens.next() {
+                None => return Err(MathError::UnclosedGroup),
+                Some(Token::Close) if depth == 0 => return Ok(text),
+                Some(Token::Close) => {
+                    depth -= 1;
+                    text.push('}');
+                }
+                Some(Token::Open) => {
+                    depth += 1;
+                    text.push('{');
+                }
+                // Escaped characters
+                Some(Token::Command(c)) if c.len() == 1 && !c.starts_with(char::is_alphabetic) => {
+                    text.push_str(&c);
+                }
+                Some(token) => text.push_str(&token.to_string()),
+            }
+        }
+    }
+
+    /// Parse a single atom, such as a letter, a number, a group or a command with its arguments
+    ///
+    /// Fails if atoms are nested deeper than [MAX_DEPTH].
+    fn parse_atom(&mut self) -> Result<Atom, MathError> {
+        if self.depth == MAX_DEPTH {
+            return Err(MathError::TooDeep);
+        }
+
+        self.depth += 1;
+        let atom = self.parse_nested_atom();
+        self.depth -= 1;
+
+        atom
+    }
+
+    /// Parse a single atom, without checking the nesting depth
+    fn parse_nested_atom(&mut self) -> Result<Atom, MathError> {
+        let Some(token) = self.tokens.next() else {
+            return Ok(Atom::from(String::new()));
+        };
+
+        let mathml = match token {
+            Token::Char(c) if c.is_numeric() => {
+                let mut number = c.to_string();
+
+                while let Some(Token::Char(c)) = self
+                    .tokens
+                    .next_if(|t| matches!(t, Token::Char(c) if c.is_numeric() || *c == '.'))
+                {
+                    number.push(c);
+                }
+
+                format!("<mn>{number}</mn>")
+            }
+            Token::Char(c) if c.is_alphabetic() => self.identifier(&c.to_string()),
+            Token::Char('~') => "<mspace width=\"0.333em\"></mspace>".to_string(),
+            Token::Char(c) => format!("<mo>{}</mo>", escape(&c.to_string())),
+            Token::Open => {
+                let content = self.parse_expression(false)?;
+
+                if self.tokens.next() != Some(Token::Close) {
+                    return Err(MathError::UnclosedGroup);
+                }
+
+                format!("<mrow>{content}</mrow>")
+            }
+            Token::Command(name) => return self.parse_command(name),
+            token => return Err(MathError::Unexpected(token.to_string())),
+        };
+
+        Ok(Atom::from(mathml))
+    }
+
+    /// Parse the command `name` and its arguments
+    fn parse_command(&mut self, name: String) -> Result<Atom, MathError> {
+        if let Some(symbol) = symbol(&name) {
+            return Ok(Atom::from(match symbol {
+                // Uppercase greek letters are upright in TeX
+                Symbol::Identifier(s)
+                    if self.variant.is_none() && s.chars().all(char::is_uppercase) =>
+                {
+                    format!("<mi mathvariant=\"normal\">{s}</mi>")
+                }
+                Symbol::Identifier(s) => self.identifier(s),
+                Symbol::Operator(s) => format!("<mo>{}</mo>", escape(s)),
+            }));
+        }
+
+        if let Some(limits) = large_operator(&name) {
+            return Ok(Atom {
+                mathml: format!("<mo largeop=\"true\" movablelimits=\"true\">{limits}</mo>"),
+                limits: !matches!(name.as_str(), "int" | "iint" | "iiint" | "oint"),
+            });
+        }
+
+        if FUNCTIONS.contains(&name.as_str()) {
+            return Ok(Atom {
+                limits: LIMIT_FUNCTIONS.contains(&name.as_str()),
+                mathml: format!("<mi>{name}</mi>"),
+            });
+        }
+
+        let mathml = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument(&name)?;
+                let denominator = self.parse_argument(&name)?;
+
+                format!("<mfrac>{numerator}{denominator}</mfrac>")
+            }
+            "binom" => {
+                let top = self.parse_argument(&name)?;
+                let bottom = self.parse_argument(&name)?;
+
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{top}{bottom}</mfrac><mo>)</mo></mrow>"
+                )
+            }
+            "sqrt" => {
+                self.skip_spaces();
+
+                if self.tokens.next_if_eq(&Token::Char('[')).is_some() {
+                    let index = self.parse_expression(true)?;
+
+                    if self.tokens.next() != Some(Token::Char(']')) {
+                        return Err(MathError::Unclosed("]"));
+                    }
+
+                    let radicand = self.parse_argument(&name)?;
+
+                    format!("<mroot>{radicand}<mrow>{index}</mrow></mroot>")
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument(&name)?)
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" => {
+                format!(
+                    "<mtext>{}</mtext>",
+                    escape(&self.parse_text_argument(&name)?)
+                )
+            }
+            "operatorname" => format!("<mi>{}</mi>", escape(&self.parse_text_argument(&name)?)),
+            "mathrm" | "mathbf" | "mathit" | "mathbb" | "mathcal" | "mathfrak" | "mathsf"
+            | "mathtt" | "boldsymbol" => {
+                let variant = match name.as_str() {
+                    "mathrm" => "normal",
+                    "mathbf" | "boldsymbol" => "bold",
+                    "mathit" => "italic",
+                    "mathbb" => "double-struck",
+                    "mathcal" => "script",
+                    "mathfrak" => "fraktur",
+                    "mathsf" => "sans-serif",
+                    _ => "monospace",
+                };
+
+                let outer_variant = self.variant.replace(variant);
+                let content = self.parse_argument(&name);
+                self.variant = outer_variant;
+
+                content?
+            }
+            "hat" | "widehat" | "bar" | "overline" | "vec" | "dot" | "ddot" | "tilde"
+            | "widetilde" | "overrightarrow" => {
+                let accent = match name.as_str() {
+                    "hat" | "widehat" => "^",
+                    "bar" | "overline" => "‾",
+                    "vec" | "overrightarrow" => "→",
+                    "dot" => "˙",
+                    "ddot" => "¨",
+                    _ => "~",
+                };
+
+                format!(
+                    "<mover accent=\"true\">{}<mo stretchy=\"true\">{accent}</mo></mover>",
+                    self.parse_argument(&name)?
+                )
+            }
+            "underline" => format!(
+                "<munder accentunder=\"true\">{}<mo stretchy=\"true\">_</mo></munder>",
+                self.parse_argument(&name)?
+            ),
+            "left" => {
+                let open = self.parse_delimiter(&name)?;
+                let content = self.parse_expression(false)?;
+
+                if self.tokens.next() != Some(Token::Command("right".to_string())) {
+                    return Err(MathError::Unclosed("right"));
+                }
+
+                let close = self.parse_delimiter("right")?;
+
+                format!("<mrow>{open}{content}{close}</mrow>")
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "Bigl" | "biggl" | "Biggl" | "bigr"
+            | "Bigr" | "biggr" | "Biggr" => self.parse_delimiter(&name)?,
+            "begin" => self.parse_environment()?,
+            "," | ":" | ";" | " " | "quad" | "qquad" => {
+                let width = match name.as_str() {
+                    "," => "0.1667em",
+                    ":" => "0.2222em",
+                    ";" => "0.2778em",
+                    " " => "0.333em",
+                    "quad" => "1em",
+                    _ => "2em",
+                };
+
+                format!("<mspace width=\"{width}\"></mspace>")
+            }
+            "!" => "<mspace width=\"-0.1667em\"></mspace>".to_string(),
+            "{" | "}" | "%" | "#" | "&" | "_" | "$" | "|" => {
+                format!("<mo>{}</mo>", escape(if name == "|" { "‖" } else { &name }))
+            }
+            _ => return Err(MathError::UnknownCommand(name)),
+        };
+
+        Ok(Atom::from(mathml))
+    }
+
+    /// Parse the delimiter following `\left`, `\right` and similar commands
+    fn parse_delimiter(&mut self, command: &str) -> Result<String, MathError> {
+        self.skip_spaces();
+
+        let delimiter = match self.tokens.next() {
+            Some(Token::Char('.')) => return Ok(String::new()),
+            Some(Token::Char(c)) => escape(&c.to_string()),
+            Some(Token::Command(name)) => match (name.as_str(), symbol(&name)) {
+                ("{" | "}" | "|", _) => if name == "|" { "‖" } else { &name }.to_string(),
+                (_, Some(Symbol::Operator(s))) => escape(s),
+                _ => return Err(MathError::UnknownCommand(name)),
+            },
+            _ => return Err(MathError::MissingArgument(command.to_string())),
+        };
+
+        Ok(format!(
+            "<mo fence=\"true\" stretchy=\"true\">{delimiter}</mo>"
+        ))
+    }
+
+    /// Parse the content of a `\begin{name} ... \end{name}` environment
+    fn parse_environment(&mut self) -> Result<String, MathError> {
+        let name = self.parse_text_argument("begin")?;
+
+        let (open, close, align) = match name.as_str() {
+            "matrix" | "smallmatrix" | "array" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("‖", "‖", None),
+            "cases" => ("{", "", Some("left left")),
+            "aligned" | "align" | "align*" | "split" => ("", "", Some("right left")),
+            "gathered" | "gather" | "gather*" => ("", "", None),
+            _ => return Err(MathError::UnknownEnvironment(name)),
+        };
+
+        if name == "array" {
+            // The column specification isn't supported, so it is ignored
+            self.parse_text_argument("begin")?;
+        }
+
+        let mut rows = String::new();
+        let mut row = String::new();
+
+        loop {
+            let cell = self.parse_expression(false)?;
+            row.push_str(&format!("<mtd>{cell}</mtd>"));
+
+            match self.tokens.next() {
+                Some(Token::Align) => {}
+                Some(Token::NewRow) => {
+                    rows.push_str(&format!("<mtr>{row}</mtr>"));
+                    row.clear();
+                }
+                Some(Token::Command(end)) if end == "end" => {
+                    let end_name = self.parse_text_argument("end")?;
+
+                    if end_name != name {
+                        return Err(MathError::MismatchedEnvironment(name, end_name));
+                    }
+
+                    break;
+                }
+                Some(token) => return Err(MathError::Unexpected(token.to_string())),
+                None => return Err(MathError::Unclosed("end")),
+            }
+        }
+
+        // A trailing `\\` doesn't start a new row
+        if row != "<mtd></mtd>" {
+            rows.push_str(&format!("<mtr>{row}</mtr>"));
+        }
+
+        let table = match align {
+            Some(align) => format!("<mtable columnalign=\"{align}\">{rows}</mtable>"),
+            None => format!("<mtable>{rows}</mtable>"),
+        };
+
+        let fence = |f: &str| {
+            if f.is_empty() {
+                String::new()
+            } else {
+                format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape(f))
+            }
+        };
+
+        Ok(format!(
+            "<mrow>{}{table}{}</mrow>",
+            fence(open),
+            fence(close)
+        ))
+    }
+
+    /// Create an identifier, respecting the current [Self::variant]
+    fn identifier(&self, identifier: &str) -> String {
+        match self.variant {
+            Some(variant) => format!("<mi mathvariant=\"{variant}\">{identifier}</mi>"),
+            None => format!("<mi>{identifier}</mi>"),
+        }
+    }
+}
+
+/// Symbols represented by a single command
+enum Symbol {
+    /// Rendered as `<mi>`
+    Identifier(&'static str),
+    /// Rendered as `<mo>`
+    Operator(&'static str),
+}
+
+/// Get the [Symbol] for the command `name`
+fn symbol(name: &str) -> Option<Symbol> {
+    use Symbol::{Identifier as I, Operator as O};
+
+    Some(match name {
+        "alpha" => I("α"),
+        "beta" => I("β"),
+        "gamma" => I("γ"),
+        "delta" => I("δ"),
+        "epsilon" => I("ϵ"),
+        "varepsilon" => I("ε"),
+        "zeta" => I("ζ"),
+        "eta" => I("η"),
+        "theta" => I("θ"),
+        "vartheta" => I("ϑ"),
+        "iota" => I("ι"),
+        "kappa" => I("κ"),
+        "lambda" => I("λ"),
+        "mu" => I("μ"),
+        "nu" => I("ν"),
+        "xi" => I("ξ"),
+        "pi" => I("π"),
+        "varpi" => I("ϖ"),
+        "rho" => I("ρ"),
+        "varrho" => I("ϱ"),
+        "sigma" => I("σ"),
+        "varsigma" => I("ς"),
+        "tau" => I("τ"),
+        "upsilon" => I("υ"),
+        "phi" => I("ϕ"),
+        "varphi" => I("φ"),
+        "chi" => I("χ"),
+        "psi" => I("ψ"),
+        "omega" => I("ω"),
+        "Gamma" => I("Γ"),
+        "Delta" => I("Δ"),
+        "Theta" => I("Θ"),
+        "Lambda" => I("Λ"),
+        "Xi" => I("Ξ"),
+        "Pi" => I("Π"),
+        "Sigma" => I("Σ"),
+        "Upsilon" => I("Υ"),
+        "Phi" => I("Φ"),
+        "Psi" => I("Ψ"),
+        "Omega" => I("Ω"),
+        "infty" => I("∞"),
+        "partial" => I("∂"),
+        "nabla" => I("∇"),
+        "emptyset" | "varnothing" => I("∅"),
+        "hbar" => I("ℏ"),
+        "ell" => I("ℓ"),
+        "Re" => I("ℜ"),
+        "Im" => I("ℑ"),
+        "aleph" => I("ℵ"),
+        "cdot" => O("⋅"),
+        "times" => O("×"),
+        "div" => O("÷"),
+        "pm" => O("±"),
+        "mp" => O("∓"),
+        "ast" => O("∗"),
+        "star" => O("⋆"),
+        "circ" => O("∘"),
+        "bullet" => O("∙"),
+        "oplus" => O("⊕"),
+        "otimes" => O("⊗"),
+        "leq" | "le" => O("≤"),
+        "geq" | "ge" => O("≥"),
+        "neq" | "ne" => O("≠"),
+        "ll" => O("≪"),
+        "gg" => O("≫"),
+        "approx" => O("≈"),
+        "equiv" => O("≡"),
+        "sim" => O("∼"),
+        "simeq" => O("≃"),
+        "cong" => O("≅"),
+        "propto" => O("∝"),
+        "perp" => O("⊥"),
+        "parallel" => O("∥"),
+        "mid" => O("∣"),
+        "in" => O("∈"),
+        "notin" => O("∉"),
+        "ni" => O("∋"),
+        "subset" => O("⊂"),
+        "subseteq" => O("⊆"),
+        "supset" => O("⊃"),
+        "supseteq" => O("⊇"),
+        "cup" => O("∪"),
+        "cap" => O("∩"),
+        "setminus" => O("∖"),
+        "land" | "wedge" => O("∧"),
+        "lor" | "vee" => O("∨"),
+        "neg" | "lnot" => O("¬"),
+        "forall" => O("∀"),
+        "exists" => O("∃"),
+        "nexists" => O("∄"),
+        "angle" => O("∠"),
+        "to" | "rightarrow" => O("→"),
+        "leftarrow" | "gets" => O("←"),
+        "leftrightarrow" => O("↔"),
+        "Rightarrow" | "implies" => O("⇒"),
+        "Leftarrow" => O("⇐"),
+        "Leftrightarrow" | "iff" => O("⇔"),
+        "mapsto" => O("↦"),
+        "uparrow" => O("↑"),
+        "downarrow" => O("↓"),
+        "ldots" | "dots" => O("…"),
+        "cdots" => O("⋯"),
+        "vdots" => O("⋮"),
+        "ddots" => O("⋱"),
+        "prime" => O("′"),
+        "langle" => O("⟨"),
+        "rangle" => O("⟩"),
+        "lfloor" => O("⌊"),
+        "rfloor" => O("⌋"),
+        "lceil" => O("⌈"),
+        "rceil" => O("⌉"),
+        "vert" => O("|"),
+        "Vert" => O("‖"),
+        "lbrace" => O("{"),
+        "rbrace" => O("}"),
+        _ => return None,
+    })
+}
+
+/// Get the symbol of the large operator `name`, such as `\sum`
+fn large_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        _ => return None,
+    })
+}
+
+/// Functions which are rendered upright, such as `\sin`
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "det", "dim", "ker", "deg", "arg", "gcd", "lim", "liminf",
+    "limsup", "max", "min", "sup", "inf", "Pr",
+];
+
+/// The [FUNCTIONS] which have their subscripts placed below them in display mode
+const LIMIT_FUNCTIONS: &[&str] = &[
+    "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd", "Pr",
+];
+
+/// Escape the characters with special meaning in html
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MAX_DEPTH, MathError, tex_to_mathml};
+
+    /// Convert `tex` to inline MathML and strip the surrounding `<math>` element
+    fn inline(tex: &str) -> Result<String, MathError> {
+        tex_to_mathml(tex, false).map(|mathml| {
+            let start = mathml.find("<semantics><mrow>").unwrap() + "<semantics><mrow>".len();
+            let end = mathml.find("</mrow><annotation").unwrap();
+
+            mathml[start..end].to_string()
+        })
+    }
+
+    #[test]
+    fn basics() {
+        assert_eq!(
+            inline("x + 12.5").unwrap(),
+            "<mi>x</mi><mo>+</mo><mn>12.5</mn>"
+        );
+        assert_eq!(
+            inline("a < b").unwrap(),
+            "<mi>a</mi><mo>&lt;</mo><mi>b</mi>"
+        );
+        assert_eq!(inline(r"\alpha").unwrap(), "<mi>α</mi>");
+        assert_eq!(
+            inline(r"\frac{1}{n}").unwrap(),
+            "<mfrac><mrow><mn>1</mn></mrow><mrow><mi>n</mi></mrow></mfrac>"
+        );
+        assert_eq!(
+            inline(r"\sqrt[3]{x}").unwrap(),
+            "<mroot><mrow><mi>x</mi></mrow><mrow><mn>3</mn></mrow></mroot>"
+        );
+        assert_eq!(
+            inline(r"\text{if } x").unwrap(),
+            "<mtext>if </mtext><mi>x</mi>"
+        );
+        assert_eq!(
+            inline(r"\mathbb{R}").unwrap(),
+            "<mrow><mi mathvariant=\"double-struck\">R</mi></mrow>"
+        );
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(inline("x^2").unwrap(), "<msup><mi>x</mi><mn>2</mn></msup>");
+        assert_eq!(
+            inline("x_i^{2}").unwrap(),
+            "<msubsup><mi>x</mi><mi>i</mi><mrow><mn>2</mn></mrow></msubsup>"
+        );
+        assert_eq!(
+            inline("f'").unwrap(),
+            "<msup><mi>f</mi><mrow><mo>′</mo></mrow></msup>"
+        );
+
+        // large operators only have their limits above and below in display mode
+        assert!(inline(r"\sum_{i=0}^n i").unwrap().starts_with("<msubsup>"));
+        assert!(
+            tex_to_mathml(r"\sum_{i=0}^n i", true)
+                .unwrap()
+                .contains("<munderover>")
+        );
+    }
+
+    #[test]
+    fn environments() {
+        assert_eq!(
+            inline(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}").unwrap(),
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable>\
+            <mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>\
+            <mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr>\
+            </mtable><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"
+        );
+        assert_eq!(
+            inline(r"\left[ 0, 1 \right.").unwrap(),
+            "<mrow><mo fence=\"true\" stretchy=\"true\">[</mo><mn>0</mn><mo>,</mo><mn>1</mn></mrow>"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            inline(r"\unknown{x}"),
+            Err(MathError::UnknownCommand("unknown".to_string()))
+        );
+        assert_eq!(inline(r"\frac{1}{2"), Err(MathError::UnclosedGroup));
+        assert_eq!(inline(r"x}"), Err(MathError::Unexpected("}".to_string())));
+        assert_eq!(
+            inline(r"\frac{1}"),
+            Err(MathError::MissingArgument("frac".to_string()))
+        );
+        assert_eq!(inline(r"x^1^2"), Err(MathError::DoubleScript("super")));
+        assert_eq!(inline(r"\left( x"), Err(MathError::Unclosed("right")));
+        assert_eq!(
+            inline(r"\begin{matrix} x \end{pmatrix}"),
+            Err(MathError::MismatchedEnvironment(
+                "matrix".to_string(),
+                "pmatrix".to_string()
+            ))
+        );
+        assert_eq!(
+            inline(r"\begin{tikzpicture}\end{tikzpicture}"),
+            Err(MathError::UnknownEnvironment("tikzpicture".to_string()))
+        );
+
+        let nested = |depth| format!("{}x{}", "{".repeat(depth), "}".repeat(depth));
+        assert!(inline(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(inline(&nested(MAX_DEPTH)), Err(MathError::TooDeep));
+        assert_eq!(inline(&nested(100_000)), Err(MathError::TooDeep));
+        assert_eq!(inline(&r"\sqrt".repeat(100_000)), Err(MathError::TooDeep));
+    }
+}
@@ -0,0 +1,783 @@
+//! The conversion logic from md to HTML. See: [md_to_html]
+//!
+//! We also need to do some post processing, using the [transform::Pipeline], to make the resulting
+//! markdown work for our application.
+//!
+//! How the conversion is done can be adjusted using [ConvertOptions].
+pub mod blocks;
+pub mod filter;
+pub mod front_matter;
+pub mod highlight;
+pub mod include;
+pub mod math;
+pub mod sanitize;
+pub mod transform;
+
+use kuchikiki::NodeRef;
+use markdown::{Options, to_html_with_options};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use filter::ExternalFilter;
+use transform::{Context, Pipeline};
+
+/// Options to adjust [md_to_html]
+///
+/// One set of options is shared by all clients of a server. See: [crate::config::Config]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ConvertOptions {
+    /// Highlight code blocks using [highlight::highlight] instead of leaving it to the viewer
+    pub highlight: bool,
+    /// Render math to MathML using [math::tex_to_mathml] instead of leaving it to the viewer
+    pub math: bool,
+    /// Remove unsafe html, like scripts, from the markdown using [sanitize::sanitize]
+    pub safe: bool,
+    /// Commands the html is passed through after all other transforms, in order
+    pub filters: Vec<ExternalFilter>,
+}
+
+/// A single heading of a document, as found by [outline]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    /// Level of the heading, `1` for `<h1>` up to `6` for `<h6>`
+    pub level: u8,
+    /// Text content of the heading
+    pub text: String,
+    /// The `id` of the heading, which can be used as a link target
+    pub slug: String,
+    /// Headings of a lower level that follow this one
+    pub children: Vec<Heading>,
+}
+
+/// Generates GitHub-compatible slugs for headings
+///
+/// Keeps track of the slugs already handed out, so that duplicates get a `-1`, `-2`, ... suffix
+/// just like on GitHub.
+#[derive(Debug, Default)]
+struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    /// Get a unique slug for the given text
+    fn slug(&mut self, text: &str) -> String {
+        let original = Self::slugify(text);
+        let mut slug = original.clone();
+
+        while self.occurrences.contains_key(&slug) {
+            let count = self
+                .occurrences
+                .get_mut(&original)
+                .expect("The original slug is always inserted before its suffixed versions.");
+            *count += 1;
+
+            slug = format!("{original}-{count}");
+        }
+
+        self.occurrences.insert(slug.clone(), 0);
+
+        slug
+    }
+
+    /// Turn the text into a slug without checking for duplicates
+    ///
+    /// Lowercases the text, removes all punctuation except `-` and `_` and replaces spaces with
+    /// `-`.
+    fn slugify(text: &str) -> String {
+        text.trim()
+            .to_lowercase()
+            .chars()
+            .filter_map(|c| match c {
+                ' ' => Some('-'),
+                '-' | '_' => Some(c),
+                c if c.is_alphanumeric() => Some(c),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// The actual conversion from md to HTML, see [md_to_main]
+///
+/// Returns the whole html document.
+pub fn md_to_html(md: &str, md_path: &Path, options: &ConvertOptions) -> String {
+    let main = md_to_main(md, md_path, options);
+    let document = main.ancestors().last().unwrap_or(main);
+
+    let mut html = Vec::new();
+    document
+        .serialize(&mut html)
+        .expect("Serialization should never fail, if it does there is a bug.");
+
+    String::from_utf8(html).expect("Converting document should never fail.")
+}
+
+/// Convert the `md` to html, returning the `<main>` element of the resulting DOM
+///
+/// Uses the [Pipeline] described by the `options` to adjust the HTML before returning it. The DOM
+/// the pipeline works on is returned directly, so callers don't have to parse the html again.
+///
+/// If [ConvertOptions::safe] is set, the HTML is sanitized before it is post-processed, so the
+/// markup added by the [Pipeline] is kept.
+///
+/// `md_path` is the location of the markdown file, which relative links and images are resolved
+/// against.
+pub fn md_to_main(md: &str, md_path: &Path, options: &ConvertOptions) -> NodeRef {
+    md_to_main_with(md, md_path, options.safe, &Pipeline::new(options))
+}
+
+/// Convert the `md` to html using a custom `pipeline`, returning the `<main>` element of the
+/// resulting DOM
+///
+/// Works like [md_to_main], which uses [Pipeline::new()]. This allows adding transforms of your
+/// own, by [Pipeline::push()]ing them to the pipeline of the options. If `safe` is set, the HTML
+/// is sanitized before the pipeline is applied, see [ConvertOptions::safe].
+///
+/// # Panics
+///
+/// If the `pipeline` doesn't create a `<main>`, which is done by [transform::MainWrapper].
+pub fn md_to_main_with(md: &str, md_path: &Path, safe: bool, pipeline: &Pipeline) -> NodeRef {
+    let html =
+        to_html_with_options(md, &markdown_options(md)).expect("See docs of to_html_with_options.");
+
+    let html = if safe {
+        sanitize::sanitize(&html)
+    } else {
+        html
+    };
+
+    let document = pipeline.apply(html, &Context { md_path, md });
+
+    // External filters may replace the document, so this has to be selected afterwards. Output
+    // without a main is rejected by them
+    document
+        .select_first("main")
+        .expect("The pipeline creates a main, which filters have to keep.")
+        .as_node()
+        .clone()
+}
+
+/// The options used to parse and compile the markdown `md`
+///
+/// Front matter is only parsed if it is closed, since markdown-rs panics on some documents starting
+/// with an unclosed fence.
+fn markdown_options(md: &str) -> Options {
+    Options {
+        parse: markdown::ParseOptions {
+            constructs: markdown::Constructs {
+                html_flow: true,
+                html_text: true,
+                definition: true,
+                frontmatter: front_matter::exists(md),
+                math_flow: true,
+                math_text: true,
+                ..markdown::Constructs::gfm()
+            },
+            ..markdown::ParseOptions::gfm()
+        },
+        compile: markdown::CompileOptions {
+            allow_dangerous_html: true,
+            ..markdown::CompileOptions::gfm()
+        },
+    }
+}
+
+/// Get the outline of the given document, or part of a document
+///
+/// The headings are nested by their level, so that every heading contains the headings of a lower
+/// level which follow it. Only headings with an `id` are included, which after [md_to_html] is the
+/// case for all of them.
+pub fn outline(node: &NodeRef) -> Vec<Heading> {
+    let mut outline = Vec::new();
+
+    for heading in node
+        .select("h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]")
+        .expect("Selector is hard-coded.")
+    {
+        let level = heading.name.local.as_bytes()[1] - b'0';
+        let slug = heading
+            .attributes
+            .borrow()
+            .get("id")
+            .expect("The selector ensures the existence of the id.")
+            .to_string();
+
+        insert_heading(
+            &mut outline,
+            Heading {
+                level,
+                text: heading.text_contents().trim().to_string(),
+                slug,
+                children: Vec::new(),
+            },
+        );
+    }
+
+    outline
+}
+
+/// Insert the `heading` as the last heading into the tree of `siblings`
+fn insert_heading(siblings: &mut Vec<Heading>, heading: Heading) {
+    match siblings.last_mut() {
+        Some(last) if last.level < heading.level => insert_heading(&mut last.children, heading),
+        _ => siblings.push(heading),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        ConvertOptions, Heading, Slugger, include, md_to_html, md_to_main_with, outline,
+        transform::{Context, Pipeline, Transform},
+    };
+    use kuchikiki::{ElementData, NodeDataRef, NodeRef, traits::*};
+    use std::path::Path;
+
+    #[test]
+    fn links() {
+        let md_input = [
+            "[](https://test.md)",
+            "[](http://test.md)",
+            "[](/test.md)",
+            "[](./test.md)",
+            "[](../test.md)",
+            "[](./test.md#section)",
+            "[](https://test.md#section)",
+            "[](#section)",
+            "[](./dir/)",
+            "[](./test.markdown)",
+            "[](./image.png)",
+            "[](mailto:test.md)",
+        ];
+
+        let html_output = md_input
+            .map(|md| md_to_html(md, Path::new("/docs/test.md"), &ConvertOptions::default()));
+
+        let mut elements = html_output.into_iter().map(|h| {
+            let document = kuchikiki::parse_html().one(h);
+
+            document
+                .select_first(r#"a"#)
+                .expect("Selector is hard-coded.")
+        });
+
+        assert_link(elements.next().unwrap(), false);
+        assert_link(elements.next().unwrap(), false);
+        assert_link(elements.next().unwrap(), true);
+        assert_link(elements.next().unwrap(), true);
+        assert_link(elements.next().unwrap(), true);
+        assert_link(elements.next().unwrap(), true);
+        assert_link(elements.next().unwrap(), false);
+        assert_link(elements.next().unwrap(), false);
+        assert_link(elements.next().unwrap(), true);
+        assert_link(elements.next().unwrap(), true);
+        assert_link(elements.next().unwrap(), false);
+        assert_link(elements.next().unwrap(), false);
+
+        // make sure the iterator is empty
+        assert!(elements.next().is_none());
+    }
+
+    #[test]
+    fn images() {
+        let html = md_to_html(
+            "![](./img/a.png) ![](../b%20c.png) ![](/abs.png) ![](https://example.com/d.png)",
+            Path::new("/docs/sub/test.md"),
+            &ConvertOptions::default(),
+        );
+
+        let document = kuchikiki::parse_html().one(html);
+
+        let srcs: Vec<String> = document
+            .select("img")
+            .expect("Selector is hard-coded.")
+            .map(|img| img.attributes.borrow().get("src").unwrap().to_string())
+            .collect();
+
+        assert_eq!(
+            srcs,
+            [
+                "asset:///docs/sub/img/a.png",
+                "asset:///docs/b%20c.png",
+                "asset:///abs.png",
+                "https://example.com/d.png"
+            ]
+        );
+    }
+
+    fn assert_link(element: NodeDataRef<ElementData>, internal: bool) {
+        let attributes = element.attributes.borrow();
+        if internal {
+            assert_eq!(
+                attributes.get("onclick"),
+                Some(
+                    format!(
+                        "return handle_redirect(\"{}\")",
+                        attributes.get("href").unwrap()
+                    )
+                    .as_str()
+                )
+            )
+        } else {
+            assert!(attributes.get("onclick").is_none())
+        }
+    }
+
+    #[test]
+    fn alerts() {
+        let md_input = [
+            r#"
+> [!Warning]
+> This should work
+            "#,
+            r#"
+> [!Warning] This
+> shouldn't work
+            "#,
+            r#"
+> [!Important] This
+> is sooo much longer
+> but still shouldn't work
+            "#,
+            r#"
+> [!Note]
+> `should work`
+            "#,
+            r#"
+> [!NOTE]
+> This multi line alter, which is rather long
+> should work
+            "#,
+            r#"
+1. Text
+
+    - Some
+
+    > [!NOTE]
+    > This should not work
+            "#,
+        ];
+
+        let html_output = md_input
+            .map(|md| md_to_html(md, Path::new("/docs/test.md"), &ConvertOptions::default()));
+
+        let mut elements = html_output.into_iter().map(|h| {
+            let document = kuchikiki::parse_html().one(h);
+
+            document.select_first(r#"div"#)
+        });
+
+        assert!(elements.next().unwrap().is_ok_and(is_alert));
+        assert!(elements.next().unwrap().is_err());
+        assert!(elements.next().unwrap().is_err());
+        assert!(elements.next().unwrap().is_ok_and(is_alert));
+        assert!(elements.next().unwrap().is_ok_and(is_alert));
+        assert!(elements.next().unwrap().is_err());
+
+        // make sure the iterator is empty
+        assert!(elements.next().is_none());
+    }
+
+    fn is_alert(element: NodeDataRef<ElementData>) -> bool {
+        let attributes = element.attributes.borrow();
+        attributes
+            .get("class")
+            .is_some_and(|c| c.contains("markdown-alert"))
+            && element.as_node().select_first("p").is_ok_and(|p| {
+                p.attributes
+                    .borrow()
+                    .get("class")
+                    .is_some_and(|c| c.contains("markdown-alert-title"))
+            })
+    }
+
+    #[test]
+    fn slugs() {
+        let mut slugger = Slugger::default();
+
+        assert_eq!(slugger.slug("Hello World"), "hello-world");
+        assert_eq!(slugger.slug("Hello World"), "hello-world-1");
+        assert_eq!(slugger.slug("Hello World"), "hello-world-2");
+        assert_eq!(slugger.slug("hello-world-1"), "hello-world-1-1");
+        assert_eq!(slugger.slug("What's `new`? (v0.3.0)"), "whats-new-v030");
+        assert_eq!(slugger.slug("snake_case & Ünïcödé"), "snake_case--ünïcödé");
+        assert_eq!(slugger.slug("  Trimmed  "), "trimmed");
+    }
+
+    #[test]
+    fn heading_anchors() {
+        let html = md_to_html(
+            "# Title\n\n## Usage\n\n## Usage\n\n<h3 id=\"custom\">Raw</h3>",
+            Path::new("/docs/test.md"),
+            &ConvertOptions::default(),
+        );
+
+        let document = kuchikiki::parse_html().one(html);
+
+        let ids: Vec<String> = document
+            .select("h1, h2, h3")
+            .expect("Selector is hard-coded.")
+            .map(|h| {
+                let href = h
+                    .as_node()
+                    .select_first("a.anchor")
+                    .expect("Every heading should have an anchor.")
+                    .attributes
+                    .borrow()
+                    .get("href")
+                    .unwrap()
+                    .to_string();
+
+                let id = h.attributes.borrow().get("id").unwrap().to_string();
+
+                assert_eq!(href, format!("#{id}"));
+
+                id
+            })
+            .collect();
+
+        assert_eq!(ids, ["title", "usage", "usage-1", "custom"]);
+    }
+
+    #[test]
+    fn outline_nesting() {
+        let html = md_to_html(
+            "## Intro\n\n# Title\n\n### Deep\n\n## Section\n\n# Other",
+            Path::new("/docs/test.md"),
+            &ConvertOptions::default(),
+        );
+
+        let document = kuchikiki::parse_html().one(html);
+
+        let heading = |level, text: &str, children| Heading {
+            level,
+            text: text.to_string(),
+            slug: text.to_lowercase(),
+            children,
+        };
+
+        assert_eq!(
+            outline(&document),
+            vec![
+                heading(2, "Intro", vec![]),
+                heading(
+                    1,
+                    "Title",
+                    vec![heading(3, "Deep", vec![]), heading(2, "Section", vec![])]
+                ),
+                heading(1, "Other", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_highlighting() {
+        let md = "```rust\nfn main() {}\n```\n\n```not-a-language\nfn main() {}\n```";
+
+        let plain = kuchikiki::parse_html().one(md_to_html(
+            md,
+            Path::new("/docs/test.md"),
+            &ConvertOptions::default(),
+        ));
+        assert!(plain.select_first("code.hljs").is_err());
+        assert!(plain.select_first("code span").is_err());
+
+        let highlighted = kuchikiki::parse_html().one(md_to_html(
+            md,
+            Path::new("/docs/test.md"),
+            &ConvertOptions {
+                highlight: true,
+                ..Default::default()
+            },
+        ));
+
+        let code_blocks: Vec<_> = highlighted
+            .select("pre > code")
+            .expect("Selector is hard-coded.")
+            .collect();
+
+        assert_eq!(code_blocks.len(), 2);
+
+        let rust = &code_blocks[0];
+        assert_eq!(rust.text_contents(), "fn main() {}\n");
+        assert!(
+            rust.attributes
+                .borrow()
+                .get("class")
+                .is_some_and(|c| c.split_whitespace().eq(["language-rust", "hljs"]))
+        );
+        assert!(rust.as_node().select_first("span.hljs-keyword").is_ok());
+
+        // unknown languages are left for the viewer
+        let unknown = &code_blocks[1];
+        assert!(
+            unknown
+                .attributes
+                .borrow()
+                .get("data-highlighted")
+                .is_none()
+        );
+        assert!(unknown.as_node().select_first("span").is_err());
+    }
+
+    #[test]
+    fn math_rendering() {
+        let md = "$x^2$ and $\\unknown$\n\n$$\n\\frac{1}{2}\n$$";
+
+        let plain = kuchikiki::parse_html().one(md_to_html(
+            md,
+            Path::new("/docs/test.md"),
+            &ConvertOptions::default(),
+        ));
+        assert!(plain.select_first("math").is_err());
+        assert_eq!(
+            plain
+                .select("code.language-math")
+                .expect("Selector is hard-coded.")
+                .count(),
+            3
+        );
+
+        let rendered = kuchikiki::parse_html().one(md_to_html(
+            md,
+            Path::new("/docs/test.md"),
+            &ConvertOptions {
+                math: true,
+                ..Default::default()
+            },
+        ));
+
+        assert!(rendered.select_first("code.language-math").is_err());
+        assert!(rendered.select_first("pre").is_err());
+
+        let displays: Vec<String> = rendered
+            .select("math")
+            .expect("Selector is hard-coded.")
+            .map(|m| m.attributes.borrow().get("display").unwrap().to_string())
+            .collect();
+        assert_eq!(displays, ["inline", "block"]);
+
+        assert!(rendered.select_first("math msup").is_ok());
+        assert!(rendered.select_first("math mfrac").is_ok());
+
+        let error = rendered
+            .select_first("span.math-error")
+            .expect("Unknown commands should be shown as an error.");
+        assert_eq!(error.text_contents(), "\\unknown");
+        assert_eq!(
+            error.attributes.borrow().get("title"),
+            Some("Unknown command `\\unknown`")
+        );
+
+        let nested = format!("${}x{}$", "{".repeat(100_000), "}".repeat(100_000));
+        let rendered = kuchikiki::parse_html().one(md_to_html(
+            &nested,
+            Path::new("/docs/test.md"),
+            &ConvertOptions {
+                math: true,
+                ..Default::default()
+            },
+        ));
+        let error = rendered
+            .select_first("span.math-error")
+            .expect("Deeply nested math should be shown as an error.");
+        assert_eq!(
+            error.attributes.borrow().get("title"),
+            Some("Nested too deeply")
+        );
+    }
+
+    #[test]
+    fn front_matter_removed() {
+        for md in [
+            "---\ntitle: Yaml\n---\n# Content",
+            "+++\ntitle = \"Toml\"\n+++\n# Content",
+        ] {
+            let document = kuchikiki::parse_html().one(md_to_html(
+                md,
+                Path::new("/docs/test.md"),
+                &ConvertOptions::default(),
+            ));
+
+            assert!(document.select_first("hr").is_err());
+            assert_eq!(
+                document
+                    .select_first("main")
+                    .expect("The pipeline creates a main, which filters have to keep.")
+                    .text_contents()
+                    .trim(),
+                "Content"
+            );
+        }
+    }
+
+    #[test]
+    fn unclosed_front_matter() {
+        // markdown-rs panics on these, if front matter is enabled
+        for md in [
+            "---\n    code\n> > [!NOTE]\n- [ ] [^1]",
+            "---\n| a | b |\n|---|---|\n*+++\n1. # ",
+        ] {
+            let path = Path::new("/docs/test.md");
+
+            md_to_html(md, path, &ConvertOptions::default());
+            include::expand(
+                &format!("{md}\n\n{{{{#include a.md}}}}"),
+                path,
+                &ConvertOptions::default(),
+            );
+        }
+    }
+
+    #[test]
+    fn safe_mode() {
+        let md = r#"# Title
+
+<script>alert("script")</script>
+<img src="./image.png" onerror="alert('img')">
+
+> [!NOTE]
+> Note
+
+- [x] Done
+
+$x^2$
+
+```rust
+fn main() {}
+```
+"#;
+
+        let document = kuchikiki::parse_html().one(md_to_html(
+            md,
+            Path::new("/docs/test.md"),
+            &ConvertOptions {
+                highlight: true,
+                math: true,
+                safe: true,
+                ..Default::default()
+            },
+        ));
+
+        assert!(document.select_first("script").is_err());
+        assert!(document.select_first("[onerror]").is_err());
+
+        // the markup added while post-processing is kept
+        assert!(document.select_first("h1 > a.anchor svg").is_ok());
+        assert!(document.select_first(".markdown-alert-note").is_ok());
+        assert!(document.select_first("li.task-list-item").is_ok());
+        assert!(document.select_first("math msup").is_ok());
+        assert!(document.select_first("code.hljs span.hljs-keyword").is_ok());
+        assert_eq!(
+            document
+                .select_first("img")
+                .unwrap()
+                .attributes
+                .borrow()
+                .get("src"),
+            Some("asset:///docs/image.png")
+        );
+    }
+
+    #[test]
+    fn custom_pipeline() {
+        /// Adds a class to every paragraph
+        #[derive(Debug)]
+        struct Classes;
+
+        impl Transform for Classes {
+            fn name(&self) -> &str {
+                "classes"
+            }
+
+            fn apply(&self, document: &NodeRef, _context: &Context) {
+                for p in document.select("p").expect("Selector is hard-coded.") {
+                    p.attributes
+                        .borrow_mut()
+                        .insert("class", "custom".to_string());
+                }
+            }
+        }
+
+        let options = ConvertOptions::default();
+        let mut pipeline = Pipeline::new(&options);
+        pipeline.push(Classes);
+
+        let main = md_to_main_with(
+            "# Title\n\nText",
+            Path::new("/docs/test.md"),
+            options.safe,
+            &pipeline,
+        );
+
+        assert!(main.select_first("p.custom").is_ok());
+        // the transforms of the options are still applied
+        assert!(main.select_first("h1#title").is_ok());
+    }
+
+    #[test]
+    fn source_lines() {
+        let md = r#"---
+title: Lines
+---
+# Title
+
+Paragraph[^1]
+
+- Item
+- Item
+
+> [!NOTE]
+> Note
+
+<div>
+<p>Raw</p>
+</div>
+
+$$
+x
+$$
+
+| a |
+| - |
+| b |
+
+[^1]: Footnote
+"#;
+
+        let document = kuchikiki::parse_html().one(md_to_html(
+            md,
+            Path::new("/docs/test.md"),
+            &ConvertOptions {
+                math: true,
+                ..Default::default()
+            },
+        ));
+
+        let lines: Vec<(String, String)> = document
+            .select("[data-source-line]")
+            .expect("Selector is hard-coded.")
+            .map(|e| {
+                (
+                    e.name.local.to_string(),
+                    e.attributes
+                        .borrow()
+                        .get("data-source-line")
+                        .unwrap()
+                        .to_string(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            lines
+                .iter()
+                .map(|(tag, line)| format!("{tag}:{line}"))
+                .collect::<Vec<_>>(),
+            [
+                "h1:4", "p:6", "ul:8", "li:8", "li:9", "div:11", "p:14", "math:18", "table:22",
+                "tr:22", "tr:24"
+            ]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Sanitization of untrusted html. See: [sanitize]
+//!
+//! The allowlist is modelled after the one GitHub uses for rendering READMEs.
+use ammonia::Builder;
+use std::{borrow::Cow, sync::LazyLock};
+
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::default();
+
+    builder
+        .add_tags(["input", "video", "audio", "source", "picture"])
+        .add_generic_attributes(["id", "align"])
+        .add_tag_attributes("code", ["class"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("video", ["src", "controls", "loop", "muted", "poster"])
+        .add_tag_attributes("audio", ["src", "controls", "loop", "muted"])
+        .add_tag_attributes("source", ["src", "srcset", "type", "media"])
+        .add_tag_attributes("img", ["srcset"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            // only keep the classes used to mark the language of code blocks, and whether math
+            // is displayed as a block
+            ("code", "class") => {
+                let classes = value
+                    .split_whitespace()
+                    .filter(|c| {
+                        c.starts_with("language-") || matches!(*c, "math-display" | "math-inline")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                (!classes.is_empty()).then_some(Cow::Owned(classes))
+            }
+            // only keep the checkboxes of task lists
+            ("input", "type") => (value == "checkbox").then_some(Cow::Borrowed(value)),
+            _ => Some(Cow::Borrowed(value)),
+        });
+
+    builder
+});
+
+/// Remove all tags and attributes from the `html`, which aren't on the allowlist
+///
+/// This removes scripts, event handlers, `<iframe>`s, `<style>`s and the like, while keeping the
+/// markup created by converting markdown.
+pub fn sanitize(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::sanitize;
+
+    #[test]
+    fn removes_dangerous_html() {
+        assert_eq!(
+            sanitize(r#"<p onclick="alert(1)">Text<script>alert(2)</script></p>"#),
+            "<p>Text</p>"
+        );
+        assert_eq!(
+            sanitize(r#"<iframe src="https://example.com"></iframe>"#),
+            ""
+        );
+        assert_eq!(
+            sanitize(r#"<a href="javascript:alert(1)">Link</a>"#),
+            r#"<a rel="noopener noreferrer">Link</a>"#
+        );
+        assert_eq!(sanitize(r#"<input type="text" value="x">"#), "<input>");
+        assert_eq!(
+            sanitize(r#"<code class="language-rust evil">x</code>"#),
+            r#"<code class="language-rust">x</code>"#
+        );
+    }
+
+    #[test]
+    fn keeps_markdown() {
+        for html in [
+            r#"<h2 id="usage">Usage</h2>"#,
+            r#"<ul><li><input type="checkbox" checked="" disabled=""> Done</li></ul>"#,
+            r#"<p><img src="./image.png" alt="Image"></p>"#,
+            r#"<details><summary>More</summary><p>Hidden</p></details>"#,
+            r#"<blockquote><p>[!NOTE]<br>Note</p></blockquote>"#,
+            r#"<pre><code class="language-math math-display">x^2</code></pre>"#,
+            r#"<p><code class="language-math math-inline">x</code></p>"#,
+        ] {
+            assert_eq!(sanitize(html), html);
+        }
+    }
+}
@@ -0,0 +1,888 @@
+//! The pipeline of transforms applied to the html of a document. See: [Pipeline]
+//!
+//! The html created from the markdown needs some adjustments to work for our application, e.g.
+//! GitHub-style alerts and links to other markdown files. Each of these is a [Transform], which
+//! works on the parsed DOM of the document.
+//!
+//! New passes are added by implementing [Transform] and adding them to [Pipeline::new()]. Passes
+//! which aren't part of igneous-md can be added to a pipeline passed to
+//! [super::md_to_main_with()].
+use kuchikiki::{NodeRef, traits::*};
+use markdown::mdast;
+use markup5ever::{interface::QualName, local_name, namespace_url, ns};
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use regex::Regex;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    path::Path,
+    vec,
+};
+
+use super::{ConvertOptions, Slugger, highlight, markdown_options, math};
+use crate::paths;
+
+static ALERT_REGEX: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
+    Regex::new(r#"^\s*\[!(?i)(note|tip|important|warning|caution)\](\s*$|\s*\n)"#)
+        .expect("Regex is hard-coded.")
+});
+
+static SVGS: std::sync::LazyLock<HashMap<&'static str, &'static str>> =
+    std::sync::LazyLock::new(|| {
+        HashMap::from([
+            ("Note", include_str!("../../assets/info-16.svg")),
+            ("Tip", include_str!("../../assets/light-bulb-16.svg")),
+            ("Important", include_str!("../../assets/report-16.svg")),
+            ("Warning", include_str!("../../assets/alert-16.svg")),
+            ("Caution", include_str!("../../assets/stop-16.svg")),
+        ])
+    });
+
+static LINK_SVG: &str = include_str!("../../assets/link-16.svg");
+
+/// Matches the scheme of urls such as `https://` or `mailto:`
+static SCHEME_REGEX: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
+    Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").expect("Regex is hard-coded.")
+});
+
+/// Characters which need to be percent-encoded in the path of an `asset://` URI
+const ASSET_PATH_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A single pass over the html of a document
+pub trait Transform: Debug + Send + Sync {
+    /// Name of the transform, used in logs
+    fn name(&self) -> &str;
+
+    /// Adjust the `document` in place
+    fn apply(&self, document: &NodeRef, context: &Context);
+}
+
+/// Information about the document being transformed
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    /// Location of the markdown file
+    pub md_path: &'a Path,
+    /// The markdown the html was created from
+    pub md: &'a str,
+}
+
+/// An ordered list of [Transform]s
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    /// Create the pipeline described by the `options`
+    ///
+    /// It consists of the following transforms, in order:
+    ///
+    /// 1. [MainWrapper]
+    ///
+    /// 2. [SourceLines]
+    ///
+    /// 3. [TaskLists]
+    ///
+    /// 4. [MdLinks]
+    ///
+    /// 5. [Alerts]
+    ///
+    /// 6. [MediaAssets]
+    ///
+    /// 7. [HeadingAnchors]
+    ///
+    /// 8. [Math], if [ConvertOptions::math] is set
+    ///
+    /// 9. [Highlight], if [ConvertOptions::highlight] is set
+    ///
+    /// 10. All [ConvertOptions::filters]
+    pub fn new(options: &ConvertOptions) -> Self {
+        let mut pipeline = Self::default();
+
+        pipeline
+            .push(MainWrapper)
+            .push(SourceLines)
+            .push(TaskLists)
+            .push(MdLinks)
+            .push(Alerts)
+            .push(MediaAssets)
+            .push(HeadingAnchors);
+
+        // Math needs to be rendered before highlighting, so math blocks aren't mistaken for code
+        if options.math {
+            pipeline.push(Math);
+        }
+
+        if options.highlight {
+            pipeline.push(Highlight);
+        }
+
+        for filter in &options.filters {
+            pipeline.push(filter.clone());
+        }
+
+        pipeline
+    }
+
+    /// Add the `transform` to the end of the pipeline
+    pub fn push(&mut self, transform: impl Transform + 'static) -> &mut Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Parse the `html` and apply all transforms to it in order, returning the document
+    pub fn apply(&self, html: String, context: &Context) -> NodeRef {
+        let document = kuchikiki::parse_html().one(html);
+
+        for transform in &self.transforms {
+            log::trace!("Applying transform: {}", transform.name());
+
+            transform.apply(&document, context);
+        }
+
+        document
+    }
+
+    /// [Self::apply()] and serialize the document again
+    pub fn run(&self, html: String, context: &Context) -> String {
+        let document = self.apply(html, context);
+
+        let mut output = Vec::new();
+        document
+            .serialize(&mut output)
+            .expect("Serialization should never fail, if it does there is a bug.");
+        String::from_utf8(output).expect("Converting document should never fail.")
+    }
+}
+
+/// Wrap the content of the `<body>` in a `<main>` element
+#[derive(Debug, Clone, Copy)]
+pub struct MainWrapper;
+
+impl Transform for MainWrapper {
+    fn name(&self) -> &str {
+        "main-wrapper"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        let body = document.select_first("body").unwrap();
+        let body = body.as_node();
+
+        let mut body_children = vec![];
+
+        body.children().for_each(|c| {
+            c.detach();
+            body_children.push(c);
+        });
+
+        let main = kuchikiki::NodeRef::new_element(
+            QualName::new(None, ns!(html), local_name!("main")),
+            [],
+        );
+
+        for c in body_children {
+            main.append(c);
+        }
+
+        body.append(main);
+    }
+}
+
+/// Add a `data-source-line` attribute to all block-level elements, containing the line of the
+/// markdown they were created from
+///
+/// The lines are taken from the mdast of [Context::md], whose blocks are matched up with the
+/// elements of the same tag in order. Elements from raw html get the line the html starts on.
+///
+/// Paragraphs in list items and footnotes are skipped, since the former are only rendered in
+/// loose lists and the latter are moved to the end of the document.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceLines;
+
+impl SourceLines {
+    /// Collect the lines of the blocks in `node`, by the tag of the element they become
+    fn collect(node: &mdast::Node, parent: Option<&mdast::Node>, lines: &mut SourceLineQueues) {
+        let line = node.position().map(|p| p.start.line);
+
+        let tag = match node {
+            mdast::Node::Heading(heading) => Some(format!("h{}", heading.depth)),
+            mdast::Node::Paragraph(_) if !matches!(parent, Some(mdast::Node::ListItem(_))) => {
+                Some("p".to_string())
+            }
+            mdast::Node::List(list) if list.ordered => Some("ol".to_string()),
+            mdast::Node::List(_) => Some("ul".to_string()),
+            mdast::Node::ListItem(_) => Some("li".to_string()),
+            mdast::Node::Blockquote(_) => Some("blockquote".to_string()),
+            mdast::Node::Code(_) | mdast::Node::Math(_) => Some("pre".to_string()),
+            mdast::Node::Table(_) => Some("table".to_string()),
+            mdast::Node::TableRow(_) => Some("tr".to_string()),
+            mdast::Node::ThematicBreak(_) => Some("hr".to_string()),
+            mdast::Node::FootnoteDefinition(_) => return,
+            mdast::Node::Html(html) => {
+                for fragment in parse_fragment(&html.value) {
+                    for element in fragment.inclusive_descendants() {
+                        if let Some(tag) = Self::tag(&element) {
+                            lines.entry(tag).or_default().push_back(line);
+                        }
+                    }
+                }
+
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(tag) = tag {
+            lines.entry(tag).or_default().push_back(line);
+        }
+
+        for child in node.children().into_iter().flatten() {
+            Self::collect(child, Some(node), lines);
+        }
+    }
+
+    /// The tag of the `element`, if it should get a source line
+    fn tag(element: &NodeRef) -> Option<String> {
+        let tag = element.as_element()?.name.local.to_string();
+
+        let is_block = matches!(
+            tag.as_str(),
+            "h1" | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "p"
+                | "ol"
+                | "ul"
+                | "li"
+                | "blockquote"
+                | "pre"
+                | "table"
+                | "tr"
+                | "hr"
+        );
+
+        let in_list_item = || {
+            element
+                .parent()
+                .and_then(|p| p.as_element().map(|e| e.name.local == local_name!("li")))
+                .unwrap_or(false)
+        };
+
+        let in_footnotes = || {
+            element.ancestors().any(|a| {
+                a.as_element()
+                    .is_some_and(|e| e.attributes.borrow().contains("data-footnotes"))
+            })
+        };
+
+        (is_block && !(tag == "p" && in_list_item()) && !in_footnotes()).then_some(tag)
+    }
+}
+
+/// Source lines of blocks, by the tag of the element they become. See: [SourceLines]
+type SourceLineQueues = HashMap<String, VecDeque<Option<usize>>>;
+
+impl Transform for SourceLines {
+    fn name(&self) -> &str {
+        "source-lines"
+    }
+
+    fn apply(&self, document: &NodeRef, context: &Context) {
+        let mdast = match markdown::to_mdast(context.md, &markdown_options(context.md).parse) {
+            Ok(mdast) => mdast,
+            Err(e) => {
+                log::warn!("Failed to parse markdown for source lines: {e}");
+                return;
+            }
+        };
+
+        let mut lines = SourceLineQueues::new();
+        Self::collect(&mdast, None, &mut lines);
+
+        for element in document.descendants() {
+            let Some(tag) = Self::tag(&element) else {
+                continue;
+            };
+
+            if let Some(line) = lines.get_mut(&tag).and_then(VecDeque::pop_front).flatten() {
+                element
+                    .as_element()
+                    .expect("Only elements have a tag.")
+                    .attributes
+                    .borrow_mut()
+                    .insert("data-source-line", line.to_string());
+            }
+        }
+    }
+}
+
+/// Add the classes GitHub uses for task lists
+#[derive(Debug, Clone, Copy)]
+pub struct TaskLists;
+
+impl Transform for TaskLists {
+    fn name(&self) -> &str {
+        "task-lists"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        // These are not part of the gfm spec, hence not added by the converter.
+        let checkboxes = document
+            .select("li input[type=\"checkbox\"]")
+            .expect("Selector is hard-coded.");
+
+        for checkbox in checkboxes {
+            let checkbox = checkbox.as_node();
+            let li = checkbox
+                .ancestors()
+                .find(|n| {
+                    n.as_element()
+                        .expect("We know this is an element.")
+                        .name
+                        .local
+                        .eq("li")
+                })
+                .expect("The selector determines that these exist");
+
+            let ul = li
+                .parent()
+                .expect("The selector determines that these exist");
+
+            if let Some(checkbox_data) = checkbox.as_element() {
+                let mut attributes = checkbox_data.attributes.borrow_mut();
+
+                attributes.insert("class".to_string(), "task-list-item-checkbox".to_string());
+            }
+
+            if let Some(li_data) = li.as_element() {
+                let mut attributes = li_data.attributes.borrow_mut();
+
+                attributes.insert("class".to_string(), "task-list-item".to_string());
+            }
+
+            if let Some(ul_data) = ul.as_element() {
+                let mut attributes = ul_data.attributes.borrow_mut();
+
+                attributes.insert("class".to_string(), "contains-task-list".to_string());
+            }
+        }
+    }
+}
+
+/// Make links to local markdown files and directories open them in the viewer
+#[derive(Debug, Clone, Copy)]
+pub struct MdLinks;
+
+impl Transform for MdLinks {
+    fn name(&self) -> &str {
+        "md-links"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        // The links are resolved by the server, see [crate::paths::resolve_md_link()]
+        let links = document.select("a[href]").expect("Selector is hard-coded.");
+
+        for link in links {
+            let mut attributes = link.attributes.borrow_mut();
+
+            let href = attributes
+                .get("href")
+                .expect("The selector ensures the existence of an href.")
+                .to_string();
+
+            if !is_md_link(&href) {
+                continue;
+            }
+
+            attributes.insert(
+                "onclick",
+                format!(
+                    "return handle_redirect({})",
+                    serde_json::to_string(&href).expect("Serializing a string never fails.")
+                ),
+            );
+        }
+    }
+}
+
+/// Turn blockquotes starting with e.g. `[!NOTE]` into GitHub-style alerts
+#[derive(Debug, Clone, Copy)]
+pub struct Alerts;
+
+impl Transform for Alerts {
+    fn name(&self) -> &str {
+        "alerts"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        let alerts_data: Vec<(kuchikiki::NodeDataRef<kuchikiki::ElementData>, String)> = document
+            .select(r#"main > blockquote > p:first-child"#)
+            .expect("Selector is hard-coded.")
+            .filter_map(|a| {
+                // We're getting the first text node of `a` to use in the regex
+                if let Some(text) = a.as_node().first_child().map(|c| c.text_contents()) {
+                    // If the regex matches then return `a` and the `alert-type` we matched
+                    if let Some(Some(alert_type)) = ALERT_REGEX.captures(&text).map(|c| c.get(1)) {
+                        return Some((a, alert_type.as_str().to_string()));
+                    }
+                }
+
+                None
+            })
+            .collect();
+
+        for (a, alert_type) in alerts_data {
+            let blockquote = a
+                .as_node()
+                .parent()
+                .expect("The selector ensures the existence of the blockquote.");
+
+            let mut blockquote_children: VecDeque<NodeRef> = blockquote
+                .children()
+                .filter(|c| c.text_contents() != "\n")
+                .collect();
+
+            // First p in the blockquote
+            let first_p = blockquote_children
+                .remove(0)
+                .expect("The selector ensures the existence of the p element");
+
+            let mut first_p_children = first_p.children();
+
+            // Split the first "text" of the first p element to get any text in the line after the
+            // alert title
+            //
+            // We need to do this since in a normal block quote the first line has no special meaning
+            // and will therefore be put into the same `p` as the text on the second line, if there is
+            // no empty line between the two.
+            let content_after_title = first_p_children
+                .next()
+                .and_then(|c| {
+                    c.text_contents()
+                        .split_once('\n')
+                        .map(|(_, after)| after.to_owned())
+                })
+                .unwrap_or_default();
+
+            // Make the div for the alert
+            //
+            // This replaces the blockquote
+            let alert_container_node = kuchikiki::NodeRef::new_element(
+                QualName::new(None, ns!(html), local_name!("div")),
+                [(
+                    kuchikiki::ExpandedName::new(ns!(), local_name!("class")),
+                    kuchikiki::Attribute {
+                        prefix: None,
+                        value: format!(
+                            "markdown-alert markdown-alert-{}",
+                            alert_type.as_str().to_lowercase()
+                        ),
+                    },
+                )],
+            );
+
+            copy_source_line(&blockquote, &alert_container_node);
+
+            // The node containing the icon and title
+            let title_node = kuchikiki::NodeRef::new_element(
+                QualName::new(None, ns!(html), local_name!("p")),
+                [(
+                    kuchikiki::ExpandedName::new(ns!(), local_name!("class")),
+                    kuchikiki::Attribute {
+                        prefix: None,
+                        value: "markdown-alert-title".to_string(),
+                    },
+                )],
+            );
+
+            // The fist p element after the title
+            let content_node = kuchikiki::NodeRef::new_element(
+                QualName::new(None, ns!(html), local_name!("p")),
+                None,
+            );
+
+            content_node.append(kuchikiki::NodeRef::new_text(content_after_title));
+
+            for child in first_p_children {
+                content_node.append(child);
+            }
+
+            let mut title = alert_type.to_lowercase();
+            title[..1].make_ascii_uppercase();
+
+            title_node.append(kuchikiki::parse_html().one(*SVGS.get(&*title).expect(
+                "We know this will never fail, since the regex will only match valid alert types",
+            )));
+
+            title_node.append(kuchikiki::NodeRef::new_text(title));
+
+            alert_container_node.append(title_node);
+            alert_container_node.append(content_node);
+
+            for child in blockquote_children {
+                alert_container_node.append(child);
+            }
+
+            // Replace the blockquote with the alert container
+            blockquote.insert_after(alert_container_node);
+            blockquote.detach();
+        }
+    }
+}
+
+/// Make local images and other media use an absolute `asset://` URI, resolved against the
+/// directory of [Context::md_path]
+#[derive(Debug, Clone, Copy)]
+pub struct MediaAssets;
+
+impl Transform for MediaAssets {
+    fn name(&self) -> &str {
+        "media-assets"
+    }
+
+    fn apply(&self, document: &NodeRef, context: &Context) {
+        let document_dir = paths::document_dir(context.md_path);
+
+        let media = document
+            .select("img[src], video[src], audio[src], source[src]")
+            .expect("Selector is hard-coded.");
+
+        for element in media {
+            if let Some(v) = element.attributes.borrow_mut().get_mut("src") {
+                // don't touch non local paths
+                if v.is_empty() || !is_local(v) {
+                    continue;
+                }
+
+                let path = paths::resolve_href(&document_dir, v);
+
+                *v = format!(
+                    "asset://{}",
+                    utf8_percent_encode(&path.to_string_lossy(), ASSET_PATH_SET)
+                );
+            }
+        }
+    }
+}
+
+/// Give headings GitHub-style `id`s and permalink anchors
+#[derive(Debug, Clone, Copy)]
+pub struct HeadingAnchors;
+
+impl Transform for HeadingAnchors {
+    fn name(&self) -> &str {
+        "heading-anchors"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        let headings = document
+            .select("h1, h2, h3, h4, h5, h6")
+            .expect("Selector is hard-coded.");
+
+        let mut slugger = Slugger::default();
+
+        for heading in headings {
+            let slug = {
+                let mut attributes = heading.attributes.borrow_mut();
+
+                // Respect ids set via raw html
+                if let Some(id) = attributes.get("id") {
+                    id.to_string()
+                } else {
+                    let slug = slugger.slug(&heading.text_contents());
+                    attributes.insert("id", slug.clone());
+                    slug
+                }
+            };
+
+            let anchor = kuchikiki::NodeRef::new_element(
+                QualName::new(None, ns!(html), local_name!("a")),
+                [
+                    (
+                        kuchikiki::ExpandedName::new(ns!(), local_name!("class")),
+                        kuchikiki::Attribute {
+                            prefix: None,
+                            value: "anchor".to_string(),
+                        },
+                    ),
+                    (
+                        kuchikiki::ExpandedName::new(ns!(), local_name!("aria-hidden")),
+                        kuchikiki::Attribute {
+                            prefix: None,
+                            value: "true".to_string(),
+                        },
+                    ),
+                    (
+                        kuchikiki::ExpandedName::new(ns!(), local_name!("href")),
+                        kuchikiki::Attribute {
+                            prefix: None,
+                            value: format!("#{slug}"),
+                        },
+                    ),
+                ],
+            );
+
+            anchor.append(kuchikiki::parse_html().one(LINK_SVG));
+
+            heading.as_node().prepend(anchor);
+        }
+    }
+}
+
+/// Render math to MathML using [math::tex_to_mathml]
+#[derive(Debug, Clone, Copy)]
+pub struct Math;
+
+impl Transform for Math {
+    fn name(&self) -> &str {
+        "math"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        // This needs to happen before highlighting, so math blocks aren't mistaken for code.
+        let math_code: Vec<NodeRef> = document
+            .select("code.language-math")
+            .expect("Selector is hard-coded.")
+            .map(|c| c.as_node().clone())
+            .collect();
+
+        for code in math_code {
+            let tex = code.text_contents();
+
+            // Display math is wrapped in a `<pre>`, which is replaced as well
+            let (node, display) = match code.parent() {
+                Some(pre)
+                    if pre
+                        .as_element()
+                        .is_some_and(|e| e.name.local == local_name!("pre")) =>
+                {
+                    (pre, true)
+                }
+                _ => (code, false),
+            };
+
+            let replacement = match math::tex_to_mathml(&tex, display) {
+                Ok(mathml) => parse_fragment(&mathml),
+                Err(e) => {
+                    log::debug!("Failed to render math `{tex}`: {e}");
+
+                    vec![math_error(&tex, &e)]
+                }
+            };
+
+            if let Some(first) = replacement.first() {
+                copy_source_line(&node, first);
+            }
+
+            for r in replacement {
+                node.insert_before(r);
+            }
+
+            node.detach();
+        }
+    }
+}
+
+/// Highlight code blocks using [highlight::highlight]
+#[derive(Debug, Clone, Copy)]
+pub struct Highlight;
+
+impl Transform for Highlight {
+    fn name(&self) -> &str {
+        "highlight"
+    }
+
+    fn apply(&self, document: &NodeRef, _context: &Context) {
+        let code_blocks = document
+            .select(r#"pre > code[class*="language-"]"#)
+            .expect("Selector is hard-coded.");
+
+        for code_block in code_blocks {
+            let mut attributes = code_block.attributes.borrow_mut();
+
+            let Some(lang) = attributes.get("class").and_then(|c| {
+                c.split_whitespace()
+                    .find_map(|c| c.strip_prefix("language-"))
+            }) else {
+                continue;
+            };
+
+            let Some(tokens) = highlight::highlight(&code_block.text_contents(), lang) else {
+                continue;
+            };
+
+            let code_node = code_block.as_node();
+
+            code_node.children().for_each(|c| c.detach());
+
+            for token in tokens {
+                let text = kuchikiki::NodeRef::new_text(token.text);
+
+                let Some(class) = token.class else {
+                    code_node.append(text);
+                    continue;
+                };
+
+                let span = kuchikiki::NodeRef::new_element(
+                    QualName::new(None, ns!(html), local_name!("span")),
+                    [(
+                        kuchikiki::ExpandedName::new(ns!(), local_name!("class")),
+                        kuchikiki::Attribute {
+                            prefix: None,
+                            value: class.to_string(),
+                        },
+                    )],
+                );
+
+                span.append(text);
+                code_node.append(span);
+            }
+
+            let class = format!("{} hljs", attributes.get("class").unwrap_or_default());
+            attributes.insert("class", class);
+            // Same as highlight.js, so it doesn't highlight the code a second time
+            attributes.insert("data-highlighted", "yes".to_string());
+        }
+    }
+}
+
+/// Whether the `href` points to a local file, rather than a website or an anchor
+fn is_local(href: &str) -> bool {
+    !(href.starts_with('#') || href.starts_with("//") || SCHEME_REGEX.is_match(href))
+}
+
+/// Whether the `href` links to a local markdown file or a directory, which may contain one
+fn is_md_link(href: &str) -> bool {
+    let path = href.split(['#', '?']).next().unwrap_or_default();
+
+    is_local(href)
+        && (path.ends_with(".md")
+            || path.ends_with(".markdown")
+            || path.ends_with('/')
+            || path == "."
+            || path == "..")
+}
+
+/// Copy the `data-source-line` attribute of `from` to `to`, if both are elements
+///
+/// Used when replacing elements, so the replacement still has a source line. See: [SourceLines]
+fn copy_source_line(from: &NodeRef, to: &NodeRef) {
+    let Some(line) = from.as_element().and_then(|e| {
+        e.attributes
+            .borrow()
+            .get("data-source-line")
+            .map(str::to_string)
+    }) else {
+        return;
+    };
+
+    if let Some(to) = to.as_element() {
+        to.attributes.borrow_mut().insert("data-source-line", line);
+    }
+}
+
+/// Parse the given html fragment into nodes, which can be inserted into a document
+fn parse_fragment(html: &str) -> Vec<NodeRef> {
+    kuchikiki::parse_html()
+        .one(html)
+        .select_first("body")
+        .expect("The parser always creates a body.")
+        .as_node()
+        .children()
+        .collect()
+}
+
+/// Create the inline element shown in place of math, which couldn't be rendered
+///
+/// It shows the original `tex`, with the `error` as its title.
+fn math_error(tex: &str, error: &math::MathError) -> NodeRef {
+    let span = kuchikiki::NodeRef::new_element(
+        QualName::new(None, ns!(html), local_name!("span")),
+        [
+            (
+                kuchikiki::ExpandedName::new(ns!(), local_name!("class")),
+                kuchikiki::Attribute {
+                    prefix: None,
+                    value: "math-error".to_string(),
+                },
+            ),
+            (
+                kuchikiki::ExpandedName::new(ns!(), local_name!("title")),
+                kuchikiki::Attribute {
+                    prefix: None,
+                    value: error.to_string(),
+                },
+            ),
+            (
+                kuchikiki::ExpandedName::new(ns!(), local_name!("style")),
+                kuchikiki::Attribute {
+                    prefix: None,
+                    value: "color: #cc0000".to_string(),
+                },
+            ),
+        ],
+    );
+
+    let code =
+        kuchikiki::NodeRef::new_element(QualName::new(None, ns!(html), local_name!("code")), None);
+    code.append(kuchikiki::NodeRef::new_text(tex.trim()));
+    span.append(code);
+
+    span
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Context, MainWrapper, Pipeline, Transform};
+    use kuchikiki::NodeRef;
+    use std::path::Path;
+
+    /// Appends its name to the text of the `<main>`
+    #[derive(Debug)]
+    struct Append(&'static str);
+
+    impl Transform for Append {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn apply(&self, document: &NodeRef, _context: &Context) {
+            document
+                .select_first("main")
+                .expect("MainWrapper runs first.")
+                .as_node()
+                .append(NodeRef::new_text(self.0));
+        }
+    }
+
+    #[test]
+    fn order() {
+        let mut pipeline = Pipeline::default();
+        pipeline
+            .push(MainWrapper)
+            .push(Append("a"))
+            .push(Append("b"));
+
+        let html = pipeline.run(
+            "<p>Text</p>".to_string(),
+            &Context {
+                md_path: Path::new("/docs/test.md"),
+                md: "Text",
+            },
+        );
+
+        assert!(html.contains("<main><p>Text</p>ab</main>"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Rendered documents shared by all clients. See: [Documents]
+//!
+//! Clients viewing the same file share its render, so a file is only converted to html once per
+//! change, no matter how many clients view it.
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::convert::{Heading, blocks::Block};
+
+/// A markdown document converted to html
+#[derive(Debug, Default)]
+pub struct Rendered {
+    /// The html `<main>` element
+    pub html: String,
+    /// The outline of [Self::html]
+    pub outline: Vec<Heading>,
+    /// The blocks of [Self::html], if it can be split into blocks
+    pub blocks: Option<Vec<Block>>,
+}
+
+/// The last render of a document, with the hash of the content it was rendered from
+type Slot = Arc<Mutex<Option<(u64, Arc<Rendered>)>>>;
+
+/// A document with at least one [Subscription]
+#[derive(Debug, Default)]
+struct Entry {
+    /// Number of [Subscription]s to the document
+    subscribers: usize,
+    /// See [Slot]
+    rendered: Slot,
+}
+
+/// The renders of all documents viewed by clients of a server
+///
+/// Documents are kept until their last [Subscription] is dropped.
+#[derive(Debug, Default)]
+pub struct Documents {
+    /// The documents by the canonical path of their files
+    documents: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl Documents {
+    /// Subscribe to the renders of the file at `path`, until the returned [Subscription] is
+    /// dropped
+    pub fn subscribe(self: &Arc<Self>, path: &Path) -> Subscription {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        let rendered = {
+            let mut documents = self.documents.lock().unwrap();
+            let entry = documents.entry(path.clone()).or_default();
+
+            entry.subscribers += 1;
+
+            Arc::clone(&entry.rendered)
+        };
+
+        Subscription {
+            documents: Arc::clone(self),
+            renderer: Renderer { path, rendered },
+        }
+    }
+
+    /// Decrement the subscribers of the document at `path`, evicting it if there are none left
+    fn unsubscribe(&self, path: &Path) {
+        let mut documents = self.documents.lock().unwrap();
+
+        if let Some(entry) = documents.get_mut(path) {
+            entry.subscribers -= 1;
+
+            if entry.subscribers == 0 {
+                documents.remove(path);
+
+                log::debug!("Evicted render of {}", path.display());
+            }
+        }
+    }
+}
+
+/// Keeps the render of a document in [Documents] until it is dropped
+#[derive(Debug)]
+pub struct Subscription {
+    /// The store the document is kept in
+    documents: Arc<Documents>,
+    /// Renders the document
+    renderer: Renderer,
+}
+
+impl Subscription {
+    /// Get the canonical path of the document
+    ///
+    /// The path is canonicalized once when subscribing, falling back to the given path if the
+    /// file doesn't exist.
+    pub fn path(&self) -> &Path {
+        &self.renderer.path
+    }
+
+    /// Get a [Renderer] for the document
+    ///
+    /// The renderer can be moved to another thread to render there, without keeping the document
+    /// subscribed.
+    pub fn renderer(&self) -> Renderer {
+        self.renderer.clone()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.documents.unsubscribe(&self.renderer.path);
+    }
+}
+
+/// Renders a document of [Documents], see [Subscription::renderer()]
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    /// Canonical path of the document
+    path: PathBuf,
+    /// See [Slot]
+    rendered: Slot,
+}
+
+impl Renderer {
+    /// Get the render of the document for the given `content`
+    ///
+    /// `render` is only called if the document wasn't rendered from the same `content` yet.
+    /// `content` has to contain everything the render depends on, such as the content of included
+    /// files and the options it is converted with. While `render` runs, other subscribers of the
+    /// document wait for it rather than rendering the same content themselves.
+    ///
+    /// If `render` panics, the previous render is kept and used by the other subscribers.
+    pub fn render(&self, content: impl Hash, render: impl FnOnce() -> Rendered) -> Arc<Rendered> {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // A panicking render doesn't replace the previous render, which is therefore still valid
+        let mut rendered = self.rendered.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some((h, rendered)) = &*rendered
+            && *h == hash
+        {
+            log::debug!("Using cached render of {}", self.path.display());
+
+            return Arc::clone(rendered);
+        }
+
+        let new = Arc::new(render());
+        *rendered = Some((hash, Arc::clone(&new)));
+
+        new
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Documents, Rendered};
+    use std::{panic, path::Path, sync::Arc};
+
+    fn rendered(html: &str) -> Rendered {
+        Rendered {
+            html: html.to_string(),
+            outline: Vec::new(),
+            blocks: None,
+        }
+    }
+
+    #[test]
+    fn shared_renders() {
+        let documents = Arc::new(Documents::default());
+        let path = Path::new("/does/not/exist.md");
+
+        let first = documents.subscribe(path);
+        let second = documents.subscribe(path);
+
+        let a = first.renderer().render("# A", || rendered("<h1>A</h1>"));
+        // the same content is only rendered once
+        let b = second
+            .renderer()
+            .render("# A", || panic!("The render should be shared."));
+        assert!(Arc::ptr_eq(&a, &b));
+
+        let changed = second.renderer().render("# B", || rendered("<h1>B</h1>"));
+        assert_eq!(changed.html, "<h1>B</h1>");
+        assert_eq!(
+            first.renderer().render("# B", || panic!()).html,
+            "<h1>B</h1>"
+        );
+
+        // the document is evicted once the last subscriber leaves
+        drop(first);
+        assert_eq!(documents.documents.lock().unwrap().len(), 1);
+        drop(second);
+        assert!(documents.documents.lock().unwrap().is_empty());
+
+        let third = documents.subscribe(path);
+        assert_eq!(
+            third.renderer().render("# B", || rendered("new")).html,
+            "new"
+        );
+    }
+
+    #[test]
+    fn panicking_render() {
+        let documents = Arc::new(Documents::default());
+        let subscription = documents.subscribe(Path::new("/does/not/exist.md"));
+        let renderer = subscription.renderer();
+
+        renderer.render("# A", || rendered("<h1>A</h1>"));
+
+        let result = panic::catch_unwind(|| renderer.render("# B", || panic!("Render failed")));
+        assert!(result.is_err());
+
+        // the previous render is kept and later renders still work
+        assert_eq!(renderer.render("# A", || panic!()).html, "<h1>A</h1>");
+        assert_eq!(
+            renderer.render("# B", || rendered("<h1>B</h1>")).html,
+            "<h1>B</h1>"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Addresses servers listen on and clients connect to. See: [Endpoint]
+//!
+//! Servers listen either on a TCP port of `127.0.0.1`, which every local user can connect to, or
+//! on a unix domain socket, which only the user can connect to. The websocket protocol is the same
+//! for both.
+use std::{
+    fmt::{self, Display},
+    io,
+    num::ParseIntError,
+    path::PathBuf,
+    str::FromStr,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::token::Token;
+
+/// A stream of a connection to or from an [Endpoint]
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection for T {}
+
+/// Where a server listens for connections
+///
+/// Written as the port for [Self::Tcp], and as `unix:` followed by the path for [Self::Unix],
+/// e.g. in [crate::instance::Instance::port_file()].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// The port on `127.0.0.1`
+    ///
+    /// If this is 0 when launching a server, a free port is used instead.
+    Tcp(u16),
+    /// Path to a unix domain socket
+    Unix(PathBuf),
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(port) => write!(f, "{port}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => s.parse().map(Self::Tcp),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Open a websocket to the server on this endpoint, sending it the `query` and `token`
+    ///
+    /// See [crate::ws::handshake::WsQueryParams] for the parameters of the `query`.
+    pub async fn connect(
+        &self,
+        query: &str,
+        token: Option<&Token>,
+    ) -> io::Result<WebSocketStream<Box<dyn Connection>>> {
+        let (stream, host): (Box<dyn Connection>, _) = match self {
+            Self::Tcp(port) => (
+                Box::new(TcpStream::connect(("127.0.0.1", *port)).await?),
+                format!("127.0.0.1:{port}"),
+            ),
+            #[cfg(unix)]
+            Self::Unix(path) => (
+                Box::new(tokio::net::UnixStream::connect(path).await?),
+                "localhost".to_string(),
+            ),
+            #[cfg(not(unix))]
+            Self::Unix(_) => return Err(unix_unsupported()),
+        };
+
+        let token = token.map(|t| format!("&token={t}")).unwrap_or_default();
+
+        let (socket, _) =
+            tokio_tungstenite::client_async(format!("ws://{host}/ws/?{query}{token}"), stream)
+                .await
+                .map_err(io::Error::other)?;
+
+        Ok(socket)
+    }
+}
+
+/// Listens for connections on an [Endpoint]
+#[derive(Debug)]
+pub enum Listener {
+    /// See [Endpoint::Tcp]
+    Tcp(TcpListener),
+    /// See [Endpoint::Unix]
+    ///
+    /// The socket is removed once this is dropped.
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Start listening on the `endpoint`
+    ///
+    /// Sockets are only accessible by the user. A socket already at the path is replaced, so
+    /// check that no server is listening on it first.
+    pub async fn bind(endpoint: &Endpoint) -> io::Result<Self> {
+        match endpoint {
+            Endpoint::Tcp(port) => Ok(Self::Tcp(
+                TcpListener::bind(format!("127.0.0.1:{port}")).await?,
+            )),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                use std::{fs, os::unix::fs::FileTypeExt};
+
+                // Only sockets are removed, anything else at the path is kept
+                if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    log::debug!("Removing stale socket {}", path.display());
+
+                    fs::remove_file(path)?;
+                }
+
+                Ok(Self::Unix(bind_private(path)?, path.clone()))
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    /// Get the endpoint the listener is listening on
+    ///
+    /// Unlike the endpoint it was bound to, this contains the actual port for [Endpoint::Tcp].
+    pub fn endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Self::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?.port())),
+            #[cfg(unix)]
+            Self::Unix(_, path) => Ok(Endpoint::Unix(path.clone())),
+        }
+    }
+
+    /// Accept the next connection
+    pub async fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self {
+            Self::Tcp(listener) => Ok(Box::new(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Self::Unix(listener, _) => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Unix(_, path) = &*self
+            && let Err(e) = std::fs::remove_file(path)
+        {
+            log::warn!("Failed to remove socket {}: {e}", path.display());
+        }
+    }
+}
+
+/// Bind a unix socket at `path`, which only the user can connect to
+///
+/// Sockets are created with the permissions of the umask, so the socket is bound in a directory
+/// only the user can access first. Once it is private, it is linked to the `path`, which fails if
+/// anything is there already.
+#[cfg(unix)]
+fn bind_private(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::{
+        fs,
+        os::unix::fs::{DirBuilderExt, PermissionsExt},
+    };
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = path.with_file_name(format!(".{file_name}.{}", std::process::id()));
+
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let tmp = dir.join("socket");
+    let listener = tokio::net::UnixListener::bind(&tmp).and_then(|listener| {
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        fs::hard_link(&tmp, path)?;
+
+        Ok(listener)
+    });
+
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        log::warn!("Failed to remove {}: {e}", dir.display());
+    }
+
+    listener
+}
+
+/// Error returned when using [Endpoint::Unix] on platforms without unix domain sockets
+#[cfg(not(unix))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix domain sockets aren't supported on this platform",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Endpoint, Listener};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse() {
+        for endpoint in [
+            Endpoint::Tcp(4242),
+            Endpoint::Unix(PathBuf::from("/run/user/1000/igneous-md/docs.sock")),
+        ] {
+            assert_eq!(endpoint.to_string().parse(), Ok(endpoint));
+        }
+
+        assert!("not a port".parse::<Endpoint>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("socket.sock");
+        let endpoint = Endpoint::Unix(path.clone());
+
+        // A socket left behind, e.g. by a crashed server, is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let listener = Listener::bind(&endpoint).await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let accepted = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap();
+            tokio_tungstenite::accept_async(stream).await.unwrap();
+
+            listener
+        });
+
+        endpoint.connect("md_path=/dev/null", None).await.unwrap();
+
+        drop(accepted.await.unwrap());
+        assert!(!path.exists());
+
+        // anything else at the path is kept
+        std::fs::write(&path, "file").unwrap();
+        assert!(Listener::bind(&endpoint).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "file");
+
+        // the directory the socket is bound in is removed
+        assert_eq!(std::fs::read_dir(temp.path()).unwrap().count(), 1);
+    }
+}
@@ -10,7 +10,7 @@ use std::{
 
 use thiserror::Error;
 
-use crate::config::generate::urls;
+use crate::{config::generate::urls, endpoint::Endpoint};
 
 /// Top-level errors that may occur when running different actions
 ///
@@ -61,6 +61,19 @@ pub enum Error {
     /// Failed to launch the server
     #[error("Failed to launch the backend server")]
     ServerLaunchFailed(#[source] io::Error),
+    /// A server is already running, but was launched with other options than requested
+    #[error(
+        "The server running on {0} was launched with different options (--safe, --filter, \
+        --server-math, --server-highlight, --poll or the config.toml). Stop it or launch another \
+        server using --instance or --socket."
+    )]
+    ServerOptionsDiffer(Endpoint),
+    /// Failed to read the markdown from stdin
+    #[error("Failed to read markdown from stdin")]
+    StdinFailed(#[source] io::Error),
+    /// Failed to show the markdown from stdin before converting it
+    #[error("Failed to show markdown from stdin for the conversion")]
+    StdinShowFailed,
     /// Failed to register the ctrl_c signal
     #[error("Failed to register the ctrl_c signal to wait for exit")]
     SignalFailed(#[source] io::Error),
@@ -0,0 +1,135 @@
+//! Navigation history of a client. See: [History]
+//!
+//! Following links between markdown files pushes them onto the history, which can then be moved
+//! through using [crate::ws::msg::ClientMsg::Back] and [crate::ws::msg::ClientMsg::Forward].
+use std::path::PathBuf;
+
+/// A file in the [History]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path to the `.md` on disk
+    pub path: PathBuf,
+    /// The line of the markdown the client was last at, see [History::set_line()]
+    ///
+    /// Lines are used instead of pixel offsets, so the position survives changes to the file
+    /// and the size of the viewer.
+    pub line: usize,
+}
+
+impl Entry {
+    /// Create an entry for the top of the file at `path`
+    fn new(path: PathBuf) -> Self {
+        Self { path, line: 1 }
+    }
+}
+
+/// The files a client has viewed, like the history of a browser tab
+#[derive(Debug, Clone)]
+pub struct History {
+    /// All entries, from oldest to newest
+    ///
+    /// Never empty.
+    entries: Vec<Entry>,
+    /// Index of the entry currently viewed
+    current: usize,
+}
+
+impl History {
+    /// Create a history starting at the file at `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            entries: vec![Entry::new(path)],
+            current: 0,
+        }
+    }
+
+    /// Get the entry currently viewed
+    pub fn current(&self) -> &Entry {
+        &self.entries[self.current]
+    }
+
+    /// Navigate to the file at `path`
+    ///
+    /// All entries after the current one are dropped. Navigating to the file which is already
+    /// viewed doesn't add an entry.
+    pub fn push(&mut self, path: PathBuf) {
+        if self.current().path == path {
+            return;
+        }
+
+        self.entries.truncate(self.current + 1);
+        self.entries.push(Entry::new(path));
+        self.current += 1;
+    }
+
+    /// Replace the path of the current entry, e.g. because its file was moved
+    pub fn replace(&mut self, path: PathBuf) {
+        self.entries[self.current].path = path;
+    }
+
+    /// Remember that the client is at `line` of the current file
+    pub fn set_line(&mut self, line: usize) {
+        self.entries[self.current].line = line;
+    }
+
+    /// Go to the previous entry, returning it
+    ///
+    /// Returns [None] if there is no previous entry.
+    pub fn back(&mut self) -> Option<&Entry> {
+        self.current = self.current.checked_sub(1)?;
+
+        Some(self.current())
+    }
+
+    /// Go to the next entry, returning it
+    ///
+    /// Returns [None] if there is no next entry.
+    pub fn forward(&mut self) -> Option<&Entry> {
+        if self.current + 1 >= self.entries.len() {
+            return None;
+        }
+
+        self.current += 1;
+
+        Some(self.current())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, History};
+    use std::path::PathBuf;
+
+    fn entry(path: &str, line: usize) -> Option<Entry> {
+        Some(Entry {
+            path: PathBuf::from(path),
+            line,
+        })
+    }
+
+    #[test]
+    fn navigation() {
+        let mut history = History::new(PathBuf::from("/a.md"));
+
+        assert_eq!(history.back(), None);
+        assert_eq!(history.forward(), None);
+
+        history.set_line(10);
+        history.push(PathBuf::from("/b.md"));
+        history.set_line(20);
+        history.push(PathBuf::from("/c.md"));
+        // the current file isn't added again
+        history.push(PathBuf::from("/c.md"));
+
+        assert_eq!(history.back().cloned(), entry("/b.md", 20));
+        assert_eq!(history.back().cloned(), entry("/a.md", 10));
+        assert_eq!(history.back(), None);
+        assert_eq!(history.forward().cloned(), entry("/b.md", 20));
+
+        // navigating drops the entries after the current one
+        history.push(PathBuf::from("/d.md"));
+        assert_eq!(history.forward(), None);
+        assert_eq!(history.current().path, PathBuf::from("/d.md"));
+        assert_eq!(history.back().cloned(), entry("/b.md", 20));
+    }
+}
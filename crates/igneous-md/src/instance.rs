@@ -0,0 +1,365 @@
+//! Named server instances, so several servers can run at once. See: [Instance]
+//!
+//! A server claims its instance by locking a file in [runtime::RUNTIME_DIR] and writes the
+//! [Endpoint] it is listening on and its [Token] next to it. The files are shared with the viewer,
+//! see [igneous_md_viewer::runtime].
+use igneous_md_viewer::runtime::{self, InstanceFiles};
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::{endpoint::Endpoint, paths, token::Token};
+
+/// Name of the instance used if none is given
+pub const DEFAULT_INSTANCE: &str = "default";
+
+/// How often [Instance::claim()] tries to lock the instance, 10ms apart
+const CLAIM_ATTEMPTS: u32 = 10;
+
+/// A server instance, identified by its name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    /// The name only contains ASCII alphanumerics, `-`, `_` and `.`, and doesn't start with `.`
+    files: InstanceFiles,
+}
+
+/// Error returned when parsing an [Instance] from an invalid name
+#[derive(Debug, Clone, Error)]
+#[error(
+    "Invalid instance name `{0}`. Only letters, digits, `-`, `_` and `.` are allowed, and it may \
+     not start with `.`"
+)]
+pub struct InvalidInstanceName(String);
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            files: InstanceFiles::new(DEFAULT_INSTANCE),
+        }
+    }
+}
+
+impl FromStr for Instance {
+    type Err = InvalidInstanceName;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if name.is_empty() || name.starts_with('.') || !name.chars().all(is_name_char) {
+            return Err(InvalidInstanceName(name.to_string()));
+        }
+
+        Ok(Self {
+            files: InstanceFiles::new(name),
+        })
+    }
+}
+
+impl Instance {
+    /// Get the instance of the project the markdown file at `md_path` belongs to
+    ///
+    /// The project is the closest directory containing a `.git`, or the directory of the file if
+    /// there is none. The instance is named after the project directory, with a hash of its path,
+    /// so projects of the same name don't share an instance.
+    pub fn for_project(md_path: &Path) -> Self {
+        let dir = paths::document_dir(md_path);
+        let root = dir
+            .ancestors()
+            .find(|d| d.join(".git").exists())
+            .unwrap_or(&dir);
+
+        let mut hasher = DefaultHasher::new();
+        root.hash(&mut hasher);
+
+        let dir_name: String = root
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if is_name_char(c) && c != '.' { c } else { '_' })
+            .collect();
+
+        Self {
+            files: InstanceFiles::new(&format!("{dir_name}-{:08x}", hasher.finish() as u32)),
+        }
+    }
+
+    /// Get the name of the instance
+    pub fn name(&self) -> &str {
+        self.files.name()
+    }
+
+    /// Get the file the server of this instance writes its [Endpoint] to
+    pub fn port_file(&self) -> PathBuf {
+        self.files.port_file()
+    }
+
+    /// Get the file the server of this instance writes its [Token] to
+    pub fn token_file(&self) -> PathBuf {
+        self.files.token_file()
+    }
+
+    /// Write a page redirecting browsers to the `url` of the viewer, see
+    /// [InstanceFiles::write_redirect()]
+    ///
+    /// The page is removed once the server of this instance exits.
+    pub fn write_redirect(&self, url: &str) -> io::Result<PathBuf> {
+        self.files.write_redirect(url)
+    }
+
+    /// Read the endpoint of the server of this instance
+    ///
+    /// Returns [None] if no server is running, removing the port file if it was left behind.
+    pub fn read_endpoint(&self) -> Option<Endpoint> {
+        let endpoint = self.files.read_endpoint()?.parse::<Endpoint>();
+
+        if endpoint.is_err() {
+            log::debug!(
+                "{} is invalid. Attempting to delete.",
+                self.port_file().display()
+            );
+
+            self.files.remove_port_file();
+        }
+
+        endpoint.ok()
+    }
+
+    /// Read the token of the server of this instance
+    ///
+    /// Returns [None] if the server doesn't need one, see [Token]. Read it after
+    /// [Self::read_endpoint()], which removes the files of servers which are gone.
+    pub fn read_token(&self) -> Option<Token> {
+        self.files.read_token()?.parse().ok()
+    }
+
+    /// Claim this instance for a server
+    ///
+    /// Fails with [io::ErrorKind::AddrInUse] if another server has claimed it. The instance is
+    /// released once the returned [InstanceLock] is dropped.
+    pub fn claim(&self) -> io::Result<InstanceLock> {
+        runtime::create_private_dir(self.files.dir())?;
+
+        let mut options = OpenOptions::new();
+        options.create(true).truncate(false).write(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let file = options.open(self.files.lock_file())?;
+
+        // Checking if a server is running takes the lock for a moment, see
+        // [InstanceFiles::is_running()]
+        let mut attempts = 1;
+
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if attempts < CLAIM_ATTEMPTS => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("Instance `{}` is already running", self.name()),
+                    ));
+                }
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+        }
+
+        Ok(InstanceLock {
+            _lock: file,
+            port_file: self.port_file(),
+            token_file: self.token_file(),
+            redirect_file: self.files.redirect_file(),
+        })
+    }
+}
+
+/// Keeps an [Instance] claimed, see [Instance::claim()]
+#[derive(Debug)]
+pub struct InstanceLock {
+    /// The locked file, which unlocks when it is closed
+    _lock: File,
+    /// See [Instance::port_file()]
+    port_file: PathBuf,
+    /// See [Instance::token_file()]
+    token_file: PathBuf,
+    /// See [Instance::write_redirect()]
+    redirect_file: PathBuf,
+}
+
+impl InstanceLock {
+    /// Write the `endpoint` the server is listening on to the port file, and its `token` to the
+    /// token file
+    ///
+    /// The files are only readable by the user. The token is written first, so it is there once
+    /// the port file is.
+    pub fn write_endpoint(&self, endpoint: &Endpoint, token: Option<&Token>) -> io::Result<()> {
+        match token {
+            Some(token) => write_private(&self.token_file, token.as_str())?,
+            // A token left behind by a crashed server
+            None => remove(&self.token_file, "token file"),
+        }
+
+        write_private(&self.port_file, &endpoint.to_string())
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        remove(&self.port_file, "port file");
+        remove(&self.token_file, "token file");
+        remove(&self.redirect_file, "redirect file");
+    }
+}
+
+/// Write the `content` to a file at `path` only readable by the user
+///
+/// The file is replaced at once, so it is never read half-written.
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(&tmp)?.write_all(content.as_bytes())?;
+
+    fs::rename(tmp, path)
+}
+
+/// Remove the `file` at `path`, if it exists
+fn remove(path: &Path, file: &str) {
+    if let Err(e) = fs::remove_file(path)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        log::warn!("Failed to remove {file}: {e}");
+    }
+}
+
+/// Whether `c` may be part of the name of an [Instance]
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+#[cfg(test)]
+mod test {
+    use super::Instance;
+    use crate::{endpoint::Endpoint, token::Token};
+    use igneous_md_viewer::runtime::InstanceFiles;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn names() {
+        assert!("docs".parse::<Instance>().is_ok());
+        assert!("my_project-2.1".parse::<Instance>().is_ok());
+        assert!("".parse::<Instance>().is_err());
+        assert!(".hidden".parse::<Instance>().is_err());
+        assert!("../escape".parse::<Instance>().is_err());
+        assert!("a b".parse::<Instance>().is_err());
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("project.dir");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::create_dir_all(dir.join("docs")).unwrap();
+
+        // files anywhere in the project share the instance
+        let instance = Instance::for_project(&dir.join("docs/a.md"));
+        assert_eq!(instance, Instance::for_project(&dir.join("b.md")));
+        assert_ne!(instance, Instance::for_project(Path::new("/other/b.md")));
+        assert!(instance.name().parse::<Instance>().is_ok());
+        assert!(instance.name().starts_with("project_dir-"));
+    }
+
+    #[test]
+    fn claims() {
+        let temp = tempfile::tempdir().unwrap();
+        let instance = Instance {
+            files: InstanceFiles::in_dir(temp.path().join("runtime"), "test"),
+        };
+
+        assert_eq!(instance.read_endpoint(), None);
+
+        let lock = instance.claim().unwrap();
+        let token = Token::generate().unwrap();
+        lock.write_endpoint(&Endpoint::Tcp(4242), Some(&token))
+            .unwrap();
+
+        // only one server can run as the instance
+        assert!(instance.claim().is_err());
+        drop(lock);
+
+        // checking if a server is running doesn't keep one from claiming the instance
+        let probe = fs::File::open(instance.files.lock_file()).unwrap();
+        probe.lock().unwrap();
+        let unlock = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            probe.unlock().unwrap();
+        });
+        let lock = instance.claim().unwrap();
+        unlock.join().unwrap();
+        lock.write_endpoint(&Endpoint::Tcp(4242), Some(&token))
+            .unwrap();
+
+        assert_eq!(instance.read_endpoint(), Some(Endpoint::Tcp(4242)));
+        assert_eq!(instance.read_token(), Some(token));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(instance.token_file())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // browsers are redirected to the url with the token
+        let redirect = instance
+            .write_redirect("http://localhost:4242/?path=a.md&token=secret")
+            .unwrap();
+        assert!(
+            fs::read_to_string(&redirect)
+                .unwrap()
+                .contains(r#"url=http://localhost:4242/?path=a.md&amp;token=secret""#)
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&redirect).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // a port file left behind by a server which is gone is stale
+        let stale = fs::read(instance.port_file()).unwrap();
+        drop(lock);
+        assert!(!instance.port_file().exists());
+        assert!(!instance.token_file().exists());
+        assert!(!redirect.exists());
+
+        fs::write(instance.port_file(), stale).unwrap();
+        assert_eq!(instance.read_endpoint(), None);
+        assert!(!instance.port_file().exists());
+
+        // as is an invalid one
+        let lock = instance.claim().unwrap();
+        fs::write(instance.port_file(), "invalid").unwrap();
+        assert_eq!(instance.read_endpoint(), None);
+        assert!(!instance.port_file().exists());
+        drop(lock);
+    }
+}
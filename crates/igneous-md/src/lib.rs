@@ -0,0 +1,21 @@
+//! igneous-md | the simple and lightweight markdown viewer
+//!
+//! The library behind the `igneous-md` binary, containing the server and the conversion from
+//! markdown to html. It is split from the binary, so the conversion can be benchmarked.
+pub mod buffers;
+pub mod client;
+pub mod config;
+pub mod convert;
+pub mod documents;
+pub mod endpoint;
+pub mod errors;
+pub mod history;
+pub mod instance;
+pub mod paths;
+pub mod registry;
+pub mod server;
+pub mod stdin;
+pub mod token;
+pub mod watcher;
+pub mod web;
+pub mod ws;
@@ -15,30 +15,34 @@ use simple_logger::SimpleLogger;
 use std::fs;
 
 mod cli;
-mod client;
-mod config;
-mod convert;
-mod errors;
-mod paths;
-mod server;
-mod ws;
 
 use cli::{Action, Cli};
-use errors::Error;
-
-use crate::errors::AppResult;
+#[cfg(feature = "viewer")]
+use igneous_md::convert::front_matter::FrontMatter;
+use igneous_md::{
+    config::{self, ServerOptions},
+    endpoint::Endpoint,
+    errors::{AppResult, Error},
+    instance::Instance,
+    server, stdin,
+    token::Token,
+};
 
 #[cfg(feature = "viewer")]
 use {
-    igneous_md_viewer::{Address, Viewer},
+    igneous_md_viewer::{Address, Server, Viewer},
     std::thread,
 };
 
 use std::{
     io::{self, Write},
+    path::{Path, PathBuf},
     time::Duration,
 };
-use tokio::time::sleep;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
 
 #[tokio::main]
 async fn main() -> AppResult {
@@ -53,7 +57,7 @@ async fn run() -> Result<(), Error> {
         .init()
         .expect("Failed to init Logger.");
 
-    let config = match config::Config::new(cli.config) {
+    let mut config = match config::Config::new(cli.config) {
         Ok(mut c) => {
             c.start_watching().map_err(Error::WatchConfigDirFailed)?;
 
@@ -70,67 +74,105 @@ async fn run() -> Result<(), Error> {
             path,
             css,
             export_path,
+            convert_args,
         } => {
-            let default_export_path = config.export_path();
-            let handle = server::launch_server(0, config)
+            config.set_convert_options(convert_args.into());
+
+            let (path, stdin_md) = if path == Path::new("-") {
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                stdin::read(io::stdin().lock(), &tx).map_err(Error::StdinFailed)?;
+
+                // Only the last document is converted
+                let mut md = String::new();
+                while let Ok(document) = rx.try_recv() {
+                    md = document;
+                }
+
+                (stdin::virtual_path().map_err(Error::StdinFailed)?, Some(md))
+            } else {
+                // Relative links and assets are resolved against the location of the file
+                (path.canonicalize().unwrap_or(path), None)
+            };
+
+            let title = stdin_md
+                .clone()
+                .or_else(|| fs::read_to_string(&path).ok())
+                .and_then(|md| FrontMatter::parse(&md))
+                .and_then(|f| f.title().map(str::to_string));
+            let default_export_path = config.export_path(title.as_deref());
+            let handle = server::launch_server(Endpoint::Tcp(0), config, None)
                 .await
                 .map_err(Error::ServerLaunchFailed)?;
 
-            let tcp_port = handle.port();
+            let endpoint = handle.endpoint().clone();
+            let token = handle.token().cloned();
+
+            // The viewer exports its first render, so it may only connect once the markdown is
+            // shown
+            if let Some(md) = stdin_md {
+                let (tx, rx) = mpsc::unbounded_channel();
+                let _ = tx.send(md);
+
+                let shown = push_stdin(endpoint.clone(), token.clone(), path.clone(), rx);
+
+                timeout(Duration::from_secs(10), shown)
+                    .await
+                    .ok()
+                    .and_then(Result::ok)
+                    .ok_or(Error::StdinShowFailed)?;
+            }
 
             let path = path.to_string_lossy().to_string();
             let css = css.map(|v| v.to_string_lossy().to_string());
 
             thread::spawn(move || {
-                let address =
-                    Address::new("localhost", tcp_port, 1000, css.as_deref(), path.as_str());
+                let address = Address::new(
+                    viewer_server(&endpoint),
+                    token.as_ref().map(Token::as_str),
+                    1000,
+                    css.as_deref(),
+                    path.as_str(),
+                );
                 let client = Viewer::new(address, true);
 
                 client.start()
             });
 
-            // TODO: When we add proper ClientHandles in server.rs (see TODO there) we can also
-            // improve the code here to no longer rely on these timings
-
-            // wait for client to start
-            sleep(Duration::from_millis(1000)).await;
-
-            let mut launch_tries = 0;
-            loop {
-                if let Some(tx) = handle.get_client_sender(0) {
-                    tx.send(ws::msg::ServerMsg::Export {
-                        path: export_path
-                            .map(|p| {
-                                if !p.is_absolute() {
-                                    return std::env::current_dir()
-                                        .expect("Failed to get cwd!")
-                                        .join(p);
-                                }
-
-                                p
-                            })
-                            .unwrap_or(default_export_path),
-                    })
-                    .map_err(|_| Error::HeadlessClientLaunchFailed)?;
-
-                    break;
-                }
-                launch_tries += 1;
+            let clients = handle.clients();
 
-                if launch_tries > 5 {
-                    log::error!(
-                        "Failed to start headless client! Cannot convert markdown to pdf. "
-                    );
-                    return Err(Error::HeadlessClientLaunchFailed);
-                }
+            // Only the headless viewer can export, markdown from stdin is pushed by another client
+            let viewer = timeout(
+                Duration::from_secs(10),
+                clients.connected(|c| c.capabilities.export),
+            )
+            .await
+            .map_err(|_| {
+                log::error!("Failed to start headless client! Cannot convert markdown to pdf. ");
 
-                log::warn!("Headless client hasn't started. Waiting further.");
+                Error::HeadlessClientLaunchFailed
+            })?;
 
-                sleep(Duration::from_millis(1000)).await;
+            let export_path = export_path
+                .map(|p| {
+                    if !p.is_absolute() {
+                        return std::env::current_dir().expect("Failed to get cwd!").join(p);
+                    }
+
+                    p
+                })
+                .unwrap_or(default_export_path);
+
+            if !viewer.send(igneous_md::ws::msg::ServerMsg::Export { path: export_path }) {
+                return Err(Error::HeadlessClientLaunchFailed);
             }
 
-            // wait for printing to complete
-            sleep(Duration::from_millis(1000)).await;
+            // The headless viewer closes once printing is complete
+            if timeout(Duration::from_secs(60), clients.disconnected(viewer.id))
+                .await
+                .is_err()
+            {
+                log::warn!("Headless client hasn't finished exporting. Exiting anyway.");
+            }
 
             Ok(())
         }
@@ -159,10 +201,32 @@ async fn run() -> Result<(), Error> {
             path,
             css,
             port,
+            socket,
+            instance,
+            project,
             update_rate,
+            poll,
+            convert_args,
             #[cfg(feature = "viewer")]
             no_viewer,
+            #[cfg(feature = "viewer")]
+            browser,
         } => {
+            let from_stdin = path == Path::new("-");
+
+            let path = if from_stdin {
+                stdin::virtual_path().map_err(Error::StdinFailed)?
+            } else {
+                // Relative links and assets are resolved against the location of the file
+                path.canonicalize().unwrap_or(path)
+            };
+
+            let instance = if project {
+                Instance::for_project(&path)
+            } else {
+                instance
+            };
+
             // TODO: In the future it might be nice to check if the dir contains no css, rather than just
             // checking if it exists. However as it stands currently users can avoid the prompt, by
             // creating the dirs.
@@ -172,69 +236,122 @@ async fn run() -> Result<(), Error> {
                 // Always at least create the dir
                 fs::create_dir_all(config.code_highlight_dir()).map_err(Error::ConfigGenFailed)?;
 
-                print!(
-                    "No config found. Would you like to generate the default config? [(y)es/(N)o]: "
-                );
+                // The user can't be asked, if stdin is the markdown
+                if from_stdin {
+                    log::warn!("No config found. Run `igneous-md generate-config` to generate it.");
+                } else {
+                    print!(
+                        "No config found. Would you like to generate the default config? [(y)es/(N)o]: "
+                    );
 
-                io::stdout().flush().expect("Failed to flush stdout.");
+                    io::stdout().flush().expect("Failed to flush stdout.");
 
-                let mut user_input = String::new();
+                    let mut user_input = String::new();
 
-                io::stdin()
-                    .read_line(&mut user_input)
-                    .expect("Failed to read input.");
+                    io::stdin()
+                        .read_line(&mut user_input)
+                        .expect("Failed to read input.");
 
-                if user_input
-                    .to_lowercase()
-                    .chars()
-                    .next()
-                    .is_some_and(|c| c == 'y')
-                {
-                    config::generate::generate_config_files(&config.css_dir()).await?;
+                    if user_input
+                        .to_lowercase()
+                        .chars()
+                        .next()
+                        .is_some_and(|c| c == 'y')
+                    {
+                        config::generate::generate_config_files(&config.css_dir()).await?;
+                    }
                 }
             }
 
-            let mut existing_port = None;
-            // if no port was given explicitly
-            if port == 0 {
-                match fs::read_to_string(paths::SERVER_PORT_FILE) {
-                    Ok(content) => {
-                        if let Ok(port) = content.parse::<u16>() {
-                            if server::test_server_connection(port).await {
-                                log::info!("Connecting to existing server on port {port}");
-                                existing_port = Some(port);
-                            }
-                        } else {
-                            log::debug!(
-                                "{} is invalid. Attempting to delete.",
-                                paths::SERVER_PORT_FILE
-                            );
-                            paths::attempt_delete_port_file();
-                        }
-                    }
-                    Err(e) => {
-                        log::warn!("Could not read {}: {e}", paths::SERVER_PORT_FILE);
-                    }
+            config.set_convert_options(convert_args.into());
+            config.set_poll(poll);
+
+            let options = config.server_options();
+
+            let mut existing_server = None;
+            if let Some(socket) = &socket {
+                // A server listening on the socket is used, otherwise the socket is replaced
+                let endpoint = Endpoint::Unix(socket.clone());
+
+                if socket.exists()
+                    && let Some(running) = server::test_server_connection(&endpoint, None).await
+                {
+                    check_options(&endpoint, &running, &options)?;
+
+                    log::info!("Connecting to existing server on {endpoint}");
+                    existing_server = Some((endpoint, None));
+                }
+            } else if port == 0
+                && let Some(endpoint) = instance.read_endpoint()
+            {
+                let token = instance.read_token();
+
+                // if no port was given explicitly
+                if let Some(running) =
+                    server::test_server_connection(&endpoint, token.as_ref()).await
+                {
+                    check_options(&endpoint, &running, &options)?;
+
+                    log::info!(
+                        "Connecting to existing server of instance `{}` on {endpoint}",
+                        instance.name()
+                    );
+                    existing_server = Some((endpoint, token));
+                } else {
+                    log::warn!(
+                        "The server of instance `{}` on {endpoint} isn't responding",
+                        instance.name()
+                    );
                 }
             }
 
             let mut handle = None;
-            let tcp_port = if let Some(p) = existing_port {
-                p
+            let (endpoint, token) = if let Some(server) = existing_server {
+                server
             } else {
-                let h = server::launch_server(port, config)
+                let endpoint = socket.map(Endpoint::Unix).unwrap_or(Endpoint::Tcp(port));
+
+                let h = server::launch_server(endpoint, config, Some(&instance))
                     .await
                     .map_err(Error::ServerLaunchFailed)?;
 
-                let p = h.port();
+                let server = (h.endpoint().clone(), h.token().cloned());
 
                 handle = Some(h);
 
-                p
+                server
             };
 
+            if from_stdin {
+                show_stdin(endpoint.clone(), token.clone(), path.clone(), |tx| {
+                    stdin::read(io::stdin().lock(), &tx)
+                });
+            }
+
+            #[cfg(feature = "viewer")]
+            if browser {
+                let path = path.to_string_lossy();
+                let address = Address::new(
+                    viewer_server(&endpoint),
+                    token.as_ref().map(Token::as_str),
+                    update_rate,
+                    css.as_ref().and_then(|c| c.to_str()),
+                    &path,
+                );
+
+                // The url contains the token, which is kept off the command line of the browser
+                if matches!(endpoint, Endpoint::Unix(_)) {
+                    log::warn!("Browsers can't connect to servers listening on a socket");
+                } else if let Err(e) = instance
+                    .write_redirect(&address.to_string())
+                    .and_then(open::that_detached)
+                {
+                    log::warn!("Failed to open browser: {e}");
+                }
+            }
+
             #[cfg(feature = "viewer")]
-            let viewer_handle = if !no_viewer {
+            let viewer_handle = if !no_viewer && !browser {
                 let path = path.to_string_lossy().to_string();
                 let css = css.map(|v| v.to_string_lossy().to_string());
 
@@ -245,8 +362,8 @@ async fn run() -> Result<(), Error> {
 
                 Some(thread::spawn(move || {
                     let address = Address::new(
-                        "localhost",
-                        tcp_port,
+                        viewer_server(&endpoint),
+                        token.as_ref().map(Token::as_str),
                         update_rate,
                         css.as_deref(),
                         path.as_str(),
@@ -266,8 +383,16 @@ async fn run() -> Result<(), Error> {
                 {
                     if let Some(vh) = viewer_handle {
                         vh.join().unwrap();
+
+                        return Ok(());
                     }
                 }
+
+                // Markdown from stdin is only shown while this process runs
+                if from_stdin {
+                    tokio::signal::ctrl_c().await.map_err(Error::SignalFailed)?;
+                }
+
                 return Ok(());
             };
 
@@ -279,3 +404,68 @@ async fn run() -> Result<(), Error> {
         }
     }
 }
+
+/// Show the markdown documents sent by `read` in all viewers of `path` on the server on `endpoint`,
+/// which accepts the `token`
+///
+/// `read` is run on its own thread, since reading stdin blocks. See: [stdin::push()]
+fn show_stdin(
+    endpoint: Endpoint,
+    token: Option<Token>,
+    path: PathBuf,
+    read: impl FnOnce(mpsc::UnboundedSender<String>) -> io::Result<()> + Send + 'static,
+) {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        if let Err(e) = read(tx) {
+            log::error!("Failed to read markdown from stdin: {e}");
+        }
+    });
+
+    push_stdin(endpoint, token, path, rx);
+}
+
+/// Push the `documents` to the server on `endpoint` in the background, see [stdin::push()]
+///
+/// The returned receiver resolves once the first document is shown, or fails if it can't be.
+fn push_stdin(
+    endpoint: Endpoint,
+    token: Option<Token>,
+    path: PathBuf,
+    documents: mpsc::UnboundedReceiver<String>,
+) -> oneshot::Receiver<()> {
+    let (shown_tx, shown_rx) = oneshot::channel();
+
+    tokio::spawn(async move {
+        if let Err(e) = stdin::push(endpoint, token, path, documents, shown_tx).await {
+            log::error!("Failed to show markdown from stdin: {e}");
+        }
+    });
+
+    shown_rx
+}
+
+/// Check that the server running on `endpoint` was launched with the `options` requested now
+///
+/// Otherwise the options would silently be ignored, since they apply to all clients of a server.
+fn check_options(
+    endpoint: &Endpoint,
+    running: &ServerOptions,
+    options: &ServerOptions,
+) -> Result<(), Error> {
+    if running != options {
+        return Err(Error::ServerOptionsDiffer(endpoint.clone()));
+    }
+
+    Ok(())
+}
+
+/// Get the server the viewer connects to for the `endpoint`
+#[cfg(feature = "viewer")]
+fn viewer_server(endpoint: &Endpoint) -> Server<'_> {
+    match endpoint {
+        Endpoint::Tcp(port) => Server::Tcp("localhost", *port),
+        Endpoint::Unix(path) => Server::Unix(path),
+    }
+}
@@ -6,15 +6,14 @@ use lightningcss::{
     printer::PrinterOptions,
     stylesheet::ParserOptions,
 };
+use percent_encoding::percent_decode_str;
 use std::{
     fs, io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::LazyLock,
 };
 
-use crate::config::CssEntry;
-
-pub const SERVER_PORT_FILE: &str = "/tmp/igneous-md";
+use crate::config::{ColorScheme, CssEntry};
 
 /// Default config dir for the application
 ///
@@ -34,6 +33,74 @@ pub static DEFAULT_CONFIG_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
         .join("igneous-md/")
 });
 
+/// File the name of the last stylesheet selected by the user is stored in
+///
+/// This is kept outside of the config dir, since changes to the config dir reload the config.
+/// See: [crate::config::Config::set_last_css()]
+pub static LAST_CSS_FILE: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("igneous-md/last-css"))
+});
+
+/// Files shown when a link points to a directory, in order of preference
+const DIRECTORY_INDEX_FILES: [&str; 4] = ["README.md", "readme.md", "index.md", "README.markdown"];
+
+/// Get the directory relative links in the markdown file at `md_path` are resolved against
+///
+/// This is the absolute path of the directory containing the file, if it can be determined.
+pub fn document_dir(md_path: &Path) -> PathBuf {
+    let dir = md_path.parent().unwrap_or(Path::new(""));
+
+    dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// Resolve the `href` of a link or image against the `document_dir` (see [document_dir()])
+///
+/// The `href` is percent-decoded and any `#fragment` or `?query` is removed. `.` and `..`
+/// components are resolved lexically, without accessing the file system.
+pub fn resolve_href(document_dir: &Path, href: &str) -> PathBuf {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+
+    normalize(&document_dir.join(&*percent_decode_str(href).decode_utf8_lossy()))
+}
+
+/// Resolve the `.` and `..` components of the `path` lexically, without accessing the file system
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+/// Resolve a link to another markdown file, found in the markdown file at `md_path`
+///
+/// Works like [resolve_href()], but links to directories are resolved to the README or index
+/// file within them, if there is one.
+pub fn resolve_md_link(md_path: &Path, href: &str) -> PathBuf {
+    let path = resolve_href(&document_dir(md_path), href);
+
+    if path.is_dir()
+        && let Some(index) = DIRECTORY_INDEX_FILES
+            .iter()
+            .map(|f| path.join(f))
+            .find(|p| p.is_file())
+    {
+        return index;
+    }
+
+    path
+}
+
 /// Bundle and minify a CSS file using lightningcss
 ///
 /// This processes @import rules and inlines them, then minifies the result.
@@ -85,7 +152,16 @@ pub fn read_css_dir(css_dir: &Path) -> io::Result<Vec<CssEntry>> {
                     }
                 };
 
-                return Some(CssEntry { path, content });
+                // The comment setting the color scheme is removed when minifying
+                let color_scheme = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|css| ColorScheme::of_stylesheet(&path, &css));
+
+                return Some(CssEntry {
+                    path,
+                    content,
+                    color_scheme,
+                });
             }
 
             None
@@ -103,26 +179,52 @@ pub fn read_css_dir(css_dir: &Path) -> io::Result<Vec<CssEntry>> {
     Ok(entries)
 }
 
-/// Attempt to delete the tmp port file
-///
-/// If this fails it will log a warning.
-///
-/// See:
-/// - [SERVER_PORT_FILE]
-pub fn attempt_delete_port_file() {
-    if let Err(e) = fs::remove_file(SERVER_PORT_FILE) {
-        log::warn!("Failed to remove tmp port file: {e}")
+#[cfg(test)]
+mod test {
+    use super::{document_dir, resolve_href, resolve_md_link};
+    use std::{fs, path::Path};
+
+    #[test]
+    fn hrefs() {
+        let dir = Path::new("/docs/guide");
+
+        assert_eq!(resolve_href(dir, "./b.md"), Path::new("/docs/guide/b.md"));
+        assert_eq!(
+            resolve_href(dir, "../a.md#section"),
+            Path::new("/docs/a.md")
+        );
+        assert_eq!(
+            resolve_href(dir, "img%20one.png?raw=true"),
+            Path::new("/docs/guide/img one.png")
+        );
+        assert_eq!(resolve_href(dir, "/abs/c.md"), Path::new("/abs/c.md"));
     }
-}
 
-/// Attempt to write the tmp port file
-///
-/// If this fails it will log a warning
-///
-/// See:
-/// - [SERVER_PORT_FILE]
-pub fn attempt_write_port_file(port: u16) {
-    if let Err(e) = fs::write(SERVER_PORT_FILE, port.to_string()) {
-        log::warn!("Failed to write tmp port file: {e}")
+    #[test]
+    fn md_links() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        fs::create_dir_all(dir.join("guide")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("guide/index.md"), "").unwrap();
+        fs::write(dir.join("guide/README.md"), "").unwrap();
+
+        let md_path = dir.join("a.md");
+        let dir = document_dir(&md_path);
+
+        assert_eq!(
+            resolve_md_link(&md_path, "b.markdown"),
+            dir.join("b.markdown")
+        );
+        assert_eq!(
+            resolve_md_link(&md_path, "./guide/"),
+            dir.join("guide/README.md")
+        );
+        assert_eq!(
+            resolve_md_link(&md_path, "guide#usage"),
+            dir.join("guide/README.md")
+        );
+        assert_eq!(resolve_md_link(&md_path, "empty"), dir.join("empty"));
     }
 }
@@ -0,0 +1,279 @@
+//! Clients connected to a server. See: [Registry]
+//!
+//! Every connection registers a [ClientHandle] once its handshake is done, which is removed again
+//! when it disconnects. [ClientEvent]s are sent whenever that happens, so callers can await
+//! clients instead of guessing how long they take to start.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::ws::msg::ServerMsg;
+
+/// What a client supports, as announced during the handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether the client supports [ServerMsg::HtmlPatch]
+    pub patch: bool,
+    /// Whether the client can export to pdf, see [ServerMsg::Export]
+    pub export: bool,
+}
+
+/// A client connected to the server
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    /// Unique id of the connection
+    pub id: usize,
+    /// Path to the `.md` the client is viewing
+    pub path: PathBuf,
+    /// When the client connected
+    pub connected_at: SystemTime,
+    /// See [Capabilities]
+    pub capabilities: Capabilities,
+    /// File name of the stylesheet the client is using, if there is any css
+    pub css: Option<String>,
+    /// Sends messages to the client, see [Self::send()]
+    sender: mpsc::UnboundedSender<ServerMsg>,
+}
+
+impl ClientHandle {
+    /// Send `msg` to the client
+    ///
+    /// Messages are only sent once the client has received its first html, so they apply to a
+    /// rendered document. Returns `false` if the client has disconnected.
+    pub fn send(&self, msg: ServerMsg) -> bool {
+        self.sender.send(msg).is_ok()
+    }
+}
+
+/// A change to the clients of a [Registry]
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// A client has connected
+    Connected(ClientHandle),
+    /// The client with this id has disconnected
+    Disconnected(usize),
+}
+
+/// All clients connected to a server
+#[derive(Debug)]
+pub struct Registry {
+    /// The connected clients by their id
+    clients: RwLock<HashMap<usize, ClientHandle>>,
+    /// Sends a [ClientEvent] for every client connecting or disconnecting
+    events: broadcast::Sender<ClientEvent>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            clients: RwLock::default(),
+            events: broadcast::channel(16).0,
+        }
+    }
+}
+
+impl Registry {
+    /// Register the client connected as `id`
+    ///
+    /// The client stays registered until the returned [Registration] is dropped.
+    pub fn register(
+        &self,
+        id: usize,
+        path: PathBuf,
+        capabilities: Capabilities,
+        sender: mpsc::UnboundedSender<ServerMsg>,
+    ) -> Registration<'_> {
+        let handle = ClientHandle {
+            id,
+            path,
+            connected_at: SystemTime::now(),
+            capabilities,
+            css: None,
+            sender,
+        };
+
+        self.write().insert(id, handle.clone());
+
+        log::debug!("Client {id} connected");
+
+        // Fails if there are no receivers, which is fine
+        let _ = self.events.send(ClientEvent::Connected(handle));
+
+        Registration { registry: self, id }
+    }
+
+    /// Get the client with the `id`
+    ///
+    /// The returned value is cloned.
+    pub fn get(&self, id: usize) -> Option<ClientHandle> {
+        self.read().get(&id).cloned()
+    }
+
+    /// Get all connected clients, ordered by their id
+    pub fn all(&self) -> Vec<ClientHandle> {
+        let mut clients: Vec<_> = self.read().values().cloned().collect();
+        clients.sort_by_key(|c| c.id);
+
+        clients
+    }
+
+    /// Subscribe to the [ClientEvent]s
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// Wait for a client for which `f` returns `true`, returning it
+    ///
+    /// Returns right away if such a client is already connected.
+    pub async fn connected(&self, f: impl Fn(&ClientHandle) -> bool) -> ClientHandle {
+        // Subscribed before looking at the clients, so none connecting in between are missed
+        let mut events = self.subscribe();
+
+        if let Some(client) = self.all().into_iter().find(&f) {
+            return client;
+        }
+
+        loop {
+            match events.recv().await {
+                Ok(ClientEvent::Connected(client)) if f(&client) => return client,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if let Some(client) = self.all().into_iter().find(&f) {
+                        return client;
+                    }
+                }
+                // The registry holds the sender, so it is never closed while borrowed
+                Err(broadcast::error::RecvError::Closed) => unreachable!(),
+            }
+        }
+    }
+
+    /// Wait for the client with the `id` to disconnect
+    ///
+    /// Returns right away if it isn't connected.
+    pub async fn disconnected(&self, id: usize) {
+        let mut events = self.subscribe();
+
+        while self.read().contains_key(&id) {
+            match events.recv().await {
+                Ok(ClientEvent::Disconnected(i)) if i == id => return,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => unreachable!(),
+            }
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<usize, ClientHandle>> {
+        self.clients
+            .read()
+            .expect("Clients RWLock should never be poisoned.")
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<usize, ClientHandle>> {
+        self.clients
+            .write()
+            .expect("Clients RWLock should never be poisoned.")
+    }
+}
+
+/// Keeps a client registered in a [Registry], removing it when dropped
+#[derive(Debug)]
+pub struct Registration<'a> {
+    /// The registry the client is registered in
+    registry: &'a Registry,
+    /// Id of the client
+    id: usize,
+}
+
+impl Registration<'_> {
+    /// Update the `path` and `css` of the client
+    pub fn update(&self, path: &Path, css: Option<String>) {
+        if let Some(client) = self.registry.write().get_mut(&self.id) {
+            client.path = path.to_path_buf();
+            client.css = css;
+        }
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.registry.write().remove(&self.id);
+
+        log::debug!("Client {} disconnected", self.id);
+
+        // Fails if there are no receivers, which is fine
+        let _ = self
+            .registry
+            .events
+            .send(ClientEvent::Disconnected(self.id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Capabilities, ClientEvent, Registry};
+    use std::{path::PathBuf, sync::Arc};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn lifecycle() {
+        let registry = Arc::new(Registry::default());
+        let mut events = registry.subscribe();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let waiting = tokio::spawn({
+            let registry = Arc::clone(&registry);
+
+            async move { registry.connected(|c| c.capabilities.export).await.id }
+        });
+
+        let viewer = registry.register(
+            1,
+            PathBuf::from("/a.md"),
+            Capabilities {
+                patch: true,
+                export: true,
+            },
+            tx.clone(),
+        );
+        let editor = registry.register(2, PathBuf::from("/a.md"), Capabilities::default(), tx);
+
+        assert_eq!(waiting.await.unwrap(), 1);
+        assert!(matches!(events.recv().await, Ok(ClientEvent::Connected(c)) if c.id == 1));
+        assert!(matches!(events.recv().await, Ok(ClientEvent::Connected(c)) if c.id == 2));
+
+        viewer.update(&PathBuf::from("/b.md"), Some("dark.css".to_string()));
+        let client = registry.get(1).unwrap();
+        assert_eq!(client.path, PathBuf::from("/b.md"));
+        assert_eq!(client.css.as_deref(), Some("dark.css"));
+
+        assert!(client.send(crate::ws::msg::ServerMsg::Success));
+        assert!(rx.recv().await.is_some_and(|m| m.is_success()));
+
+        // already connected clients are returned right away
+        assert_eq!(registry.connected(|c| c.id == 2).await.id, 2);
+
+        drop(editor);
+        assert!(matches!(
+            events.recv().await,
+            Ok(ClientEvent::Disconnected(2))
+        ));
+        assert_eq!(registry.all().iter().map(|c| c.id).collect::<Vec<_>>(), [1]);
+
+        let disconnected = tokio::spawn({
+            let registry = Arc::clone(&registry);
+
+            async move { registry.disconnected(1).await }
+        });
+
+        drop(viewer);
+        disconnected.await.unwrap();
+        assert!(registry.get(1).is_none());
+        // not connected anymore, so this returns right away
+        registry.disconnected(1).await;
+    }
+}
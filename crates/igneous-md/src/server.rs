@@ -1,134 +1,218 @@
 //! Module containing items relating to the backend server.
 //!
-//! The server listens for incoming connections and upgrades them to websocket connections via
-//! [crate::ws::upgrade_connection()].
+//! The server listens for incoming connections on an [Endpoint] and upgrades them to websocket
+//! connections via [crate::ws::upgrade_connection()]. Plain http requests of browsers are served by
+//! [crate::web::serve()].
 //!
 //! Each client connection is spawned as its own task, sharing a single [Config] between all clients.
+//! Everything else shared between the connections is bundled in [Shared], including the [Registry]
+//! of connected clients.
 
 use futures_util::{SinkExt, StreamExt};
 use std::sync::{Arc, RwLock};
 use tokio::{
-    net::TcpListener,
-    sync::{mpsc, oneshot},
+    sync::{broadcast, oneshot},
+    time::{self, Duration},
 };
 use tokio_tungstenite::tungstenite::Message as WsMessage;
 
 use crate::{
-    config::Config,
-    paths,
+    buffers::Buffers,
+    config::{Config, ServerOptions},
+    documents::Documents,
+    endpoint::{Endpoint, Listener},
+    instance::Instance,
+    registry::Registry,
+    token::Token,
+    watcher::FileWatcher,
+    web,
     ws::{
+        SourceLine,
         msg::{AsMsg, ClientMsg, ServerMsg},
-        upgrade_connection,
     },
 };
 
+/// State shared between all connections of a server
+#[derive(Debug, Clone)]
+pub struct Shared {
+    /// See [Config]
+    pub config: Arc<RwLock<Config>>,
+    /// See [Buffers]
+    pub buffers: Arc<Buffers>,
+    /// See [FileWatcher]
+    pub file_watcher: Arc<FileWatcher>,
+    /// See [Documents]
+    pub documents: Arc<Documents>,
+    /// Sends the [SourceLine]s of all connections
+    pub source_line_tx: broadcast::Sender<SourceLine>,
+    /// See [Registry]
+    pub clients: Arc<Registry>,
+    /// Clients have to present this token to connect, see [Token]
+    pub token: Option<Token>,
+}
+
 /// Handle to the running server
 ///
-/// Dropping this handle will **not stop** the server. Use [Self::stop()] to stop the server.
+/// Dropping this handle will **not stop** the server. Use [Self::stop()] to stop the server, which
+/// also releases its [Instance].
 pub struct ServerHandle {
     /// Channel to signal the server to stop
     stop_tx: oneshot::Sender<()>,
-    /// Port the server is listening on
-    port: u16,
-    /// Senders to clients which have connected
+    /// Endpoint the server is listening on
+    endpoint: Endpoint,
+    /// See [Shared::token]
+    token: Option<Token>,
+    /// Clients which are connected, see [Registry]
     ///
     /// This allows the server to send messages to clients.
-    ///
-    /// Beware that after clients disconnect their senders remain here until the server is
-    /// shutdown. This means that sending a message to a client won't always be successful, since
-    /// the other side may have been dropped.
-    ///
-    // TODO: In the future we should replace this with a proper ClientHandle struct so we have more
-    // control over the clients. This will also allow us to clean up the Action::convert code.
-    clients: Arc<RwLock<Vec<mpsc::UnboundedSender<ServerMsg>>>>,
+    clients: Arc<Registry>,
 }
 
 impl ServerHandle {
-    /// Get the port the server is listening on
-    pub fn port(&self) -> u16 {
-        self.port
+    /// Get the endpoint the server is listening on
+    ///
+    /// For [Endpoint::Tcp] this is the actual port, even if the server was launched on port 0.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Get the token clients have to present to connect, see [Token]
+    pub fn token(&self) -> Option<&Token> {
+        self.token.as_ref()
     }
 
     /// Stop the server
+    #[allow(
+        clippy::result_unit_err,
+        reason = "The server task is gone if it fails, there is nothing else to report."
+    )]
     pub fn stop(self) -> Result<(), ()> {
         log::info!("Server exiting");
-        paths::attempt_delete_port_file();
         self.stop_tx.send(())
     }
 
-    /// Get the client sender at `index`
-    ///
-    /// The returned value is cloned.
-    pub fn get_client_sender(&self, index: usize) -> Option<mpsc::UnboundedSender<ServerMsg>> {
-        self.clients
-            .read()
-            .expect("Clients RWLock should never be poisoned.")
-            .get(index)
-            .cloned()
+    /// Get the clients connected to the server
+    pub fn clients(&self) -> &Registry {
+        &self.clients
     }
 }
 
 /// Launch the server
 ///
-/// Binds to `127.0.0.1:{port}` and listens for incoming connections.
+/// Binds to the `endpoint` and listens for incoming connections.
+///
+/// If an `instance` is given, the server claims it and writes the endpoint it is listening on to
+/// its [Instance::port_file()], since for port 0 it will randomly select a port to use. Servers
+/// without an instance can't be found by other invocations, e.g. those used for conversion.
 ///
-/// The server writes the port it is listening on to [paths::SERVER_PORT_FILE] since if `port` is 0
-/// it will randomly select a port to use.
-pub async fn launch_server(port: u16, config: Config) -> Result<ServerHandle, std::io::Error> {
-    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await?;
-    let tcp_port = listener.local_addr()?.port();
+/// Servers listening on a port generate a [Token] clients have to present, which is written to
+/// the [Instance::token_file()].
+pub async fn launch_server(
+    endpoint: Endpoint,
+    config: Config,
+    instance: Option<&Instance>,
+) -> Result<ServerHandle, std::io::Error> {
+    let listener = Listener::bind(&endpoint).await?;
+    let endpoint = listener.endpoint()?;
+
+    // Only the user can connect to sockets anyway
+    let token = match endpoint {
+        Endpoint::Tcp(_) => Some(Token::generate()?),
+        Endpoint::Unix(_) => None,
+    };
 
     let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
 
-    paths::attempt_write_port_file(tcp_port);
+    // The server still runs if the instance can't be claimed, it just can't be found
+    let instance_lock = instance.and_then(|instance| {
+        let lock = instance
+            .claim()
+            .inspect_err(|e| log::warn!("Failed to claim instance `{}`: {e}", instance.name()))
+            .ok()?;
 
-    let config = Arc::new(RwLock::new(config));
-    let clients: Arc<RwLock<Vec<mpsc::UnboundedSender<ServerMsg>>>> = Arc::default();
+        if let Err(e) = lock.write_endpoint(&endpoint, token.as_ref()) {
+            log::warn!("Failed to write port file: {e}");
+        }
+
+        Some(lock)
+    });
+
+    let shared = Shared {
+        file_watcher: Arc::new(if config.poll() {
+            FileWatcher::polling()
+        } else {
+            FileWatcher::new()
+        }),
+        config: Arc::new(RwLock::new(config)),
+        buffers: Arc::default(),
+        documents: Arc::default(),
+        source_line_tx: broadcast::channel(16).0,
+        clients: Arc::default(),
+        token: token.clone(),
+    };
+    let clients = Arc::clone(&shared.clients);
+
+    let mut connections = 0;
 
-    let clients_clone = Arc::clone(&clients);
     tokio::spawn(async move {
+        // Released once the server stops
+        let _instance_lock = instance_lock;
+
         loop {
             tokio::select! {
                 _ = &mut stop_rx => {
                     return;
                 }
                 accept_result = listener.accept() => {
-                    let (stream, _) = accept_result.expect("Failed to accept connection");
-                    let (tx, rx) = mpsc::unbounded_channel();
-
-                    tokio::spawn(upgrade_connection(stream, Arc::clone(&config), rx));
+                    let stream = accept_result.expect("Failed to accept connection");
 
-                    clients_clone.write().expect("Clients RWLock should never be poisoned.").push(tx);
+                    tokio::spawn(web::serve(stream, shared.clone(), connections));
+                    connections += 1;
                 }
             }
         }
     });
 
-    log::info!("Server launched on port {tcp_port}!");
+    log::info!("Server launched on {endpoint}!");
 
     Ok(ServerHandle {
         stop_tx,
-        port: tcp_port,
-        clients: Arc::clone(&clients),
+        endpoint,
+        token,
+        clients,
     })
 }
 
-/// Test if there is a server running on the given port
-pub async fn test_server_connection(port: u16) -> bool {
-    let Ok((mut socket, _)) =
-        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/?md_path=/dev/null"))
-            .await
-    else {
-        return false;
-    };
-
-    socket.send(ClientMsg::CheckServer.as_msg()).await.unwrap();
-
-    socket.select_next_some().await.is_ok_and(|msg| {
-        if let WsMessage::Text(str) = msg {
-            return serde_json::from_str::<ServerMsg>(&str).is_ok_and(|v| v.is_success());
+/// Test if there is a server running on the given endpoint, which accepts the `token`
+///
+/// Returns the options the server was launched with, or [None] if there is no server responding.
+///
+/// The server renders `/dev/null` for the connection as well, so the messages of that render
+/// are skipped until it answers [ClientMsg::GetServerOptions].
+pub async fn test_server_connection(
+    endpoint: &Endpoint,
+    token: Option<&Token>,
+) -> Option<ServerOptions> {
+    let mut socket = endpoint.connect("md_path=/dev/null", token).await.ok()?;
+
+    socket
+        .send(ClientMsg::GetServerOptions.as_msg())
+        .await
+        .ok()?;
+
+    let running = async {
+        while let Some(Ok(msg)) = socket.next().await {
+            if let WsMessage::Text(str) = msg
+                && let Ok(ServerMsg::ServerOptions(options)) = serde_json::from_str(&str)
+            {
+                return Some(options);
+            }
         }
 
-        false
-    })
+        None
+    };
+
+    time::timeout(Duration::from_secs(1), running)
+        .await
+        .unwrap_or(None)
 }
@@ -0,0 +1,186 @@
+//! Markdown read from stdin, to preview generated markdown without a file. See: [push()]
+//!
+//! The stream may contain several documents, separated by NUL or form-feed characters. Each
+//! document replaces the previous one once it is complete, so viewers update live while the
+//! stream keeps producing documents.
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+use crate::{
+    endpoint::Endpoint,
+    token::Token,
+    ws::msg::{AsMsg, ClientMsg, ServerMsg},
+};
+
+/// Characters separating the documents in the stream
+const SEPARATORS: [u8; 2] = [b'\0', b'\x0c'];
+
+/// Get the path markdown from stdin is shown under
+///
+/// No file exists at this path. It is in the working directory, so relative links are resolved
+/// against it, and unique to this process, so several streams can be viewed at once.
+pub fn virtual_path() -> io::Result<PathBuf> {
+    Ok(std::env::current_dir()?
+        .canonicalize()?
+        .join(format!("stdin-{}.md", std::process::id())))
+}
+
+/// Read the documents from `reader`, sending each to `documents` once it is complete
+///
+/// The last document is complete once the `reader` reaches its end. Empty documents are
+/// skipped. Blocks until the `reader` ends or nobody receives the documents anymore.
+pub fn read(mut reader: impl Read, documents: &mpsc::UnboundedSender<String>) -> io::Result<()> {
+    let mut document = Vec::new();
+    let mut buf = [0; 8192];
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for part in buf[..n].split_inclusive(|b| SEPARATORS.contains(b)) {
+            match part.split_last() {
+                Some((last, content)) if SEPARATORS.contains(last) => {
+                    document.extend_from_slice(content);
+
+                    if !send(documents, &mut document) {
+                        return Ok(());
+                    }
+                }
+                _ => document.extend_from_slice(part),
+            }
+        }
+    }
+
+    send(documents, &mut document);
+
+    Ok(())
+}
+
+/// Send the `document` unless it is empty, clearing it
+///
+/// Returns `false` if nobody receives the documents anymore.
+fn send(documents: &mpsc::UnboundedSender<String>, document: &mut Vec<u8>) -> bool {
+    let content = String::from_utf8_lossy(document).into_owned();
+    document.clear();
+
+    if content.trim().is_empty() {
+        return true;
+    }
+
+    documents.send(content).is_ok()
+}
+
+/// Show the `documents` as the markdown of `path` in all viewers of the server on `endpoint`,
+/// connecting with its `token`
+///
+/// Connects to the server like an editor, see [ClientMsg::PushMarkdown]. `shown` is sent once the
+/// server has the first document, so viewers connecting from then on show it. The documents stay
+/// shown until the connection is closed, so this only returns once the server closes it, or if
+/// sending fails.
+pub async fn push(
+    endpoint: Endpoint,
+    token: Option<Token>,
+    path: PathBuf,
+    mut documents: mpsc::UnboundedReceiver<String>,
+    shown: oneshot::Sender<()>,
+) -> io::Result<()> {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("md_path", &path.to_string_lossy())
+        .finish();
+
+    let socket = endpoint.connect(&query, token.as_ref()).await?;
+
+    let (mut write, mut read) = socket.split();
+
+    // The server sends the html of the documents as well, which isn't needed. The messages
+    // still have to be read, so the server isn't blocked sending them.
+    let closed = tokio::spawn(async move {
+        let mut shown = Some(shown);
+
+        while let Some(Ok(msg)) = read.next().await {
+            // Messages are handled in order, so the server has the document pushed before
+            if let WsMessage::Text(str) = msg
+                && serde_json::from_str::<ServerMsg>(&str).is_ok_and(|v| v.is_success())
+                && let Some(shown) = shown.take()
+            {
+                // Fails if there are no receivers, which is fine
+                let _ = shown.send(());
+            }
+        }
+    });
+
+    // Viewers connecting before the first document is complete show an empty document, rather
+    // than a missing file. It isn't sent if the document is complete already, e.g. when
+    // converting, where the first render of a viewer would export it.
+    let first = documents.try_recv().ok();
+    let mut placeholder = first.is_none();
+    let mut content = Some(first.unwrap_or_default());
+    let mut confirmed = false;
+
+    while let Some(document) = content {
+        write
+            .send(
+                ClientMsg::PushMarkdown {
+                    path: path.clone(),
+                    content: document,
+                }
+                .as_msg(),
+            )
+            .await
+            .map_err(io::Error::other)?;
+
+        // Answered once the document is handled, see above
+        if !placeholder && !confirmed {
+            write
+                .send(ClientMsg::CheckServer.as_msg())
+                .await
+                .map_err(io::Error::other)?;
+
+            confirmed = true;
+        }
+
+        placeholder = false;
+        content = documents.recv().await;
+    }
+
+    closed.await.map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod test {
+    use super::read;
+    use tokio::sync::mpsc;
+
+    fn documents(input: &str) -> Vec<String> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        read(input.as_bytes(), &tx).unwrap();
+        drop(tx);
+
+        let mut documents = Vec::new();
+        while let Ok(document) = rx.try_recv() {
+            documents.push(document);
+        }
+
+        documents
+    }
+
+    #[test]
+    fn separators() {
+        assert_eq!(
+            documents("# A\n\0# B\n\x0c# C\n"),
+            ["# A\n", "# B\n", "# C\n"]
+        );
+        // empty documents, e.g. from trailing separators, are skipped
+        assert_eq!(documents("# A\0\0\n\x0c"), ["# A"]);
+        assert_eq!(documents(""), Vec::<String>::new());
+    }
+}
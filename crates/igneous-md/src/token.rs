@@ -0,0 +1,124 @@
+//! Tokens authenticating connections to a server. See: [Token]
+//!
+//! Every local user, and every web page open in a browser, can connect to a port on `127.0.0.1`.
+//! Servers listening on a port therefore only accept connections presenting the token they
+//! generated at launch, which is only readable by the user (see
+//! [crate::instance::Instance::token_file()]).
+//!
+//! Servers listening on a unix socket don't need a token, since only the user can connect to the
+//! socket.
+use http::Request;
+use std::{
+    convert::Infallible,
+    fmt::{self, Debug, Display},
+    io,
+    str::FromStr,
+};
+
+/// Number of random bytes of a token
+const TOKEN_BYTES: usize = 32;
+
+/// A secret clients present to connect to a server, as the `token` query parameter
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token(String);
+
+impl Token {
+    /// Generate a random token
+    pub fn generate() -> io::Result<Self> {
+        let mut bytes = [0; TOKEN_BYTES];
+        getrandom::fill(&mut bytes).map_err(io::Error::other)?;
+
+        Ok(Self(bytes.iter().map(|b| format!("{b:02x}")).collect()))
+    }
+
+    /// Get the token as it is written to files and urls
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check if `other` is this token
+    ///
+    /// Takes the same time wherever `other` differs, so the token can't be guessed byte by byte.
+    pub fn matches(&self, other: &str) -> bool {
+        self.0.len() == other.len()
+            && self
+                .0
+                .bytes()
+                .zip(other.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Doesn't show the token, so it isn't logged by accident
+impl Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+impl FromStr for Token {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.trim().to_string()))
+    }
+}
+
+/// Check if the `request` has the `token` as the `token` query parameter
+///
+/// Every request is authorized if there is no `token`.
+pub fn is_authorized(request: &Request<()>, token: Option<&Token>) -> bool {
+    token.is_none_or(|token| {
+        form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+            .any(|(key, value)| key == "token" && token.matches(&value))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Token, is_authorized};
+    use http::Request;
+
+    #[test]
+    fn tokens() {
+        let token = Token::generate().unwrap();
+
+        assert_eq!(token.as_str().len(), 64);
+        assert_ne!(token, Token::generate().unwrap());
+
+        assert!(token.matches(token.as_str()));
+        assert!(!token.matches(""));
+        assert!(!token.matches(&token.as_str()[1..]));
+        assert!(!token.matches(&token.as_str().replace(|c: char| c.is_ascii_digit(), "x")));
+
+        assert_eq!(format!("{token:?}"), "Token(..)");
+    }
+
+    #[test]
+    fn requests() {
+        let token = Token::generate().unwrap();
+        let request = |uri: String| Request::get(uri).body(()).unwrap();
+
+        let with_token = request(format!("/ws/?md_path=a.md&token={token}"));
+        assert!(is_authorized(&with_token, Some(&token)));
+        assert!(!is_authorized(
+            &request("/ws/?md_path=a.md".into()),
+            Some(&token)
+        ));
+        assert!(!is_authorized(
+            &request("/ws/?token=a".into()),
+            Some(&token)
+        ));
+        assert!(!is_authorized(&request("/ws/".into()), Some(&token)));
+
+        // servers without a token accept everyone
+        assert!(is_authorized(&request("/ws/".into()), None));
+    }
+}
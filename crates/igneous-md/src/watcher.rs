@@ -0,0 +1,365 @@
+//! Watching of markdown files for changes. See: [FileWatcher]
+//!
+//! One [FileWatcher] is shared by all clients of a server, so each file is only watched once, no
+//! matter how many clients view it.
+use notify::{
+    EventKind, Watcher,
+    event::{ModifyKind, RenameMode},
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::{self, Duration},
+};
+
+/// How long a file has to be quiet, before a change is reported
+///
+/// Editors often save files in several steps, e.g. by truncating and then writing the file or by
+/// writing to a temporary file which is then renamed. These should only cause a single update.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// A change to a watched file, see [FileWatcher::subscribe()]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileUpdate {
+    /// The file was changed, created or removed
+    Changed(PathBuf),
+    /// The file was renamed within its directory
+    ///
+    /// Only reported if the file doesn't exist again by the time the update is sent, since
+    /// editors which save by renaming the old file to a backup immediately write a new one.
+    Moved {
+        /// The watched file
+        from: PathBuf,
+        /// Where it was moved to
+        to: PathBuf,
+    },
+}
+
+/// Watches files for changes, using [notify]
+///
+/// Instead of the files themselves, their directories are watched, so that files replaced by an
+/// atomic rename are still watched afterwards.
+#[derive(Debug)]
+pub struct FileWatcher {
+    /// The watcher, if watching is possible at all
+    watcher: Mutex<Option<notify::RecommendedWatcher>>,
+    /// Number of [WatchGuard]s for each watched file
+    files: Arc<Mutex<HashMap<PathBuf, usize>>>,
+    /// Number of watched files in each watched directory
+    dirs: Mutex<HashMap<PathBuf, usize>>,
+    /// Sends an update for every watched file that changed
+    update_sender: broadcast::Sender<FileUpdate>,
+}
+
+impl FileWatcher {
+    /// Create a new watcher
+    ///
+    /// If the underlying watcher cannot be created, a warning is logged and the watcher behaves
+    /// like one created by [Self::polling()].
+    ///
+    /// Must be called within a tokio runtime.
+    #[allow(
+        clippy::new_without_default,
+        reason = "Needs a tokio runtime, which a Default implementation would hide."
+    )]
+    pub fn new() -> Self {
+        let watcher = Self::polling();
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let files = Arc::clone(&watcher.files);
+
+        let notify_watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+
+                if event.kind.is_access() {
+                    return;
+                }
+
+                let files = files.lock().unwrap();
+
+                if let EventKind::Modify(ModifyKind::Name(RenameMode::Both)) = event.kind
+                    && let [from, to] = &event.paths[..]
+                    && files.contains_key(from)
+                {
+                    let _ = event_tx.send((from.clone(), Some(to.clone())));
+                }
+
+                for path in event.paths {
+                    if files.contains_key(&path) {
+                        let _ = event_tx.send((path, None));
+                    }
+                }
+            });
+
+        match notify_watcher {
+            Ok(notify_watcher) => {
+                *watcher.watcher.lock().unwrap() = Some(notify_watcher);
+
+                tokio::spawn(debounce(event_rx, watcher.update_sender.clone()));
+            }
+            Err(e) => log::warn!("Failed to create file watcher, polling files instead: {e}"),
+        }
+
+        watcher
+    }
+
+    /// Create a watcher, which doesn't watch any files
+    ///
+    /// Clients then have to poll their files for changes. This is needed for file systems which
+    /// don't support [notify], such as some network file systems.
+    pub fn polling() -> Self {
+        Self {
+            watcher: Mutex::default(),
+            files: Arc::default(),
+            dirs: Mutex::default(),
+            update_sender: broadcast::channel(16).0,
+        }
+    }
+
+    /// Start watching the file at `path`, until the returned [WatchGuard] is dropped
+    ///
+    /// Returns [None] if the file cannot be watched, in which case it has to be polled.
+    pub fn watch(self: &Arc<Self>, path: &Path) -> Option<WatchGuard> {
+        let path = canonical(path)?;
+        let dir = path.parent()?.to_path_buf();
+
+        {
+            let mut watcher = self.watcher.lock().unwrap();
+            let watcher = watcher.as_mut()?;
+
+            let mut dirs = self.dirs.lock().unwrap();
+
+            if !dirs.contains_key(&dir) {
+                if let Err(e) = watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+                    log::warn!("Failed to watch {}, polling instead: {e}", dir.display());
+                    return None;
+                }
+
+                log::debug!("Watching dir: {}", dir.display());
+            }
+
+            *dirs.entry(dir.clone()).or_default() += 1;
+            *self.files.lock().unwrap().entry(path.clone()).or_default() += 1;
+        }
+
+        Some(WatchGuard {
+            watcher: Arc::clone(self),
+            path,
+            dir,
+        })
+    }
+
+    /// Get a receiver for updates of watched files
+    ///
+    /// The paths of the watched files are canonical, see [WatchGuard::path()].
+    pub fn subscribe(&self) -> broadcast::Receiver<FileUpdate> {
+        self.update_sender.subscribe()
+    }
+
+    /// Decrement the counts of the `path` and `dir`, unwatching the `dir` if it is no longer
+    /// needed
+    fn unwatch(&self, path: &Path, dir: &Path) {
+        {
+            let mut files = self.files.lock().unwrap();
+
+            if let Some(count) = files.get_mut(path) {
+                *count -= 1;
+
+                if *count == 0 {
+                    files.remove(path);
+                }
+            }
+        }
+
+        // Same locking order as in [Self::watch()]
+        let mut watcher = self.watcher.lock().unwrap();
+        let mut dirs = self.dirs.lock().unwrap();
+
+        if let Some(count) = dirs.get_mut(dir) {
+            *count -= 1;
+
+            if *count == 0 {
+                dirs.remove(dir);
+
+                if let Some(watcher) = watcher.as_mut() {
+                    let _ = watcher.unwatch(dir);
+                }
+
+                log::debug!("Stopped watching dir: {}", dir.display());
+            }
+        }
+    }
+}
+
+/// Keeps a file watched by a [FileWatcher] until it is dropped
+#[derive(Debug)]
+pub struct WatchGuard {
+    /// The watcher the file is watched by
+    watcher: Arc<FileWatcher>,
+    /// Canonical path of the file
+    path: PathBuf,
+    /// Directory of the file, which is actually watched
+    dir: PathBuf,
+}
+
+impl WatchGuard {
+    /// Canonical path of the watched file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.watcher.unwatch(&self.path, &self.dir);
+    }
+}
+
+/// Forward the paths received on `event_rx` as [FileUpdate]s, once no events were received for
+/// [DEBOUNCE]
+///
+/// Each path comes with where it was moved to, if it was renamed.
+async fn debounce(
+    mut event_rx: mpsc::UnboundedReceiver<(PathBuf, Option<PathBuf>)>,
+    update_sender: broadcast::Sender<FileUpdate>,
+) {
+    let mut pending: HashMap<PathBuf, Option<PathBuf>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            event = event_rx.recv() => {
+                match event {
+                    Some((path, moved_to)) => {
+                        let entry = pending.entry(path).or_default();
+
+                        if moved_to.is_some() {
+                            *entry = moved_to;
+                        }
+                    }
+                    // The watcher was dropped
+                    None => return,
+                }
+            }
+
+            _ = time::sleep(DEBOUNCE), if !pending.is_empty() => {
+                for (path, moved_to) in pending.drain() {
+                    let update = match moved_to {
+                        Some(to) if !path.exists() => FileUpdate::Moved { from: path, to },
+                        _ => FileUpdate::Changed(path),
+                    };
+
+                    // Fails if there are no receivers, which is fine
+                    let _ = update_sender.send(update);
+                }
+            }
+        }
+    }
+}
+
+/// Canonicalize the `path`, which may not exist yet
+///
+/// Only the directory has to exist, since the file may be created later.
+fn canonical(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Some(path);
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    Some(dir.canonicalize().ok()?.join(path.file_name()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileUpdate, FileWatcher};
+    use std::{fs, sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn shared_watches() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let path = dir.join("file.md");
+        fs::write(&path, "# Old").unwrap();
+
+        let watcher = Arc::new(FileWatcher::new());
+        let mut updates = watcher.subscribe();
+
+        let first = watcher.watch(&path).expect("The file can be watched.");
+        let second = watcher.watch(&path).expect("The file can be watched.");
+        assert_eq!(watcher.dirs.lock().unwrap().len(), 1);
+
+        // an atomic save, which replaces the file
+        fs::write(dir.join("file.md.tmp"), "# New").unwrap();
+        fs::rename(dir.join("file.md.tmp"), &path).unwrap();
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), updates.recv())
+            .await
+            .expect("The change should be reported.")
+            .unwrap();
+        assert_eq!(changed, FileUpdate::Changed(first.path().to_path_buf()));
+
+        // the directory is only unwatched once all guards are dropped
+        drop(first);
+        assert_eq!(watcher.dirs.lock().unwrap().len(), 1);
+        drop(second);
+        assert!(watcher.dirs.lock().unwrap().is_empty());
+        assert!(watcher.files.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn moves() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let path = dir.join("file.md");
+        fs::write(&path, "# Old").unwrap();
+
+        let watcher = Arc::new(FileWatcher::new());
+        let mut updates = watcher.subscribe();
+        let watch = watcher.watch(&path).expect("The file can be watched.");
+
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(5), updates.recv())
+                .await
+                .expect("The change should be reported.")
+                .unwrap()
+        };
+
+        // a backup made by an editor, which then writes the file again
+        fs::rename(&path, dir.join("file.md~")).unwrap();
+        fs::write(&path, "# New").unwrap();
+        assert_eq!(
+            next().await,
+            FileUpdate::Changed(watch.path().to_path_buf())
+        );
+
+        fs::rename(&path, dir.join("moved.md")).unwrap();
+        assert_eq!(
+            next().await,
+            FileUpdate::Moved {
+                from: watch.path().to_path_buf(),
+                to: dir.canonicalize().unwrap().join("moved.md"),
+            }
+        );
+
+        drop(watch);
+    }
+
+    #[test]
+    fn polling() {
+        let watcher = Arc::new(FileWatcher::polling());
+
+        assert!(watcher.watch(&std::env::temp_dir()).is_none());
+    }
+}
@@ -0,0 +1,526 @@
+//! Plain http requests for browsers, served next to the websockets. See: [serve()]
+//!
+//! Browsers can't use the `asset://` scheme of the GTK viewer, nor load its page from memory, so
+//! the server serves both over http. Everything else still goes through the websocket, see
+//! [crate::ws].
+//!
+//! Only the bare minimum of http is spoken: one request per connection, which is closed after the
+//! response.
+
+use http::{
+    HeaderValue, Method, Request, Response, StatusCode,
+    header::{ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, HOST, RANGE, UPGRADE},
+    uri::Authority,
+};
+use percent_encoding::percent_decode_str;
+use std::{
+    io::{self, SeekFrom},
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+};
+
+use crate::{endpoint::Connection, server::Shared, token, ws::upgrade_connection};
+
+/// Requests with a longer head are rejected
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Requests with more headers are rejected
+const MAX_HEADERS: usize = 64;
+
+/// Path local assets are served under, followed by their absolute, percent-encoded path
+///
+/// main.js rewrites the `asset://` uris of [crate::convert::transform::MediaAssets] to this.
+const ASSET_PREFIX: &str = "/asset";
+
+/// Serve the request on the `stream`
+///
+/// Websocket requests are passed on to [upgrade_connection()]. Otherwise the page of the viewer
+/// is served on `/` and local images, audio and video under [ASSET_PREFIX]. Like websockets,
+/// assets require the [Shared::token] as the `token` query parameter.
+///
+/// Requests not addressed to `localhost` are rejected, so websites can't read local files by
+/// pointing their domain at `127.0.0.1`.
+pub async fn serve(
+    mut stream: Box<dyn Connection>,
+    shared: Shared,
+    connection: usize,
+) -> io::Result<()> {
+    let mut read = Vec::new();
+    let request = read_request(&mut stream, &mut read).await?;
+
+    if !request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok()?.parse::<Authority>().ok())
+        .is_some_and(|host| matches!(host.host(), "localhost" | "127.0.0.1" | "[::1]"))
+    {
+        log::warn!("Rejecting request not addressed to localhost");
+
+        return respond(&mut stream, &request, status(StatusCode::FORBIDDEN)).await;
+    }
+
+    if request
+        .headers()
+        .get(UPGRADE)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"))
+    {
+        // The handshake reads the request again
+        let stream = Rewound {
+            read,
+            position: 0,
+            stream,
+        };
+
+        return upgrade_connection(Box::new(stream), shared, connection).await;
+    }
+
+    let response = match *request.method() {
+        Method::GET | Method::HEAD => match request.uri().path() {
+            "/" => page(),
+            path => match path.strip_prefix(ASSET_PREFIX) {
+                Some(_) if !token::is_authorized(&request, shared.token.as_ref()) => {
+                    status(StatusCode::UNAUTHORIZED)
+                }
+                Some(asset_path) => asset(asset_path, request.headers().get(RANGE)).await,
+                None => status(StatusCode::NOT_FOUND),
+            },
+        },
+        _ => status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+
+    respond(&mut stream, &request, response).await
+}
+
+/// Read the head of a request from the `stream`
+///
+/// Everything read from the `stream` is appended to `read`, which may be more than the head.
+async fn read_request(
+    stream: &mut Box<dyn Connection>,
+    read: &mut Vec<u8>,
+) -> io::Result<Request<()>> {
+    let mut chunk = [0; 1024];
+
+    loop {
+        let n = stream.read(&mut chunk).await?;
+
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before the request was complete",
+            ));
+        }
+
+        read.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut head = httparse::Request::new(&mut headers);
+
+        match head.parse(read).map_err(invalid_data)? {
+            httparse::Status::Complete(_) => {
+                let mut request = Request::builder()
+                    .method(head.method.unwrap_or_default())
+                    .uri(head.path.unwrap_or_default());
+
+                for header in head.headers.iter() {
+                    request = request.header(header.name, header.value);
+                }
+
+                return request.body(()).map_err(invalid_data);
+            }
+            httparse::Status::Partial if read.len() > MAX_HEAD_SIZE => {
+                return Err(invalid_data("Request head too large"));
+            }
+            httparse::Status::Partial => {}
+        }
+    }
+}
+
+/// Body of a response
+#[derive(Debug)]
+enum Body {
+    /// Bytes in memory
+    Bytes(Vec<u8>),
+    /// The given number of bytes of a file, starting at its current position
+    ///
+    /// The file is streamed, so large videos aren't read into memory at once.
+    File(File, u64),
+}
+
+impl Body {
+    /// Get the number of bytes of the body
+    fn len(&self) -> u64 {
+        match self {
+            Self::Bytes(bytes) => bytes.len() as u64,
+            Self::File(_, len) => *len,
+        }
+    }
+}
+
+/// Part of a file requested using the `Range` header, see [byte_range()]
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// The whole file
+    Full,
+    /// The bytes from the first to the last, inclusive
+    Part(u64, u64),
+    /// Bytes past the end of the file
+    Unsatisfiable,
+}
+
+/// Write the `response` to the `request` and close the `stream`
+async fn respond(
+    stream: &mut Box<dyn Connection>,
+    request: &Request<()>,
+    response: Response<Body>,
+) -> io::Result<()> {
+    let status = response.status();
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    );
+
+    for (name, value) in response.headers() {
+        head.push_str(&format!(
+            "{name}: {}\r\n",
+            value.to_str().unwrap_or_default()
+        ));
+    }
+
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body().len()
+    ));
+
+    stream.write_all(head.as_bytes()).await?;
+
+    if request.method() != Method::HEAD {
+        match response.into_body() {
+            Body::Bytes(bytes) => stream.write_all(&bytes).await?,
+            Body::File(file, len) => {
+                tokio::io::copy(&mut file.take(len), stream).await?;
+            }
+        }
+    }
+
+    stream.shutdown().await
+}
+
+/// Get the page of the viewer, see [igneous_md_viewer::HTML]
+#[cfg(feature = "viewer")]
+fn page() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::Bytes(igneous_md_viewer::HTML.as_bytes().to_vec()))
+        .expect("Response is hard-coded.")
+}
+
+/// The page is part of the viewer, so it can't be served without it
+#[cfg(not(feature = "viewer"))]
+fn page() -> Response<Body> {
+    status(StatusCode::NOT_FOUND)
+}
+
+/// Get the local asset at the percent-encoded, absolute `path`
+///
+/// Only images, audio and video are served, like the media [crate::convert::transform::MediaAssets]
+/// points at. If the `range` header requests part of the file, only that part is served, so
+/// browsers can seek in videos without loading them first.
+async fn asset(path: &str, range: Option<&HeaderValue>) -> Response<Body> {
+    let Ok(path) = percent_decode_str(path).decode_utf8() else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+    let path = PathBuf::from(path.as_ref());
+
+    let Some(mime) = mime_guess::from_path(&path)
+        .first()
+        .filter(|m| matches!(m.type_().as_str(), "image" | "audio" | "video"))
+    else {
+        return status(StatusCode::FORBIDDEN);
+    };
+
+    if !path.is_absolute() {
+        return status(StatusCode::BAD_REQUEST);
+    }
+
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return status(StatusCode::NOT_FOUND),
+        Err(e) => {
+            log::warn!("Failed to open asset {}: {e}", path.display());
+
+            return status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    match file_response(file, mime.as_ref(), range).await {
+        Ok(response) => response,
+        Err(e) => {
+            log::warn!("Failed to read asset {}: {e}", path.display());
+
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get the response serving the `file` of the `mime` type, or the part of it requested by the
+/// `range` header
+async fn file_response(
+    mut file: File,
+    mime: &str,
+    range: Option<&HeaderValue>,
+) -> io::Result<Response<Body>> {
+    let metadata = file.metadata().await?;
+
+    // Directories can be opened as well
+    if !metadata.is_file() {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+
+    let len = metadata.len();
+
+    let response = Response::builder()
+        .header(CONTENT_TYPE, mime)
+        .header(ACCEPT_RANGES, "bytes")
+        // Assets change like the documents they are part of
+        .header(CACHE_CONTROL, "no-cache");
+
+    let response = match byte_range(range, len) {
+        ByteRange::Full => response.body(Body::File(file, len)),
+        ByteRange::Part(first, last) => {
+            file.seek(SeekFrom::Start(first)).await?;
+
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {first}-{last}/{len}"))
+                .body(Body::File(file, last - first + 1))
+        }
+        ByteRange::Unsatisfiable => {
+            let mut response = status(StatusCode::RANGE_NOT_SATISFIABLE);
+            response.headers_mut().insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{len}"))
+                    .expect("Content ranges are valid header values."),
+            );
+
+            return Ok(response);
+        }
+    };
+
+    Ok(response.expect("Mime types are valid header values."))
+}
+
+/// Get the part of a file of `len` bytes requested by the `range` header
+///
+/// Only single ranges are supported. Other and invalid ranges are ignored, which serves the whole
+/// file.
+fn byte_range(range: Option<&HeaderValue>, len: u64) -> ByteRange {
+    let Some((first, last)) = range
+        .and_then(|r| r.to_str().ok()?.strip_prefix("bytes="))
+        .filter(|r| !r.contains(','))
+        .and_then(|r| r.split_once('-'))
+    else {
+        return ByteRange::Full;
+    };
+
+    let (first, last) = match (first.trim(), last.trim()) {
+        // The last bytes of the file
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), u64::MAX),
+            Err(_) => return ByteRange::Full,
+        },
+        (first, "") => match first.parse() {
+            Ok(first) => (first, u64::MAX),
+            Err(_) => return ByteRange::Full,
+        },
+        (first, last) => match (first.parse(), last.parse()) {
+            (Ok(first), Ok(last)) if first <= last => (first, last),
+            _ => return ByteRange::Full,
+        },
+    };
+
+    if first >= len {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Part(first, last.min(len - 1))
+}
+
+/// Get an empty response with the `status`
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::Bytes(Vec::new()));
+    *response.status_mut() = status;
+
+    response
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A stream which returns the bytes already read from it again, before reading any further
+struct Rewound<S> {
+    /// The bytes already read
+    read: Vec<u8>,
+    /// How many of [Self::read] have been returned again
+    position: usize,
+    stream: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewound<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.position < this.read.len() {
+            let n = buf.remaining().min(this.read.len() - this.position);
+
+            buf.put_slice(&this.read[this.position..this.position + n]);
+            this.position += n;
+
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut this.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewound<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Body, ByteRange, MAX_HEAD_SIZE, asset, byte_range, read_request};
+    use crate::endpoint::Connection;
+    use http::{HeaderValue, Response, StatusCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Read the body of the `response`
+    async fn body(response: Response<Body>) -> Vec<u8> {
+        match response.into_body() {
+            Body::Bytes(bytes) => bytes,
+            Body::File(file, len) => {
+                let mut bytes = Vec::new();
+                file.take(len).read_to_end(&mut bytes).await.unwrap();
+
+                bytes
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let mut server: Box<dyn Connection> = Box::new(server);
+
+        // the head may arrive in several parts
+        let head = "GET /asset/a%20b.png HTTP/1.1\r\nHost: localhost:4242\r\n\r\n";
+        let (first, second) = head.split_at(20);
+
+        let write = tokio::spawn(async move {
+            client.write_all(first.as_bytes()).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            client.write_all(second.as_bytes()).await.unwrap();
+
+            client
+        });
+
+        let mut read = Vec::new();
+        let request = read_request(&mut server, &mut read).await.unwrap();
+
+        assert_eq!(request.uri().path(), "/asset/a%20b.png");
+        assert_eq!(request.headers()["host"], "localhost:4242");
+        assert_eq!(read, head.as_bytes());
+
+        // heads which never end are rejected
+        let mut client = write.await.unwrap();
+        tokio::spawn(async move {
+            let header = format!("X: {}\r\n", "a".repeat(MAX_HEAD_SIZE));
+            let _ = client.write_all(b"GET / HTTP/1.1\r\n").await;
+            let _ = client.write_all(header.as_bytes()).await;
+        });
+
+        let e = read_request(&mut server, &mut Vec::new())
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn assets() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("a b.png"), "png").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+        let encoded = dir.to_string_lossy().replace(' ', "%20");
+
+        let response = asset(&format!("{encoded}/a%20b.png"), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/png");
+        assert_eq!(body(response).await, b"png");
+
+        // parts can be requested, e.g. to seek in videos
+        let range = HeaderValue::from_static("bytes=1-");
+        let response = asset(&format!("{encoded}/a%20b.png"), Some(&range)).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 1-2/3");
+        assert_eq!(body(response).await, b"ng");
+
+        let range = HeaderValue::from_static("bytes=3-");
+        let response = asset(&format!("{encoded}/a%20b.png"), Some(&range)).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()["content-range"], "bytes */3");
+
+        // only media is served
+        let response = asset(&format!("{encoded}/secret.txt"), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = asset(&format!("{encoded}/missing.png"), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let range = |r: &'static str| byte_range(Some(&HeaderValue::from_static(r)), 10);
+
+        assert_eq!(byte_range(None, 10), ByteRange::Full);
+        assert_eq!(range("bytes=0-4"), ByteRange::Part(0, 4));
+        assert_eq!(range("bytes=5-"), ByteRange::Part(5, 9));
+        assert_eq!(range("bytes=-3"), ByteRange::Part(7, 9));
+        assert_eq!(range("bytes=-30"), ByteRange::Part(0, 9));
+        assert_eq!(range("bytes=8-100"), ByteRange::Part(8, 9));
+        assert_eq!(range("bytes=10-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+
+        // other and invalid ranges serve the whole file
+        assert_eq!(range("bytes=0-1,3-4"), ByteRange::Full);
+        assert_eq!(range("bytes=4-2"), ByteRange::Full);
+        assert_eq!(range("lines=0-1"), ByteRange::Full);
+    }
+}
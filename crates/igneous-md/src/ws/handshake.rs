@@ -5,12 +5,17 @@
 
 // TODO: This requires improved logging
 
-use http::{Request, Response};
+use http::{Request, Response, StatusCode};
 use thiserror::Error;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::oneshot;
 use tokio_tungstenite::{WebSocketStream, accept_hdr_async};
 
+use crate::{
+    config::ColorScheme,
+    token::{self, Token},
+};
+
 /// Query parameters received during the WebSocket handshake.
 #[derive(Debug)]
 pub struct WsQueryParams {
@@ -18,6 +23,14 @@ pub struct WsQueryParams {
     pub update_rate: Option<u64>,
     /// Path to the markdown file to serve.
     pub md_path: String,
+    /// Whether the client supports [super::msg::ServerMsg::HtmlPatch].
+    pub patch: bool,
+    /// Whether the client can export to pdf, see [super::msg::ServerMsg::Export].
+    pub export: bool,
+    /// Name of the stylesheet to start with, see [crate::config::Config::css_index_by_name()].
+    pub css: Option<String>,
+    /// The color scheme preferred by the client, see [super::msg::ClientMsg::SetColorScheme].
+    pub color_scheme: Option<ColorScheme>,
 }
 
 /// Errors that can occur during WebSocket handshake validation.
@@ -29,13 +42,24 @@ pub enum WsValidationError {
     /// The required `md_path` parameter was not found.
     #[error("Missing required parameter: md_path")]
     MissingMdPath,
+    /// The `token` parameter is missing or doesn't match the [Token] of the server.
+    #[error("Missing or invalid token")]
+    InvalidToken,
 }
 
 impl WsQueryParams {
     /// Parse query parameters from an HTTP request.
     ///
-    /// Expects `md_path` and optionally `update_rate` in the query string.
-    pub fn from_request(request: &Request<()>) -> Result<Self, WsValidationError> {
+    /// Expects `md_path` and optionally `update_rate`, `patch`, `export`, `css` and `color_scheme`
+    /// in the query string. If the server has a `token`, it is required as well.
+    pub fn from_request(
+        request: &Request<()>,
+        token: Option<&Token>,
+    ) -> Result<Self, WsValidationError> {
+        if !token::is_authorized(request, token) {
+            return Err(WsValidationError::InvalidToken);
+        }
+
         let query = request
             .uri()
             .query()
@@ -43,11 +67,19 @@ impl WsQueryParams {
 
         let mut update_rate = None;
         let mut md_path = None;
+        let mut patch = false;
+        let mut export = false;
+        let mut css = None;
+        let mut color_scheme = None;
 
         for (key, value) in form_urlencoded::parse(query.as_bytes()) {
             match key.as_ref() {
                 "update_rate" => update_rate = value.parse::<u64>().ok(),
                 "md_path" => md_path = Some(value.into_owned()),
+                "patch" => patch = value == "true",
+                "export" => export = value == "true",
+                "css" => css = Some(value.into_owned()).filter(|css| !css.is_empty()),
+                "color_scheme" => color_scheme = value.parse().ok(),
                 _ => {}
             }
         }
@@ -57,14 +89,19 @@ impl WsQueryParams {
         Ok(WsQueryParams {
             update_rate,
             md_path,
+            patch,
+            export,
+            css,
+            color_scheme,
         })
     }
 }
 
 /// Create a callback for [accept_hdr_async] that validates query parameters.
 ///
-/// The callback sends the parsed result through the oneshot channel and always
-/// returns `Ok(response)`. Errors are communicated only via the channel.
+/// The callback sends the parsed result through the oneshot channel. Requests without the
+/// `token` are rejected with `401 Unauthorized`, other errors are communicated only via the
+/// channel.
 #[allow(clippy::type_complexity)]
 #[allow(
     clippy::result_large_err,
@@ -72,27 +109,45 @@ impl WsQueryParams {
 )]
 pub fn ws_callback(
     sender: oneshot::Sender<Result<WsQueryParams, WsValidationError>>,
+    token: Option<&Token>,
 ) -> impl FnOnce(&Request<()>, Response<()>) -> Result<Response<()>, Response<Option<String>>> {
     move |request, response| {
-        let result = WsQueryParams::from_request(request);
+        let result = WsQueryParams::from_request(request, token);
+        let unauthorized = matches!(result, Err(WsValidationError::InvalidToken));
         let _ = sender.send(result);
+
+        if unauthorized {
+            log::warn!("Rejecting connection without a valid token");
+
+            let mut response = Response::new(Some(WsValidationError::InvalidToken.to_string()));
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+
+            return Err(response);
+        }
+
         Ok(response)
     }
 }
 
-/// Perform the WebSocket handshake on a TCP stream.
+/// Perform the WebSocket handshake on a stream, e.g. of a TCP or unix socket connection.
 ///
 /// Returns the [WebSocketStream] and the validated [WsQueryParams] extracted
-/// from the request query string.
-pub async fn perform_handshake(
-    tcp: TcpStream,
-) -> Result<(WebSocketStream<TcpStream>, WsQueryParams), WsValidationError> {
-    let (sender, receiver) = oneshot::channel();
-    let callback = ws_callback(sender);
-
-    let ws_stream = accept_hdr_async(tcp, callback)
-        .await
-        .map_err(|_| WsValidationError::MissingQuery)?;
+/// from the request query string. See [ws_callback()] for the `token`.
+pub async fn perform_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    token: Option<&Token>,
+) -> Result<(WebSocketStream<S>, WsQueryParams), WsValidationError> {
+    let (sender, mut receiver) = oneshot::channel();
+    let callback = ws_callback(sender, token);
+
+    let ws_stream = accept_hdr_async(stream, callback).await.map_err(|_| {
+        // The callback rejected the request, or it wasn't called at all
+        receiver
+            .try_recv()
+            .ok()
+            .and_then(Result::err)
+            .unwrap_or(WsValidationError::MissingQuery)
+    })?;
 
     let params = receiver.await.expect("Callback must send result")?;
 
@@ -11,63 +11,200 @@ pub mod msg;
 
 use futures_util::{SinkExt, StreamExt};
 use std::{
-    io,
+    future, io,
     path::PathBuf,
-    sync::{Arc, RwLock},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 use tokio::{
-    net::TcpStream,
-    sync::mpsc,
+    sync::{broadcast::error::RecvError, mpsc},
+    task::{self, JoinHandle},
     time::{self, Duration},
 };
 use tokio_tungstenite::tungstenite::Message as WsMessage;
 
 // TODO: This module requires improved logging (see also TODO below)
 
-use crate::{client::Client, config::Config};
+use crate::{
+    client::{Client, Render},
+    endpoint::Connection,
+    registry::Capabilities,
+    server::Shared,
+    watcher::FileUpdate,
+};
 use handshake::perform_handshake;
 use msg::{AsMsg, ClientMsg, ServerMsg};
 
+/// A line of a markdown file a client is at, see [ClientMsg::SourceLine]
+///
+/// These are broadcast to all connections, so that clients viewing the same file can follow each
+/// other.
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+    /// Id of the connection the line was sent by
+    pub connection: usize,
+    /// The canonical path of the file the line belongs to
+    pub path: PathBuf,
+    /// The line, starting at `1`
+    pub line: usize,
+}
+
 /// Handles upgrading the connection to the Websocket protocol and facilitating communication
 /// thereafter
+///
+/// `connection` is a unique id of this connection, used to not send its own [SourceLine]s back
+/// to it. The connection is registered in [Shared::clients] under it, until it is closed.
 pub async fn upgrade_connection(
-    tcp: TcpStream,
-    config: Arc<RwLock<Config>>,
-    mut server_msg_rx: mpsc::UnboundedReceiver<ServerMsg>,
+    stream: Box<dyn Connection>,
+    shared: Shared,
+    connection: usize,
 ) -> io::Result<()> {
-    let (ws_stream, params) = perform_handshake(tcp)
+    let (ws_stream, params) = perform_handshake(stream, shared.token.as_ref())
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
 
-    let mut client = Client::new(PathBuf::from(params.md_path), Arc::clone(&config));
+    let mut client = Client::new(PathBuf::from(params.md_path), &shared);
+    client.set_patch_support(params.patch);
+
+    // A stylesheet requested explicitly is used even if it is of the other color scheme
+    if let Some(color_scheme) = params.color_scheme {
+        client.set_color_scheme(color_scheme);
+    }
+
+    if let Some(css) = &params.css
+        && !client.set_css_by_name(css)
+    {
+        log::warn!("Stylesheet `{css}` requested by the client was not found.");
+    }
+
+    let (server_msg_tx, mut server_msg_rx) = mpsc::unbounded_channel();
+    let registration = shared.clients.register(
+        connection,
+        client.md_path().to_path_buf(),
+        Capabilities {
+            patch: params.patch,
+            export: params.export,
+        },
+        server_msg_tx,
+    );
+    registration.update(client.md_path(), client.current_css_name());
 
     let (mut ws_write, mut ws_read) = ws_stream.split();
 
+    let mut source_line_rx = shared.source_line_tx.subscribe();
+    let mut buffer_update_rx = shared.buffers.subscribe();
+    let mut file_update_rx = shared.file_watcher.subscribe();
+
     let mut interval = time::interval(Duration::from_millis(params.update_rate.unwrap_or(1000)));
 
+    // Watched files are only rendered once they change, so the first html is rendered right away
+    let mut pending = None;
+    PendingRender::start(&mut pending, &client, true);
+
+    // Messages from the server backend, like [ServerMsg::Export], are only forwarded once the
+    // first render is done, so they apply to the document
+    let mut rendered = false;
+
     // TODO: This should ideally be cleaned up (using a custom Stream type?). There are 4 different locations a message can be
     // sent from which can lead to inconsistencies in logging
     loop {
         tokio::select! {
-            _ = interval.tick() => {
-                if let Ok(Some(html)) = client.get_latest_html_if_changed() {
-                    let msg = ServerMsg::HtmlUpdate { html };
-                    log::info!("Sending ws message: {}", msg.name());
+            (force, result) = finished(&mut pending) => {
+                pending = None;
+                rendered = true;
 
-                    let _ = ws_write.send(msg.as_msg()).await;
+                match result {
+                    Ok(Some(render)) => {
+                        if let Some(html) = client.finish_render(render) {
+                            for msg in html_update_msgs(html, &mut client) {
+                                log::info!("Sending ws message: {}", msg.name());
+
+                                let _ = ws_write.send(msg.as_msg()).await;
+                            }
+
+                            // The front matter may have selected a stylesheet
+                            registration.update(client.md_path(), client.current_css_name());
+                        }
+                    }
+                    Ok(None) => {}
+                    // Reported once, the file is rendered again when it reappears
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        if client.set_gone() {
+                            let msg = ServerMsg::DocumentGone { path: client.md_path().to_path_buf() };
+                            log::info!("Sending ws message: {}", msg.name());
+
+                            let _ = ws_write.send(msg.as_msg()).await;
+                        }
+                    }
+                    // Other errors of renders which weren't requested by the client, e.g. while
+                    // editors save, aren't reported
+                    Err(e) if force => {
+                        let msg = ServerMsg::Error { msg: e.to_string() };
+                        log::info!("Sending ws message: {}", msg.name());
+
+                        let _ = ws_write.send(msg.as_msg()).await;
+                    }
+                    Err(e) => log::debug!("Failed to render {}: {e}", client.md_path().display()),
                 }
             }
 
+            // Only needed if the files can't be watched
+            _ = interval.tick(), if client.polling() && pending.is_none() => {
+                PendingRender::start(&mut pending, &client, false);
+            }
+
             _ = client.config_update_receiver.recv() => {
-                if let Some(css) = client.current_css() {
-                    let msg = ServerMsg::CssUpdate { css };
+                for msg in css_update_msgs(&client) {
                     log::info!("Sending ws message: {}", msg.name());
 
                     let _ = ws_write.send(msg.as_msg()).await;
                 }
+
+                registration.update(client.md_path(), client.current_css_name());
+            },
+
+            Ok(source_line) = source_line_rx.recv() => {
+                if source_line.connection != connection && client.is_viewing(&source_line.path) {
+                    client.set_line(source_line.line);
+
+                    let msg = ServerMsg::ScrollToLine { line: source_line.line };
+                    log::debug!("Sending ws message: {}", msg.name());
+
+                    let _ = ws_write.send(msg.as_msg()).await;
+                }
             },
 
-            Some(server_msg) = server_msg_rx.recv() => {
+            update @ (Ok(_) | Err(RecvError::Lagged(_))) = file_update_rx.recv() => {
+                match update {
+                    Ok(FileUpdate::Moved { from, to }) if client.follow_move(&from, &to) => {
+                        let msg = ServerMsg::DocumentMoved { from, to };
+                        log::info!("Sending ws message: {}", msg.name());
+
+                        let _ = ws_write.send(msg.as_msg()).await;
+
+                        registration.update(client.md_path(), client.current_css_name());
+                        PendingRender::start(&mut pending, &client, true);
+                    }
+                    Ok(FileUpdate::Changed(path) | FileUpdate::Moved { from: path, .. }) => {
+                        if client.is_watching(&path) {
+                            PendingRender::start(&mut pending, &client, false);
+                        }
+                    }
+                    // The missed updates may have been of the files of the client
+                    Err(_) => PendingRender::start(&mut pending, &client, false),
+                }
+            },
+
+            update @ (Ok(_) | Err(RecvError::Lagged(_))) = buffer_update_rx.recv() => {
+                // The missed updates may have been of the buffer of the file of the client
+                if update.is_err() || update.is_ok_and(|path| client.is_viewing(&path)) {
+                    PendingRender::start(&mut pending, &client, false);
+                }
+            },
+
+            Some(server_msg) = server_msg_rx.recv(), if rendered => {
                 log::info!("Forwarding msg from server backend: {}", server_msg.name());
 
                 let _ = ws_write.send(server_msg.as_msg()).await;
@@ -78,20 +215,49 @@ pub async fn upgrade_connection(
                     Some(Ok(message)) => {
                         match message {
                             WsMessage::Text(msg_string) => {
-                                if let Ok(client_msg) = serde_json::from_str::<ClientMsg>(&msg_string) {
-                                    log::info!("Received ws message: {}", client_msg.name());
-                                    log::debug!("Full received ws message: {:?}", client_msg);
+                                match serde_json::from_str::<ClientMsg>(&msg_string) {
+                                    Ok(ClientMsg::SourceLine { line }) => {
+                                        // Sent very frequently, so this isn't logged at the info level
+                                        log::debug!("Received source line: {line}");
+
+                                        client.set_line(line);
+
+                                        // Fails if there are no receivers, which is fine
+                                        let _ = shared.source_line_tx.send(SourceLine {
+                                            connection,
+                                            path: client.canonical_md_path().to_path_buf(),
+                                            line,
+                                        });
+                                    }
+                                    Ok(client_msg) => {
+                                        log::info!("Received ws message: {}", client_msg.name());
+                                        log::debug!("Full received ws message: {:?}", client_msg);
+
+                                        let mut redirect = client_msg.is_redirect() || client_msg.is_redirect_default();
+                                        let md_path = client.md_path().to_path_buf();
+
+                                        let return_msgs = handle_client_msg(client_msg, &mut client, connection);
+
+                                        // A failed redirect keeps the current file, which doesn't have to be sent again
+                                        redirect &= !return_msgs.iter().any(ServerMsg::is_error);
+
+                                        for return_msg in return_msgs {
+                                            if let Ok(()) = ws_write.send(return_msg.as_msg()).await {
+                                                log::info!("Sent ws response: {}", return_msg.name());
+                                                log::debug!("Full sent ws message: {:?}", return_msg);
+                                            } else {
+                                                log::error!("Failed to send server response.")
+                                            }
+                                        }
 
-                                    let return_msg = handle_client_msg(client_msg, &mut client);
+                                        registration.update(client.md_path(), client.current_css_name());
 
-                                    if let Ok(()) = ws_write.send(return_msg.as_msg()).await {
-                                        log::info!("Sent ws response: {}", return_msg.name());
-                                        log::debug!("Full sent ws message: {:?}", return_msg);
-                                    } else {
-                                        log::error!("Failed to send server response.")
+                                        // The html of the new file is sent once it is rendered
+                                        if redirect || client.md_path() != md_path {
+                                            PendingRender::start(&mut pending, &client, true);
+                                        }
                                     }
-                                } else {
-                                    log::warn!("Invalid client message: {}", msg_string)
+                                    Err(_) => log::warn!("Invalid client message: {}", msg_string),
                                 }
                             },
                             WsMessage::Close(_) => {
@@ -113,42 +279,215 @@ pub async fn upgrade_connection(
         }
     }
 
+    // Viewers of files pushed by this connection show them as they are on disk again
+    shared.buffers.release(connection);
+
     Ok(())
 }
 
+/// A [crate::client::RenderJob] of a connection running on the blocking thread pool
+///
+/// Each connection only renders the latest version of its file, so dropping this cancels the job.
+/// If it is already rendering, the render is finished, but its result is dropped.
+struct PendingRender {
+    /// The running job
+    handle: JoinHandle<io::Result<Option<Render>>>,
+    /// See [crate::client::RenderJob::canceller()]
+    cancel: Arc<AtomicBool>,
+    /// Whether the job renders even unchanged files, see [Client::render_job()]
+    force: bool,
+}
+
+impl PendingRender {
+    /// Start rendering the file viewed by the `client`, replacing the `pending` render
+    ///
+    /// If the replaced render was forced, so is the new one, so the file is rendered even if it
+    /// is unchanged.
+    fn start(pending: &mut Option<Self>, client: &Client, force: bool) {
+        let force = force || pending.as_ref().is_some_and(|p| p.force);
+
+        let job = client.render_job(force);
+        let cancel = job.canceller();
+
+        *pending = Some(Self {
+            handle: task::spawn_blocking(move || job.run()),
+            cancel,
+            force,
+        });
+    }
+}
+
+impl Drop for PendingRender {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.handle.abort();
+    }
+}
+
+/// Wait for the `pending` render to finish, or forever if there is none
+///
+/// Returns whether the render was forced along with its result.
+async fn finished(pending: &mut Option<PendingRender>) -> (bool, io::Result<Option<Render>>) {
+    let Some(pending) = pending else {
+        return future::pending().await;
+    };
+
+    let result = (&mut pending.handle)
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+
+    (pending.force, result)
+}
+
+/// The messages to send when the css of the `client` has changed
+///
+/// Every [ServerMsg::CssUpdate] is followed by a [ServerMsg::CssEntries]. Nothing is sent if
+/// there is no css.
+fn css_update_msgs(client: &Client) -> Vec<ServerMsg> {
+    let (Some(css), Some(current)) = (client.current_css(), client.current_css_index()) else {
+        return Vec::new();
+    };
+
+    vec![
+        ServerMsg::CssUpdate { css },
+        ServerMsg::CssEntries {
+            names: client.config.read().unwrap().css_names(),
+            current: current.into(),
+        },
+    ]
+}
+
+/// The messages to send when the html of the `client` has changed
+///
+/// Every [ServerMsg::HtmlUpdate] or [ServerMsg::HtmlPatch] is followed by a [ServerMsg::Outline]
+/// and a [ServerMsg::FrontMatter]. If the front matter requests a different stylesheet, a
+/// [ServerMsg::CssUpdate] is sent as well. If the client went back or forward to the file, a
+/// [ServerMsg::ScrollToLine] restores where it was.
+fn html_update_msgs(html: String, client: &mut Client) -> Vec<ServerMsg> {
+    let update = match client.html_patch() {
+        Some(patches) => ServerMsg::HtmlPatch { patches },
+        None => ServerMsg::HtmlUpdate { html },
+    };
+
+    let mut msgs = vec![
+        update,
+        ServerMsg::Outline {
+            headings: client.outline(),
+        },
+        ServerMsg::FrontMatter {
+            metadata: client.front_matter().clone(),
+        },
+    ];
+
+    if client.apply_front_matter_css() {
+        msgs.extend(css_update_msgs(client));
+    }
+
+    if let Some(line) = client.take_restored_line() {
+        msgs.push(ServerMsg::ScrollToLine { line });
+    }
+
+    msgs
+}
+
 /// [upgrade_connection()] uses this to handle the incoming messages from the client
-fn handle_client_msg(msg: ClientMsg, client: &mut Client) -> ServerMsg {
+///
+/// Most messages only warrant a single response, but some, like
+/// [ClientMsg::Redirect], are answered with several.
+///
+/// `connection` is the id of the connection the message was received on.
+fn handle_client_msg(msg: ClientMsg, client: &mut Client, connection: usize) -> Vec<ServerMsg> {
     match msg {
         ClientMsg::ChangeCss { index, relative } => {
             client.change_current_css_index(index, relative);
 
-            if let Some(css) = client.current_css() {
-                return ServerMsg::CssUpdate { css };
+            // Clients request the current stylesheet this way, which isn't a choice of the user
+            if index != 0 || !relative {
+                client.remember_css();
             }
 
-            ServerMsg::Error {
-                msg: "Failed to change css.".to_string(),
+            let msgs = css_update_msgs(client);
+
+            if msgs.is_empty() {
+                return vec![ServerMsg::Error {
+                    msg: "Failed to change css.".to_string(),
+                }];
             }
+
+            msgs
         }
-        ClientMsg::RequestExport => ServerMsg::Export {
-            path: client.config.read().unwrap().export_path(),
-        },
-        ClientMsg::Redirect { path } => {
-            client.set_md_path(path);
+        ClientMsg::SetCss { name } => {
+            if !client.set_css_by_name(&name) {
+                return vec![ServerMsg::Error {
+                    msg: format!("Stylesheet `{name}` was not found."),
+                }];
+            }
+
+            client.remember_css();
 
-            match client.get_latest_html() {
-                Ok(html) => ServerMsg::HtmlUpdate { html },
-                Err(e) => ServerMsg::Error { msg: e.to_string() },
+            css_update_msgs(client)
+        }
+        ClientMsg::SetColorScheme { scheme } => {
+            if client.set_color_scheme(scheme) {
+                return css_update_msgs(client);
             }
+
+            Vec::new()
+        }
+        ClientMsg::RequestExport => vec![ServerMsg::Export {
+            path: client
+                .config
+                .read()
+                .unwrap()
+                .export_path(client.front_matter().title()),
+        }],
+        // The new file is rendered by [upgrade_connection()]
+        ClientMsg::Redirect { path } => {
+            if !client.follow_link(&path.to_string_lossy()) {
+                return vec![ServerMsg::Error {
+                    msg: format!("The file `{}` doesn't exist.", path.display()),
+                }];
+            }
+
+            Vec::new()
         }
         ClientMsg::RedirectDefault => {
             client.reset_md_path_to_initial();
 
-            match client.get_latest_html() {
-                Ok(html) => ServerMsg::HtmlUpdate { html },
-                Err(e) => ServerMsg::Error { msg: e.to_string() },
+            Vec::new()
+        }
+        // The file gone back or forward to is rendered by [upgrade_connection()]
+        ClientMsg::Back => {
+            if !client.back() {
+                log::debug!("No previous file to go back to");
+            }
+
+            Vec::new()
+        }
+        ClientMsg::Forward => {
+            if !client.forward() {
+                log::debug!("No next file to go forward to");
+            }
+
+            Vec::new()
+        }
+        ClientMsg::CheckServer => vec![ServerMsg::Success],
+        ClientMsg::GetServerOptions => vec![ServerMsg::ServerOptions(
+            client.config.read().unwrap().server_options(),
+        )],
+        // Handled by [upgrade_connection()], since it is sent to other clients
+        ClientMsg::SourceLine { .. } => Vec::new(),
+        // The html is sent to all clients viewing the file by [upgrade_connection()]
+        ClientMsg::PushMarkdown { path, content } => {
+            client.buffers.push(connection, &path, content);
+
+            Vec::new()
+        }
+        ClientMsg::PatchMarkdown { path, edits } => {
+            match client.buffers.patch(connection, &path, &edits) {
+                Ok(()) => Vec::new(),
+                Err(e) => vec![ServerMsg::Error { msg: e.to_string() }],
             }
         }
-        ClientMsg::CheckServer => ServerMsg::Success,
     }
 }
@@ -7,6 +7,12 @@ use serde::{Deserialize, Serialize};
 use strum::{EnumIs, IntoStaticStr};
 use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 
+use crate::{
+    buffers::LineEdit,
+    config::{ColorScheme, ServerOptions},
+    convert::{Heading, blocks::BlockPatch, front_matter::FrontMatter},
+};
+
 pub trait AsMsg {
     /// Convert [Self] into a [WsMessage]
     fn as_msg(&self) -> WsMessage;
@@ -24,11 +30,71 @@ pub enum ServerMsg {
         css: String,
         //NOTE: We could add a reason here in the future if there is a use
     },
+    /// The stylesheets available, sent alongside every [ServerMsg::CssUpdate]
+    ///
+    /// Allows clients to show a picker, which selects stylesheets using [ClientMsg::SetCss].
+    CssEntries {
+        /// File names of the stylesheets, in the order [ClientMsg::ChangeCss] cycles through them
+        names: Vec<String>,
+        /// Index of the current stylesheet in [Self::CssEntries::names]
+        current: usize,
+    },
     /// Updated HTML rendered from markdown
     HtmlUpdate {
         /// Html content
         html: String,
     },
+    /// Changes to the blocks of the html, sent instead of [ServerMsg::HtmlUpdate] to clients
+    /// which support it
+    ///
+    /// Clients advertise support using the `patch=true` query parameter. The first html is always
+    /// sent as a [ServerMsg::HtmlUpdate]. See: [crate::convert::blocks]
+    HtmlPatch {
+        /// The patches, to be applied in order
+        patches: Vec<BlockPatch>,
+    },
+    /// Outline of the document, sent alongside every [ServerMsg::HtmlUpdate]
+    ///
+    /// Allows clients to build a table of contents without having to parse the html.
+    Outline {
+        /// The top-level headings of the document
+        headings: Vec<Heading>,
+    },
+    /// Metadata from the front matter of the document, sent alongside every
+    /// [ServerMsg::HtmlUpdate]
+    ///
+    /// If the document has no front matter, the metadata is empty.
+    FrontMatter {
+        /// The key/value pairs of the front matter
+        metadata: FrontMatter,
+    },
+    /// Another client viewing the same file is at the given line of the markdown
+    ///
+    /// Viewers should scroll to the block with the closest `data-source-line` at or before the
+    /// line, or to the top if there is none. See: [ClientMsg::SourceLine]
+    ///
+    /// Also sent after the html of the file a client went [ClientMsg::Back] or
+    /// [ClientMsg::Forward] to, so it returns to where it was in the file.
+    ScrollToLine {
+        /// The line, starting at `1`
+        line: usize,
+    },
+    /// The viewed file was deleted, or moved somewhere it can't be followed to
+    ///
+    /// The file stays watched, so its html is sent again once it reappears.
+    DocumentGone {
+        /// The path of the file
+        path: PathBuf,
+    },
+    /// The viewed file was renamed
+    ///
+    /// The server follows the file to its new path and sends its html again.
+    DocumentMoved {
+        /// The old path of the file
+        from: PathBuf,
+        /// The new path of the file
+        to: PathBuf,
+    },
     /// Request the client export the current html to the specified path
     ///
     /// The exported file is expected to be PDF.
@@ -46,6 +112,8 @@ pub enum ServerMsg {
     },
     /// Arbitrary success message
     Success,
+    /// Answer to [ClientMsg::GetServerOptions], with the options the server was launched with
+    ServerOptions(ServerOptions),
     /// Arbitrary error message
     Error {
         /// Message describing in human-readable format the issue
@@ -85,19 +153,77 @@ pub enum ClientMsg {
         /// If the change is relative to the current css index
         relative: bool,
     },
+    /// Request the stylesheet with the given name
+    ///
+    /// Unlike [ClientMsg::ChangeCss] with an index of `0`, this is remembered as the stylesheet
+    /// selected by the user, which the next launch starts with.
+    SetCss {
+        /// File name or file stem of the stylesheet, e.g. `github-markdown-dark`
+        name: String,
+    },
+    /// The color scheme preferred by the client changed, e.g. because the desktop switched to
+    /// dark mode
+    ///
+    /// The server switches to a stylesheet of that color scheme, if the current one is of the
+    /// other. See: [crate::config::Config::css_index_for_color_scheme()]
+    SetColorScheme {
+        /// The preferred color scheme, `light` or `dark`
+        scheme: ColorScheme,
+    },
     /// Client requests the server send [ServerMsg::Export]
     ///
     /// This is required so that the server may send the path to export to.
     RequestExport,
     /// Request for the server to change the md file being viewed
+    ///
+    /// If the file doesn't exist, the current file is kept and a [ServerMsg::Error] is sent.
     Redirect {
         /// Where the redirect is headed
+        ///
+        /// This is the `href` of the link, relative paths are resolved against the directory of
+        /// the current file.
         path: PathBuf,
     },
     /// Request for the server to change the md file being viewed back to the default
     RedirectDefault,
+    /// Go back to the file viewed before the last [ClientMsg::Redirect] or
+    /// [ClientMsg::RedirectDefault], like the back button of a browser
+    ///
+    /// The html of the file is followed by a [ServerMsg::ScrollToLine] to the last
+    /// [ClientMsg::SourceLine] of the client in that file.
+    Back,
+    /// Undo a [ClientMsg::Back], see there
+    Forward,
     /// Check that the server is running and responding to requests
     CheckServer,
+    /// Request the options the server was launched with, see [ServerMsg::ServerOptions]
+    GetServerOptions,
+    /// The client is at the given line of the markdown
+    ///
+    /// Editors send this when the cursor moves and viewers when they are scrolled, reporting the
+    /// topmost visible line. All other clients viewing the same file get a
+    /// [ServerMsg::ScrollToLine].
+    SourceLine {
+        /// The line, starting at `1`
+        line: usize,
+    },
+    /// Unsaved markdown of the file at `path`, sent by editors
+    ///
+    /// All clients viewing the file show this markdown instead of the file on disk, until the
+    /// editor disconnects.
+    PushMarkdown {
+        /// Absolute path of the file
+        path: PathBuf,
+        /// The full markdown
+        content: String,
+    },
+    /// Changes to markdown previously sent using [ClientMsg::PushMarkdown]
+    PatchMarkdown {
+        /// Absolute path of the file
+        path: PathBuf,
+        /// The changes, which are applied in order
+        edits: Vec<LineEdit>,
+    },
 }
 
 impl AsMsg for ClientMsg {